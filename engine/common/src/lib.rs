@@ -4,9 +4,11 @@ pub mod tests;
 use directions::Dir;
 
 pub use clap::Parser;
-use tests::{Scene, Test};
-#[derive(clap::Parser, Debug, Clone, Copy)]
+use tests::{NoiseConfig, Scene, Test};
+#[derive(clap::Parser, serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
 #[command(version, about, long_about = None)]
+// Results written before a flag existed read it as its default
+#[serde(default = "Args::default")]
 pub struct Args {
     /// Scene to use
     #[arg(short, long, default_value = "perlin")]
@@ -43,6 +45,61 @@ pub struct Args {
     /// Auto test
     #[arg(short, long, default_value = "false")]
     pub auto_test: bool,
+
+    /// Generate meshes on the GPU with a compute shader
+    #[arg(long, default_value = "false")]
+    pub gpu_mesh: bool,
+
+    /// Mesh chunks on worker threads instead of blocking the frame
+    #[arg(long, default_value = "false")]
+    pub async_mesh: bool,
+
+    /// Frustum cull combined draws in a compute shader
+    #[arg(long, default_value = "false")]
+    pub gpu_cull: bool,
+
+    /// Occlusion cull chunks against last frame's depth pyramid
+    #[arg(long, default_value = "false")]
+    pub hiz_cull: bool,
+
+    /// Skip face directions that point away from the camera
+    #[arg(long, default_value = "false")]
+    pub dir_cull: bool,
+
+    /// Only draw chunks reachable from the camera through open chunk faces
+    #[arg(long, default_value = "false")]
+    pub cave_cull: bool,
+
+    /// Draw each chunk's bounds into an occlusion query and only draw the chunk if they pass
+    #[arg(long, default_value = "false")]
+    pub query_cull: bool,
+
+    /// Draw chunks front to back from the game camera
+    #[arg(long, default_value = "false")]
+    pub sort_chunks: bool,
+
+    /// Draw depth before colour so only the nearest fragments are shaded
+    #[arg(long, default_value = "false")]
+    pub depth_prepass: bool,
+
+    /// Load unchanged chunk meshes from mesh_cache/ instead of meshing them at startup
+    #[arg(long, default_value = "false")]
+    pub mesh_cache: bool,
+
+    /// Upload faces in the two word encoding, with room for more block types, AO and light
+    #[arg(long, default_value = "false")]
+    pub wide_faces: bool,
+
+    /// Percentage of the volume filled by the noise scene
+    #[arg(long, default_value = "50")]
+    pub fill: u8,
+
+    /// Blocks toggled at random each frame, to measure how long remeshing takes to show
+    #[arg(long, default_value = "0")]
+    pub edits: u32,
+
+    #[command(flatten)]
+    pub noise: NoiseConfig,
 }
 
 impl Args {
@@ -57,31 +114,74 @@ impl Args {
             vertex_pull: false,
             profile: false,
             auto_test: false,
+            gpu_mesh: false,
+            async_mesh: false,
+            gpu_cull: false,
+            hiz_cull: false,
+            dir_cull: false,
+            cave_cull: false,
+            query_cull: false,
+            sort_chunks: false,
+            depth_prepass: false,
+            mesh_cache: false,
+            wide_faces: false,
+            fill: 50,
+            edits: 0,
+            noise: NoiseConfig::default(),
         }
     }
+
+    /// A letter for each option that is on, as shown after the test
+    pub fn flags(&self) -> String {
+        [
+            (self.frustum_cull, 'F'),
+            (self.vertex_pull, 'V'),
+            (self.combine, 'C'),
+            (self.gpu_mesh, 'G'),
+            (self.async_mesh, 'A'),
+            (self.gpu_cull, 'K'),
+            (self.hiz_cull, 'H'),
+            (self.dir_cull, 'D'),
+            (self.cave_cull, 'R'),
+            (self.query_cull, 'Q'),
+            (self.sort_chunks, 'S'),
+            (self.depth_prepass, 'Z'),
+            (self.mesh_cache, 'M'),
+            (self.wide_faces, 'W'),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
 }
 
 impl std::fmt::Display for Args {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let radius = if self.scene == Scene::Perlin {
+        let radius = if self.scene == Scene::Noise {
+            format!(" {} {}%", self.radius, self.fill)
+        } else if self.scene == Scene::Perlin && !self.noise.is_default() {
+            format!(" {} ({})", self.radius, self.noise)
+        } else if self.scene.is_sized() {
             format!(" {}", self.radius)
         } else {
             String::new()
         };
-        let mut flags = String::new();
-        if self.frustum_cull || self.combine || self.vertex_pull {
-            flags.push(' ');
-        }
-        if self.frustum_cull {
-            flags.push('F');
-        }
-        if self.vertex_pull {
-            flags.push('V');
-        }
-        if self.combine {
-            flags.push('C');
-        }
-        write!(f, "{:?}{}, {:?}{}", self.scene, radius, self.test, flags)
+        let flags = self.flags();
+        let flags = if flags.is_empty() {
+            flags
+        } else {
+            format!(" {}", flags)
+        };
+        let edits = if self.edits > 0 {
+            format!(", {} edits/frame", self.edits)
+        } else {
+            String::new()
+        };
+        write!(
+            f,
+            "{:?}{}, {:?}{}{}",
+            self.scene, radius, self.test, flags, edits
+        )
     }
 }
 
@@ -108,6 +208,21 @@ pub fn seperate_global_pos(pos: &IVec3) -> (IVec3, IVec3) {
     (chunk_pos, in_chunk_pos)
 }
 
+/// The global position of `in_chunk_pos` in the chunk at `chunk_pos`, the inverse of
+/// [`seperate_global_pos`]. Negative chunks are shifted along by one in the same way.
+pub fn join_global_pos(chunk_pos: &IVec3, in_chunk_pos: &IVec3) -> IVec3 {
+    const CHUNK_SIZE: i32 = 30;
+    let mut pos = chunk_pos * CHUNK_SIZE + in_chunk_pos;
+
+    for axis in 0..3 {
+        if chunk_pos[axis] < 0 {
+            pos[axis] += 1;
+        }
+    }
+
+    pos
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct InstanceData(u32);
 
@@ -148,6 +263,9 @@ pub enum BlockType {
 }
 
 impl BlockType {
+    /// Number of block types, for arrays indexed by `block_type as usize`
+    pub const COUNT: usize = 5;
+
     pub fn is_solid(&self) -> bool {
         ![BlockType::Air, BlockType::Invalid].contains(self)
     }
@@ -181,6 +299,9 @@ impl TryFrom<u32> for BlockType {
 }
 
 impl InstanceData {
+    /// Bump whenever the bit layout changes, anything stored with another version is invalid
+    pub const ENCODING_VERSION: u32 = 1;
+
     pub fn new(
         x: u8,
         y: u8,
@@ -253,7 +374,11 @@ impl InstanceData {
     }
 
     pub fn block_type(&self) -> BlockType {
-        (self.0 >> 28).try_into().unwrap()
+        match self.0 >> 28 {
+            // BlockType::Invalid cut down to 4 bits
+            0b1111 => BlockType::Invalid,
+            block_type => block_type.try_into().unwrap(),
+        }
     }
 
     pub fn rotate_on_dir(&self) -> Self {
@@ -285,6 +410,22 @@ impl From<InstanceData> for u32 {
     }
 }
 
+impl TryFrom<WideInstanceData> for InstanceData {
+    type Error = WideInstanceData;
+
+    /// Drop the wide only fields, failing if the block type doesn't fit in 4 bits
+    fn try_from(wide: WideInstanceData) -> Result<Self, Self::Error> {
+        let block_type = wide.raw_block_type();
+        if block_type > 0b1111 && block_type != WideInstanceData::INVALID_BLOCK {
+            return Err(wide);
+        }
+
+        let [low, _] = wide.words();
+
+        Ok(Self((low & 0x0FFF_FFFF) | ((block_type & 0b1111) << 28)))
+    }
+}
+
 impl std::fmt::Display for InstanceData {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -300,3 +441,330 @@ impl std::fmt::Display for InstanceData {
         )
     }
 }
+
+/// A face packed into two words.
+/// The low word matches [`InstanceData`], except its top 4 bits hold a texture variant.
+/// The high word holds a 16 bit block type, 2 bits of AO for each corner, 4 bits of light
+/// and the encoding version in its top 4 bits, which is never 0 so shaders can tell the
+/// encodings apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WideInstanceData([u32; 2]);
+
+impl WideInstanceData {
+    /// Bump whenever the bit layout changes, anything stored with another version is invalid
+    pub const ENCODING_VERSION: u32 = 1;
+    /// [`BlockType::Invalid`] once cut down to 16 bits
+    const INVALID_BLOCK: u32 = 0xFFFF;
+
+    pub fn new(
+        x: u8,
+        y: u8,
+        z: u8,
+        direction: Dir,
+        width: u8,
+        height: u8,
+        block_type: BlockType,
+    ) -> Self {
+        const CHUNK_SIZE: u8 = 30;
+        debug_assert!(
+            x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE,
+            "Invalid position: ({}, {}, {})",
+            x,
+            y,
+            z
+        );
+        debug_assert!(
+            width < CHUNK_SIZE && height < CHUNK_SIZE,
+            "Invalid width or height: ({}, {})",
+            width,
+            height
+        );
+
+        let x_mask = (x as u32 & 0b11111) << 10;
+        let y_mask = (y as u32 & 0b11111) << 5;
+        let z_mask = z as u32 & 0b11111;
+
+        let d_mask = (usize::from(direction) as u32 & 0b111) << 15;
+
+        let w_mask = (width as u32 & 0b11111) << 18;
+        let h_mask = (height as u32 & 0b11111) << 23;
+
+        let block_type = u32::from(block_type) & 0xFFFF;
+        let version_mask = Self::ENCODING_VERSION << 28;
+
+        Self([
+            x_mask | y_mask | z_mask | d_mask | w_mask | h_mask,
+            block_type | version_mask,
+        ])
+    }
+
+    /// Set the AO of each corner, 0 is unoccluded and 3 is fully occluded
+    pub fn with_ao(mut self, ao: [u8; 4]) -> Self {
+        let ao = ao
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, a)| mask | ((*a as u32 & 0b11) << (i * 2)));
+
+        self.0[1] = (self.0[1] & !(0xFF << 16)) | (ao << 16);
+        self
+    }
+
+    /// Set the light level, 0 to 15
+    pub fn with_light(mut self, light: u8) -> Self {
+        self.0[1] = (self.0[1] & !(0b1111 << 24)) | ((light as u32 & 0b1111) << 24);
+        self
+    }
+
+    /// Set the texture variant, 0 to 15
+    pub fn with_variant(mut self, variant: u8) -> Self {
+        self.0[0] = (self.0[0] & 0x0FFF_FFFF) | ((variant as u32 & 0b1111) << 28);
+        self
+    }
+
+    pub fn words(&self) -> [u32; 2] {
+        self.0
+    }
+
+    /// The narrow encoding, with block types too large for it drawn as [`BlockType::Invalid`]
+    pub fn narrow(&self) -> InstanceData {
+        InstanceData::try_from(*self)
+            .unwrap_or(InstanceData((self.0[0] & 0x0FFF_FFFF) | (0b1111 << 28)))
+    }
+
+    pub fn x(&self) -> u8 {
+        ((self.0[0] >> 10) & 0b11111) as u8
+    }
+
+    pub fn y(&self) -> u8 {
+        ((self.0[0] >> 5) & 0b11111) as u8
+    }
+
+    pub fn z(&self) -> u8 {
+        (self.0[0] & 0b11111) as u8
+    }
+
+    pub fn dir(&self) -> Dir {
+        Dir::from(((self.0[0] >> 15) & 0b111) as usize)
+    }
+
+    pub fn width(&self) -> u8 {
+        ((self.0[0] >> 18) & 0b11111) as u8
+    }
+
+    pub fn height(&self) -> u8 {
+        ((self.0[0] >> 23) & 0b11111) as u8
+    }
+
+    pub fn variant(&self) -> u8 {
+        (self.0[0] >> 28) as u8
+    }
+
+    fn raw_block_type(&self) -> u32 {
+        self.0[1] & 0xFFFF
+    }
+
+    pub fn block_type(&self) -> BlockType {
+        match self.raw_block_type() {
+            Self::INVALID_BLOCK => BlockType::Invalid,
+            block_type => block_type.try_into().unwrap(),
+        }
+    }
+
+    pub fn ao(&self) -> [u8; 4] {
+        std::array::from_fn(|i| ((self.0[1] >> (16 + i * 2)) & 0b11) as u8)
+    }
+
+    pub fn light(&self) -> u8 {
+        ((self.0[1] >> 24) & 0b1111) as u8
+    }
+
+    pub fn version(&self) -> u32 {
+        self.0[1] >> 28
+    }
+
+    pub fn rotate_on_dir(&self) -> Self {
+        let x = self.x();
+        let y = self.y();
+        let z = self.z();
+        let dir = self.dir();
+        let width = self.width();
+        let height = self.height();
+        let block_type = self.block_type();
+
+        let rotated = match dir {
+            Dir::Up | Dir::Down => Self::new(x, z, y, dir, width, height, block_type),
+            Dir::Forward | Dir::Backward => Self::new(x, y, z, dir, width, height, block_type),
+            Dir::Left | Dir::Right => Self::new(z, y, x, dir, width, height, block_type),
+        };
+
+        // Only the position moves, keep everything else as it was
+        Self([
+            (rotated.0[0] & 0x0FFF_FFFF) | (self.0[0] & 0xF000_0000),
+            self.0[1],
+        ])
+    }
+}
+
+impl From<[u32; 2]> for WideInstanceData {
+    fn from(words: [u32; 2]) -> Self {
+        Self(words)
+    }
+}
+
+impl From<WideInstanceData> for [u32; 2] {
+    fn from(data: WideInstanceData) -> Self {
+        data.0
+    }
+}
+
+impl From<InstanceData> for WideInstanceData {
+    fn from(data: InstanceData) -> Self {
+        let block_type = data.0 >> 28;
+        let block_type = if block_type == 0b1111 {
+            Self::INVALID_BLOCK
+        } else {
+            block_type
+        };
+
+        Self([
+            data.0 & 0x0FFF_FFFF,
+            block_type | (Self::ENCODING_VERSION << 28),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod encoding_tests {
+    use super::*;
+
+    const BLOCKS: [BlockType; 5] = [
+        BlockType::Invalid,
+        BlockType::Air,
+        BlockType::Grass,
+        BlockType::Stone,
+        BlockType::Snow,
+    ];
+
+    /// A spread of faces covering every direction, block type and the edges of each field
+    fn faces() -> Vec<(u8, u8, u8, Dir, u8, u8, BlockType)> {
+        let mut faces = vec![];
+
+        for dir in Dir::all() {
+            for block in BLOCKS {
+                for (pos, size) in [(0, 0), (29, 29), (7, 13)] {
+                    faces.push((pos, 29 - pos, pos / 2, dir, size, 29 - size, block));
+                }
+            }
+        }
+
+        faces
+    }
+
+    #[test]
+    fn narrow_round_trip() {
+        for (x, y, z, dir, width, height, block) in faces() {
+            let face = InstanceData::new(x, y, z, dir, width, height, block);
+
+            assert_eq!(face.x(), x);
+            assert_eq!(face.y(), y);
+            assert_eq!(face.z(), z);
+            assert_eq!(usize::from(face.dir()), usize::from(dir));
+            assert_eq!(face.width(), width);
+            assert_eq!(face.height(), height);
+            assert_eq!(face.block_type(), block);
+        }
+    }
+
+    #[test]
+    fn wide_round_trip() {
+        for (x, y, z, dir, width, height, block) in faces() {
+            let face = WideInstanceData::new(x, y, z, dir, width, height, block)
+                .with_ao([0, 1, 2, 3])
+                .with_light(15)
+                .with_variant(9);
+
+            assert_eq!(face.x(), x);
+            assert_eq!(face.y(), y);
+            assert_eq!(face.z(), z);
+            assert_eq!(usize::from(face.dir()), usize::from(dir));
+            assert_eq!(face.width(), width);
+            assert_eq!(face.height(), height);
+            assert_eq!(face.block_type(), block);
+            assert_eq!(face.ao(), [0, 1, 2, 3]);
+            assert_eq!(face.light(), 15);
+            assert_eq!(face.variant(), 9);
+            assert_eq!(face.version(), WideInstanceData::ENCODING_VERSION);
+        }
+    }
+
+    #[test]
+    fn narrow_through_wide_is_unchanged() {
+        for (x, y, z, dir, width, height, block) in faces() {
+            let narrow = InstanceData::new(x, y, z, dir, width, height, block);
+            let wide = WideInstanceData::from(narrow);
+
+            assert_eq!(wide.block_type(), block);
+            assert_eq!(
+                wide,
+                WideInstanceData::new(x, y, z, dir, width, height, block)
+            );
+            assert_eq!(InstanceData::try_from(wide), Ok(narrow));
+            assert_eq!(wide.narrow(), narrow);
+        }
+    }
+
+    #[test]
+    fn wide_only_fields_are_dropped_when_narrowed() {
+        for (x, y, z, dir, width, height, block) in faces() {
+            let wide = WideInstanceData::new(x, y, z, dir, width, height, block)
+                .with_ao([3, 2, 1, 0])
+                .with_light(4)
+                .with_variant(15);
+
+            assert_eq!(
+                wide.narrow(),
+                InstanceData::new(x, y, z, dir, width, height, block)
+            );
+        }
+    }
+
+    #[test]
+    fn large_block_types_narrow_to_invalid() {
+        let face = WideInstanceData::new(3, 4, 5, Dir::Up, 6, 7, BlockType::Stone);
+        let [low, high] = face.words();
+        let wide = WideInstanceData::from([low, (high & !0xFFFF) | 0x20]);
+
+        assert_eq!(InstanceData::try_from(wide), Err(wide));
+
+        let narrow = wide.narrow();
+        assert_eq!(narrow.block_type(), BlockType::Invalid);
+        assert_eq!(
+            (
+                narrow.x(),
+                narrow.y(),
+                narrow.z(),
+                narrow.width(),
+                narrow.height()
+            ),
+            (3, 4, 5, 6, 7)
+        );
+    }
+
+    #[test]
+    fn rotating_keeps_wide_only_fields() {
+        for dir in Dir::all() {
+            let face = WideInstanceData::new(1, 2, 3, dir, 4, 5, BlockType::Snow)
+                .with_ao([1, 2, 3, 0])
+                .with_light(6)
+                .with_variant(7);
+            let rotated = face.rotate_on_dir();
+            let narrow = InstanceData::new(1, 2, 3, dir, 4, 5, BlockType::Snow).rotate_on_dir();
+
+            assert_eq!(rotated.narrow(), narrow);
+            assert_eq!(rotated.ao(), [1, 2, 3, 0]);
+            assert_eq!(rotated.light(), 6);
+            assert_eq!(rotated.variant(), 7);
+            assert_eq!(rotated.block_type(), BlockType::Snow);
+        }
+    }
+}
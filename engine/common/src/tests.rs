@@ -4,97 +4,394 @@ use rayon::prelude::*;
 use bracket_noise::prelude::{FastNoise, FractalType, NoiseType};
 use glam::{IVec3, ivec3};
 
-use crate::{Args, BlockType};
+use crate::{Args, BasicVoxel, BlockType, join_global_pos, seperate_global_pos};
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq)]
+#[derive(Debug, Clone, Copy, clap::ValueEnum, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 #[allow(dead_code)]
 pub enum Scene {
     Single,
     Cube,
     Perlin,
+    /// Every other block in all three axes, so every face is visible and nothing merges
+    Checkerboard,
+    /// Blocks placed at random, filling `fill` percent of the volume
+    Noise,
+    /// Hollow spheres inside each other, every inner shell hidden by the outer one
+    Spheres,
+    /// Staircases climbing along x, one block per step
+    Stairs,
+    /// A solid slab whose block type changes every block along x
+    Stripes,
 }
 
 impl Scene {
-    pub const fn all() -> [Scene; 3] {
-        [Self::Single, Self::Cube, Self::Perlin]
+    pub const fn all() -> [Scene; 8] {
+        [
+            Self::Single,
+            Self::Cube,
+            Self::Perlin,
+            Self::Checkerboard,
+            Self::Noise,
+            Self::Spheres,
+            Self::Stairs,
+            Self::Stripes,
+        ]
+    }
+
+    /// Whether the scene grows with the radius
+    pub fn is_sized(&self) -> bool {
+        !matches!(self, Self::Single | Self::Cube)
+    }
+}
+
+/// Shape of the Perlin terrain
+#[derive(clap::Args, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default = "NoiseConfig::default")]
+pub struct NoiseConfig {
+    /// Terrain noise seed
+    #[arg(long, default_value = "1234")]
+    pub seed: u64,
+
+    /// Terrain FBM octaves
+    #[arg(long, default_value = "5")]
+    pub octaves: i32,
+
+    /// Terrain FBM gain
+    #[arg(long, default_value = "0.5")]
+    pub gain: f32,
+
+    /// Terrain FBM lacunarity
+    #[arg(long, default_value = "2.0")]
+    pub lacunarity: f32,
+
+    /// Terrain noise frequency
+    #[arg(long, default_value = "2.0")]
+    pub frequency: f32,
+
+    /// Blocks per unit of terrain noise, larger spreads the hills out
+    #[arg(long, default_value = "160.0")]
+    pub noise_scale: f32,
+
+    /// Blocks below the top of the terrain height that are snow
+    #[arg(long, default_value = "3")]
+    pub snow_depth: i32,
+
+    /// Fraction of the terrain height above which is grass
+    #[arg(long, default_value = "0.5")]
+    pub grass_level: f32,
+}
+
+impl NoiseConfig {
+    pub const fn default() -> Self {
+        Self {
+            seed: 1234,
+            octaves: 5,
+            gain: 0.5,
+            lacunarity: 2.0,
+            frequency: 2.0,
+            noise_scale: 160.0,
+            snow_depth: 3,
+            grass_level: 0.5,
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn noise(&self) -> FastNoise {
+        let mut noise = FastNoise::seeded(self.seed);
+        noise.set_noise_type(NoiseType::PerlinFractal);
+        noise.set_fractal_type(FractalType::FBM);
+        noise.set_fractal_octaves(self.octaves);
+        noise.set_fractal_gain(self.gain);
+        noise.set_fractal_lacunarity(self.lacunarity);
+        noise.set_frequency(self.frequency);
+
+        noise
     }
 }
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq)]
+impl std::fmt::Display for NoiseConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "seed {} octaves {} gain {} lacunarity {} frequency {} scale {} snow {} grass {}",
+            self.seed,
+            self.octaves,
+            self.gain,
+            self.lacunarity,
+            self.frequency,
+            self.noise_scale,
+            self.snow_depth,
+            self.grass_level
+        )
+    }
+}
+
+const CHUNK_SIZE: i32 = 30;
+
+/// Gap between the shells of [`Scene::Spheres`]
+const SHELL_SPACING: i32 = 8;
+const STRIPE_TYPES: [BlockType; 3] = [BlockType::Grass, BlockType::Stone, BlockType::Snow];
+
+/// Cheap, well mixed hash of a position, so random scenes are the same every run
+fn hash_position(pos: IVec3) -> u32 {
+    let mut h = (pos.x as u32).wrapping_mul(0x8da6b343)
+        ^ (pos.y as u32).wrapping_mul(0xd8163841)
+        ^ (pos.z as u32).wrapping_mul(0xcb1ab31f);
+
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub enum Test {
     Tri,
     Basic,
     Culled,
+    /// Culled by looking up each block's neighbours, without depth masks
+    NaiveCulled,
     Greedy,
+    /// Surface Nets over the voxel density
+    Smooth,
 }
 
 impl Test {
     pub fn iter() -> impl Iterator<Item = Self> {
-        [Self::Basic, Self::Culled, Self::Greedy].iter().copied()
+        [
+            Self::Basic,
+            Self::NaiveCulled,
+            Self::Culled,
+            Self::Greedy,
+            Self::Smooth,
+        ]
+        .iter()
+        .copied()
     }
 }
 
-pub fn test_scene(args: &Args) -> DashMap<IVec3, BlockType> {
-    println!("Creating test scene");
-    let scene = match args.scene {
-        Scene::Single => {
-            let map = DashMap::new();
-            map.insert(ivec3(0, 30, -5), BlockType::Grass);
-            map
+/// Blocks of a single chunk, indexed by the position in the chunk
+pub type ChunkBlocks =
+    [[[BasicVoxel; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
+
+/// Decides which block is at each position of a scene.
+/// Positions can be asked for in any order, so scenes can be built a chunk at a time.
+pub struct SceneGenerator {
+    scene: Scene,
+    radius: i32,
+    depth: i32,
+    fill: u32,
+    config: NoiseConfig,
+    noise: Option<FastNoise>,
+}
+
+impl SceneGenerator {
+    pub fn new(args: &Args) -> Self {
+        let noise = (args.scene == Scene::Perlin).then(|| {
+            println!("Terrain noise: {}", args.noise);
+            args.noise.noise()
+        });
+
+        Self {
+            scene: args.scene,
+            radius: args.radius,
+            depth: args.depth,
+            fill: args.fill.min(100) as u32,
+            config: args.noise,
+            noise,
         }
-        Scene::Cube => {
-            let map = DashMap::new();
-            (0..30).for_each(|x| {
-                (0..30).for_each(|y| {
-                    (30..60).for_each(|z| {
-                        map.insert(ivec3(x, y, z), BlockType::Grass);
-                    })
-                })
-            });
+    }
 
-            map
+    /// Corners of the box every block falls in, the max is exclusive
+    fn bounds(&self) -> (IVec3, IVec3) {
+        let r = self.radius;
+        match self.scene {
+            Scene::Single => (ivec3(0, 30, -5), ivec3(1, 31, -4)),
+            Scene::Cube => (ivec3(0, 0, 30), ivec3(30, 30, 60)),
+            // FBM noise stays within -1 to 1, so the terrain is under 1.7 times the depth
+            Scene::Perlin => (ivec3(-r, 0, -r), ivec3(r, self.depth * 2 + 2, r)),
+            Scene::Spheres => (ivec3(-r, 0, -r), ivec3(r, r * 2, r)),
+            Scene::Stairs => (ivec3(-r, 0, -r), ivec3(r, self.depth.max(1), r)),
+            Scene::Checkerboard | Scene::Noise | Scene::Stripes => {
+                (ivec3(-r, 0, -r), ivec3(r, self.depth, r))
+            }
         }
-        Scene::Perlin => {
-            let mut noise = FastNoise::seeded(1234);
-            noise.set_noise_type(NoiseType::PerlinFractal);
-            noise.set_fractal_type(FractalType::FBM);
-            noise.set_fractal_octaves(5);
-            noise.set_fractal_gain(0.5);
-            noise.set_fractal_lacunarity(2.0);
-            noise.set_frequency(2.0);
-
-            const NOISE_SCALE: f32 = 160.0;
-
-            let radius = args.radius;
-            let input_height = args.depth;
-
-            let tuples: Vec<(i32, i32)> = (-radius..radius)
-                .flat_map(|x| (-radius..radius).map(move |z| (x, z)))
-                .collect();
-
-            tuples
-                .into_par_iter()
-                .flat_map(|(x, z)| {
-                    let noise = noise.get_noise(x as f32 / NOISE_SCALE, z as f32 / NOISE_SCALE);
-
-                    let height = ((noise + 0.7) * input_height as f32).ceil() as i32;
-
-                    (0..=height).into_par_iter().map(move |y| {
-                        let block_type = if y > input_height - 3 {
-                            BlockType::Snow
-                        } else if y > input_height / 2 {
-                            BlockType::Grass
-                        } else {
-                            BlockType::Stone
-                        };
-
-                        (ivec3(x, y, z), block_type)
-                    })
-                })
-                .collect()
+    }
+
+    fn in_bounds(&self, pos: IVec3) -> bool {
+        let (min, max) = self.bounds();
+        pos.cmpge(min).all() && pos.cmplt(max).all()
+    }
+
+    /// Anything shared by a column of blocks, which is the terrain height for Perlin
+    fn column(&self, x: i32, z: i32) -> i32 {
+        let Some(noise) = &self.noise else {
+            return 0;
+        };
+
+        let scale = self.config.noise_scale;
+        let noise = noise.get_noise(x as f32 / scale, z as f32 / scale);
+
+        ((noise + 0.7) * self.depth as f32).ceil() as i32
+    }
+
+    /// The block at `pos`, or `None` for air. `column` is [`SceneGenerator::column`]
+    fn block_at(&self, pos: IVec3, column: i32) -> Option<BlockType> {
+        match self.scene {
+            Scene::Single | Scene::Cube => Some(BlockType::Grass),
+            Scene::Perlin => {
+                let height = column;
+                let snow_height = self.depth - self.config.snow_depth;
+                let grass_height = self.depth as f32 * self.config.grass_level;
+
+                if pos.y > height {
+                    None
+                } else if pos.y > snow_height {
+                    Some(BlockType::Snow)
+                } else if pos.y as f32 > grass_height {
+                    Some(BlockType::Grass)
+                } else {
+                    Some(BlockType::Stone)
+                }
+            }
+            Scene::Checkerboard => {
+                ((pos.x + pos.y + pos.z).rem_euclid(2) == 0).then_some(BlockType::Grass)
+            }
+            Scene::Noise => (hash_position(pos) % 100 < self.fill).then_some(BlockType::Stone),
+            Scene::Spheres => {
+                let centre = ivec3(0, self.radius, 0);
+                let distance = (pos - centre).as_vec3().length().round() as i32;
+
+                (distance <= self.radius && distance % SHELL_SPACING == SHELL_SPACING - 1)
+                    .then_some(BlockType::Stone)
+            }
+            Scene::Stairs => {
+                let step = (pos.x + self.radius) % self.depth.max(1);
+                (pos.y <= step).then_some(BlockType::Stone)
+            }
+            Scene::Stripes => {
+                let stripe = pos.x.rem_euclid(STRIPE_TYPES.len() as i32) as usize;
+                Some(STRIPE_TYPES[stripe])
+            }
+        }
+    }
+
+    /// Every chunk that could have a block in it
+    pub fn chunk_positions(&self) -> Vec<IVec3> {
+        let (min, max) = self.bounds();
+        let (min, _) = seperate_global_pos(&min);
+        let (max, _) = seperate_global_pos(&(max - IVec3::ONE));
+
+        (min.x..=max.x)
+            .flat_map(|x| {
+                (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| ivec3(x, y, z)))
+            })
+            .collect()
+    }
+
+    /// Generate a whole chunk, or `None` if it has no blocks
+    pub fn generate_chunk(&self, chunk_pos: IVec3) -> Option<Box<ChunkBlocks>> {
+        let mut blocks: Option<Box<ChunkBlocks>> = None;
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column_pos = join_global_pos(&chunk_pos, &ivec3(x, 0, z));
+                let column = self.column(column_pos.x, column_pos.z);
+
+                for y in 0..CHUNK_SIZE {
+                    let in_chunk_pos = ivec3(x, y, z);
+                    let pos = join_global_pos(&chunk_pos, &in_chunk_pos);
+
+                    // Some positions of negative chunks belong to their neighbours
+                    if !self.in_bounds(pos) || seperate_global_pos(&pos).0 != chunk_pos {
+                        continue;
+                    }
+
+                    if let Some(block_type) = self.block_at(pos, column) {
+                        let blocks = blocks.get_or_insert_with(|| {
+                            Box::new(
+                                [[[BasicVoxel::new(BlockType::Air); CHUNK_SIZE as usize];
+                                    CHUNK_SIZE as usize];
+                                    CHUNK_SIZE as usize],
+                            )
+                        });
+
+                        blocks[x as usize][y as usize][z as usize] = BasicVoxel::new(block_type);
+                    }
+                }
+            }
         }
-    };
 
-    println!("Finished generating scsene");
+        blocks
+    }
+
+    /// Generate every chunk in parallel, leaving out empty ones
+    pub fn generate_chunks(&self) -> Vec<(IVec3, Box<ChunkBlocks>)> {
+        self.chunk_positions()
+            .into_par_iter()
+            .filter_map(|pos| self.generate_chunk(pos).map(|blocks| (pos, blocks)))
+            .collect()
+    }
+
+    /// Generate every block into one map, a column at a time in parallel
+    pub fn generate_blocks(&self) -> DashMap<IVec3, BlockType> {
+        let (min, max) = self.bounds();
+
+        let columns: Vec<(i32, i32)> = (min.x..max.x)
+            .flat_map(|x| (min.z..max.z).map(move |z| (x, z)))
+            .collect();
+
+        columns
+            .into_par_iter()
+            .flat_map_iter(|(x, z)| {
+                let column = self.column(x, z);
+
+                (min.y..max.y).filter_map(move |y| {
+                    let pos = ivec3(x, y, z);
+                    self.block_at(pos, column)
+                        .map(|block_type| (pos, block_type))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Every block of the scene in one map, for meshers that look blocks up by position
+pub fn test_scene(args: &Args) -> DashMap<IVec3, BlockType> {
+    println!("Creating test scene");
+    let start = std::time::Instant::now();
+
+    let scene = SceneGenerator::new(args).generate_blocks();
+
+    println!(
+        "Generated {} blocks in {:.2}ms",
+        scene.len(),
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    report_peak_memory();
+
     scene
 }
+
+/// Print the most memory the process has used so far, where the OS reports it
+pub fn report_peak_memory() {
+    if let Some(kb) = peak_memory_kb() {
+        println!("Peak memory: {:.1} MiB", kb as f64 / 1024.0);
+    }
+}
+
+/// Peak resident memory in KiB, read from VmHWM in /proc/self/status
+pub fn peak_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
@@ -3,27 +3,57 @@ use std::hint::black_box;
 
 use common::{
     Args, BasicVoxel, BlockType,
-    tests::{Scene, Test, test_scene},
+    tests::{Scene, SceneGenerator, Test, test_scene},
 };
 use criterion::{Criterion, criterion_group, criterion_main};
 
 use dashmap::DashMap;
-use glam::ivec3;
-use meshing::binary::{common::*, culled::chunk_data};
+use glam::{IVec3, ivec3};
+use meshing::{
+    binary::{common::*, culled::chunk_data},
+    smooth::surface_nets,
+};
+
+/// A chunk at the origin whose neighbours all share its voxels
+fn uniform_refs(voxels: &VoxelArray) -> ChunkRefs<'_> {
+    let voxel_ref = |position: IVec3| VoxelRef { voxels, position };
+
+    ChunkRefs {
+        chunk: voxel_ref(IVec3::ZERO),
+        pos: VoxelArrayRef {
+            x: voxel_ref(IVec3::X),
+            y: voxel_ref(IVec3::Y),
+            z: voxel_ref(IVec3::Z),
+        },
+        neg: VoxelArrayRef {
+            x: voxel_ref(IVec3::NEG_X),
+            y: voxel_ref(IVec3::NEG_Y),
+            z: voxel_ref(IVec3::NEG_Z),
+        },
+    }
+}
+
+/// Each meshing stage on its own, so changes to one show up directly
+fn stages(c: &mut Criterion, name: &str, refs: &ChunkRefs) {
+    let depths = build_depths(refs);
+    let culled = cull_depths(&depths);
+
+    c.bench_function(&format!("Build Depths {}", name), |b| {
+        b.iter(|| build_depths(black_box(refs)))
+    });
+    c.bench_function(&format!("Cull Depths {}", name), |b| {
+        b.iter(|| cull_depths(black_box(&depths)))
+    });
+    c.bench_function(&format!("Depths To Faces {}", name), |b| {
+        b.iter(|| depths_to_faces(black_box(&culled), black_box(refs)))
+    });
+}
 
 pub fn culled(c: &mut Criterion) {
     {
         let blank_voxels =
             [[[BasicVoxel::new(BlockType::Air); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
-        let refs = ChunkRefs {
-            chunk: &blank_voxels,
-            x_neg: &blank_voxels,
-            x_pos: &blank_voxels,
-            y_neg: &blank_voxels,
-            y_pos: &blank_voxels,
-            z_neg: &blank_voxels,
-            z_pos: &blank_voxels,
-        };
+        let refs = uniform_refs(&blank_voxels);
         let depths = build_depths(&refs);
 
         c.bench_function("Single Culled Empty", |b| {
@@ -40,16 +70,10 @@ pub fn culled(c: &mut Criterion) {
     {
         let full_voxels =
             [[[BasicVoxel::new(BlockType::Grass); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
-        let refs = ChunkRefs {
-            chunk: &full_voxels,
-            x_neg: &full_voxels,
-            x_pos: &full_voxels,
-            y_neg: &full_voxels,
-            y_pos: &full_voxels,
-            z_neg: &full_voxels,
-            z_pos: &full_voxels,
-        };
+        let refs = uniform_refs(&full_voxels);
         let depths = build_depths(&refs);
+        stages(c, "Full", &refs);
+
         c.bench_function("Single Culled Full", |b| {
             b.iter(|| {
                 make_culled_faces(black_box(&refs), black_box(&depths));
@@ -71,6 +95,9 @@ pub fn culled(c: &mut Criterion) {
 
         chunk_data(&blocks, &args, &chunks);
         let chunk = chunks.get(&ivec3(0, 0, 0)).unwrap();
+        chunk
+            .voxels()
+            .with_refs(&chunks, &ivec3(0, 0, 0), |refs| stages(c, "Perlin", refs));
         chunk.voxels().build_depths(&chunks, &ivec3(0, 0, 0));
         let voxels = chunk.voxels();
         let mask = voxels.depth_mask.read().unwrap();
@@ -98,6 +125,10 @@ pub fn culled(c: &mut Criterion) {
                 ))
             });
         });
+        c.bench_function("Single Smooth Perlin", |b| {
+            let pos = ivec3(0, 0, 0);
+            b.iter(|| black_box(surface_nets(black_box(&blocks), black_box(pos))));
+        });
     }
 
     macro_rules! serial_bench {
@@ -173,6 +204,20 @@ pub fn culled(c: &mut Criterion) {
         }};
     }
 
+    {
+        let mut args = Args::default();
+        args.scene = Scene::Perlin;
+        args.radius = 128;
+        let generator = SceneGenerator::new(&args);
+
+        c.bench_function("Generate Blocks Perlin 128", |b| {
+            b.iter(|| black_box(generator.generate_blocks()))
+        });
+        c.bench_function("Generate Chunks Perlin 128", |b| {
+            b.iter(|| black_box(generator.generate_chunks()))
+        });
+    }
+
     parallel_bench!(1);
     parallel_bench!(2);
     parallel_bench!(4);
@@ -1,11 +1,10 @@
-// type HashMap<K, V> = std::collections::HashMap<K, V>;
-type HashMap<K, V> = hashbrown::HashMap<K, V>;
+use std::simd::{Mask, Select, Simd};
 
 use dashmap::DashMap;
 use glam::IVec3;
 use renderer::{Axis, Dir};
 
-use common::{BasicVoxel, BlockType, Voxel};
+use common::{BasicVoxel, BlockType, Voxel, WideInstanceData};
 
 use super::culled::Chunk;
 
@@ -15,10 +14,112 @@ pub const CHUNK_SIZE: usize = 30;
 type Depth = u32;
 pub type VoxelArray = [[[BasicVoxel; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
 pub type AxisDepths = [[[Depth; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 3];
-type FaceDepths = Box<[[[Depth; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6]>;
-type TransformedBlockDepths = [HashMap<BlockType, Box<[[u32; CHUNK_SIZE]; CHUNK_SIZE]>>; 6];
+pub type FaceDepths = Box<[[[Depth; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6]>;
+type FacePlane = [[u32; CHUNK_SIZE]; CHUNK_SIZE];
 type GreedyFaces = Vec<GreedyFace>;
 
+/// One depth per lane, a row of a padded depth mask
+type DepthLanes = Simd<Depth, CHUNK_SIZE_P>;
+type LaneMask = Mask<i32, CHUNK_SIZE_P>;
+
+/// Face bits for each direction, transformed so the integer runs along the horizontal axis.
+/// Each block type in the chunk gets a dense plane per direction, found through a palette.
+pub struct BlockFaces {
+    palette: Vec<BlockType>,
+    /// Palette index of each block type, [`BlockFaces::UNUSED`] if it isn't in the chunk
+    slots: [u8; BlockType::COUNT],
+    /// Indexed by direction, then palette index
+    planes: [Vec<FacePlane>; 6],
+}
+
+impl BlockFaces {
+    const UNUSED: u8 = u8::MAX;
+
+    fn new() -> Self {
+        Self {
+            palette: vec![],
+            slots: [Self::UNUSED; BlockType::COUNT],
+            planes: Default::default(),
+        }
+    }
+
+    /// Palette index of `block_type`, adding its planes the first time it is seen
+    #[inline]
+    fn slot(&mut self, block_type: BlockType) -> usize {
+        let slot = &mut self.slots[block_type as usize];
+
+        if *slot == Self::UNUSED {
+            *slot = self.palette.len() as u8;
+            self.palette.push(block_type);
+
+            for planes in self.planes.iter_mut() {
+                planes.push([[0; CHUNK_SIZE]; CHUNK_SIZE]);
+            }
+        }
+
+        *slot as usize
+    }
+
+    pub fn planes(&self, dir: Dir) -> impl Iterator<Item = (&BlockType, &FacePlane)> {
+        self.palette
+            .iter()
+            .zip(self.planes[usize::from(dir)].iter())
+    }
+
+    pub fn planes_mut(&mut self, dir: Dir) -> impl Iterator<Item = (&BlockType, &mut FacePlane)> {
+        self.palette
+            .iter()
+            .zip(self.planes[usize::from(dir)].iter_mut())
+    }
+}
+
+/// Which slices of a chunk need their faces rebuilt, one bit per slice along each axis.
+/// A slice only covers the faces that point along its axis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirtySlices([u32; 3]);
+
+impl DirtySlices {
+    pub const ALL: Self = Self([(1 << CHUNK_SIZE) - 1; 3]);
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::ALL
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Mark a slice, ignoring any outside of the chunk
+    pub fn mark(&mut self, axis: Axis, slice: i32) {
+        if (0..CHUNK_SIZE as i32).contains(&slice) {
+            self.0[usize::from(axis)] |= 1 << slice;
+        }
+    }
+
+    /// Mark every slice whose faces an edit at `pos` could change.
+    /// That is the voxel's own faces, and the faces of its neighbours that touch it.
+    pub fn mark_voxel(&mut self, pos: IVec3) {
+        for axis in Axis::all() {
+            let p = pos[usize::from(axis)];
+            for slice in p - 1..=p + 1 {
+                self.mark(axis, slice);
+            }
+        }
+    }
+
+    pub fn axis_mask(&self, axis: Axis) -> u32 {
+        self.0[usize::from(axis)]
+    }
+
+    /// Whether a face, already rotated on its direction, sits in a dirty slice
+    pub fn contains_face(&self, face: WideInstanceData) -> bool {
+        let axis = Axis::from(face.dir());
+        let slice = [face.x(), face.y(), face.z()][usize::from(axis)];
+
+        self.axis_mask(axis) & (1 << slice) != 0
+    }
+}
+
 #[derive(Debug)]
 pub struct GreedyFace {
     pub x: u8,
@@ -55,6 +156,17 @@ pub fn make_faces(
     position: &IVec3,
     depths: &AxisDepths,
     greedy: bool,
+) -> GreedyFaces {
+    make_slice_faces(chunks, position, depths, greedy, DirtySlices::ALL)
+}
+
+/// Make only the faces that sit in `slices`
+pub fn make_slice_faces(
+    chunks: &DashMap<IVec3, Chunk>,
+    position: &IVec3,
+    depths: &AxisDepths,
+    greedy: bool,
+    slices: DirtySlices,
 ) -> GreedyFaces {
     macro_rules! get_chunk {
         ($chunk_name:ident, $block_name:ident,$pos:expr) => {
@@ -160,23 +272,26 @@ pub fn make_faces(
         neg,
     };
 
+    let culled = cull_depths(depths);
+    let block_faces = depths_to_slice_faces(&culled, &refs, slices);
+
     if greedy {
-        make_greedy_faces(&refs, depths)
+        greedy_faces(block_faces)
     } else {
-        make_culled_faces(&refs, depths)
+        culled_faces(block_faces)
     }
 }
 
 pub fn make_culled_faces(refs: &ChunkRefs, depths: &AxisDepths) -> GreedyFaces {
     let culled = cull_depths(depths);
-    let block_faces = depths_to_faces(culled, refs);
+    let block_faces = depths_to_faces(&culled, refs);
 
     culled_faces(block_faces)
 }
 
 pub fn make_greedy_faces(chunks: &ChunkRefs, depths: &AxisDepths) -> GreedyFaces {
     let culled = cull_depths(depths);
-    let block_faces = depths_to_faces(culled, chunks);
+    let block_faces = depths_to_faces(&culled, chunks);
 
     greedy_faces(block_faces)
 }
@@ -203,42 +318,38 @@ pub fn build_depths(chunks: &ChunkRefs) -> Box<AxisDepths> {
 
     #[inline]
     fn get_block(blocks: &VoxelRef, position: &IVec3, x: usize, y: usize, z: usize) -> bool {
-        let block_x = blocks.position.x.signum() >= 0;
-        let position_x = position.x.signum() >= 0;
-        let block_y = blocks.position.y.signum() >= 0;
-        let position_y = position.y.signum() >= 0;
-        let block_z = blocks.position.z.signum() >= 0;
-        let position_z = position.z.signum() >= 0;
-
-        let x = if block_x != position_x {
-            CHUNK_SIZE - x - 1
-        } else {
-            x
-        };
-
-        let y = if block_y != position_y {
-            CHUNK_SIZE - y - 1
-        } else {
-            y
-        };
+        neighbour_voxel(blocks, position, x, y, z).is_solid()
+    }
 
-        let z = if block_z != position_z {
-            CHUNK_SIZE - z - 1
-        } else {
-            z
-        };
+    // Each row of voxels runs along z, so becomes one lane per z.
+    // The X and Y masks are built a lane per z too, then written out.
+    let mut x_depths = [DepthLanes::splat(0); CHUNK_SIZE_P];
 
-        blocks.voxels[x][y][z].get_type().is_solid()
-    }
+    for x in 0..CHUNK_SIZE {
+        let mut y_depths = DepthLanes::splat(0);
 
-    for z in 0..CHUNK_SIZE {
         for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let v = chunks.chunk.voxels[x][y][z].get_type().is_solid();
-                // Add One to compensate for padding
-                add_voxel(v, x + 1, y + 1, z + 1, &mut depths);
+            // Add One to compensate for padding
+            let mut row = [false; CHUNK_SIZE_P];
+            for (z, voxel) in chunks.chunk.voxels[x][y].iter().enumerate() {
+                row[z + 1] = voxel.get_type().is_solid();
             }
+            let solid = LaneMask::from_array(row);
+
+            depths[usize::from(Axis::Z)][y + 1][x + 1] = solid.to_bitmask() as Depth;
+
+            let x_depth = &mut x_depths[y + 1];
+            *x_depth = solid.select(*x_depth | DepthLanes::splat(1 << (x + 1)), *x_depth);
+            y_depths = solid.select(y_depths | DepthLanes::splat(1 << (y + 1)), y_depths);
         }
+
+        for (z, depth) in y_depths.to_array().into_iter().enumerate() {
+            depths[usize::from(Axis::Y)][z][x + 1] = depth;
+        }
+    }
+
+    for (y, depth) in x_depths.into_iter().enumerate() {
+        depths[usize::from(Axis::X)][y] = depth.to_array();
     }
 
     for z in 0..CHUNK_SIZE {
@@ -274,28 +385,124 @@ pub fn build_depths(chunks: &ChunkRefs) -> Box<AxisDepths> {
     depths
 }
 
+/// Get a voxel from a neighbouring chunk, flipping the coordinates
+/// if the neighbour lies on the other side of an axis to `position`.
+#[inline]
+pub fn neighbour_voxel(
+    blocks: &VoxelRef,
+    position: &IVec3,
+    x: usize,
+    y: usize,
+    z: usize,
+) -> BlockType {
+    let block_x = blocks.position.x.signum() >= 0;
+    let position_x = position.x.signum() >= 0;
+    let block_y = blocks.position.y.signum() >= 0;
+    let position_y = position.y.signum() >= 0;
+    let block_z = blocks.position.z.signum() >= 0;
+    let position_z = position.z.signum() >= 0;
+
+    let x = if block_x != position_x {
+        CHUNK_SIZE - x - 1
+    } else {
+        x
+    };
+
+    let y = if block_y != position_y {
+        CHUNK_SIZE - y - 1
+    } else {
+        y
+    };
+
+    let z = if block_z != position_z {
+        CHUNK_SIZE - z - 1
+    } else {
+        z
+    };
+
+    blocks.voxels[x][y][z].get_type()
+}
+
+/// Flatten a chunk and the faces of its neighbours into a padded
+/// 32^3 array of block ids, indexed as `(x * 32 + y) * 32 + z`.
+/// Non solid blocks are written as 0 so the GPU mesher can treat them as air.
+pub fn padded_voxels(chunks: &ChunkRefs) -> Box<[u32]> {
+    let mut voxels = vec![0u32; CHUNK_SIZE_P * CHUNK_SIZE_P * CHUNK_SIZE_P].into_boxed_slice();
+
+    #[inline]
+    fn add_voxel(block_type: BlockType, x: usize, y: usize, z: usize, voxels: &mut [u32]) {
+        if block_type.is_solid() {
+            voxels[(x * CHUNK_SIZE_P + y) * CHUNK_SIZE_P + z] = block_type.into();
+        }
+    }
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let v = chunks.chunk.voxels[x][y][z].get_type();
+                // Add One to compensate for padding
+                add_voxel(v, x + 1, y + 1, z + 1, &mut voxels);
+            }
+        }
+    }
+
+    let position = &chunks.chunk.position;
+
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            let min = neighbour_voxel(&chunks.pos.x, position, 0, y, z);
+            let max = neighbour_voxel(&chunks.neg.x, position, CHUNK_SIZE - 1, y, z);
+
+            add_voxel(max, 0, y + 1, z + 1, &mut voxels);
+            add_voxel(min, CHUNK_SIZE + 1, y + 1, z + 1, &mut voxels);
+        }
+    }
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            let min = neighbour_voxel(&chunks.pos.z, position, x, y, 0);
+            let max = neighbour_voxel(&chunks.neg.z, position, x, y, CHUNK_SIZE - 1);
+
+            add_voxel(max, x + 1, y + 1, 0, &mut voxels);
+            add_voxel(min, x + 1, y + 1, CHUNK_SIZE + 1, &mut voxels);
+        }
+    }
+
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let min = neighbour_voxel(&chunks.pos.y, position, x, 0, z);
+            let max = neighbour_voxel(&chunks.neg.y, position, x, CHUNK_SIZE - 1, z);
+
+            add_voxel(max, x + 1, 0, z + 1, &mut voxels);
+            add_voxel(min, x + 1, CHUNK_SIZE + 1, z + 1, &mut voxels);
+        }
+    }
+
+    voxels
+}
+
 /// Use binary shifting and binary not to locate
 /// when we move between a solid to an air block
 /// Store this in binary slices for all faces.
 /// Each integer is a view along the depth of that axis.
 pub fn cull_depths(depths: &AxisDepths) -> FaceDepths {
     let mut culled_faces = Box::new([[[0; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6]);
+    let one = DepthLanes::splat(1);
 
     for axis in Axis::all() {
         for z in 0..CHUNK_SIZE_P {
-            for x in 0..CHUNK_SIZE_P {
-                let col = &depths[usize::from(axis)][z][x];
-
-                // Binary not against a left / right shift
-                // only leaves a 1 where you moved from 0-1 in the
-                // opposite direction of the shift
-                //       1->0
-                //     001100 &
-                // !<< 100111 =
-                //     000100
-                culled_faces[2 * usize::from(axis)][z][x] = col & !(col << 1);
-                culled_faces[2 * usize::from(axis) + 1][z][x] = col & !(col >> 1);
-            }
+            // A whole row of columns at once
+            let col = DepthLanes::from_array(depths[usize::from(axis)][z]);
+
+            // Binary not against a left / right shift
+            // only leaves a 1 where you moved from 0-1 in the
+            // opposite direction of the shift
+            //       1->0
+            //     001100 &
+            // !<< 100111 =
+            //     000100
+            culled_faces[2 * usize::from(axis)][z] = (col & !(col << one)).to_array();
+            culled_faces[2 * usize::from(axis) + 1][z] = (col & !(col >> one)).to_array();
         }
     }
 
@@ -303,23 +510,31 @@ pub fn cull_depths(depths: &AxisDepths) -> FaceDepths {
 }
 
 /// Transform depth from going along the integer, to the horizonal axis (X-Z) going along the integer.
-pub fn depths_to_faces(depths: FaceDepths, chunks: &ChunkRefs) -> TransformedBlockDepths {
-    let mut faces: TransformedBlockDepths = [
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-    ];
+pub fn depths_to_faces(depths: &FaceDepths, chunks: &ChunkRefs) -> BlockFaces {
+    depths_to_slice_faces(depths, chunks, DirtySlices::ALL)
+}
+
+/// [`depths_to_faces`], leaving out every face outside of `slices`
+pub fn depths_to_slice_faces(
+    depths: &FaceDepths,
+    chunks: &ChunkRefs,
+    slices: DirtySlices,
+) -> BlockFaces {
+    let mut faces = BlockFaces::new();
+
+    // Cut the padding, as it's not part of the chunk
+    let one = DepthLanes::splat(1);
 
     for dir in Dir::all() {
+        // A column's bits are the slices along the direction's axis
+        let padding = DepthLanes::splat(!(1 << CHUNK_SIZE) & slices.axis_mask(Axis::from(dir)));
+
         for z in 0..CHUNK_SIZE {
+            let cols = ((DepthLanes::from_array(depths[usize::from(dir)][z + 1]) >> one) & padding)
+                .to_array();
+
             for x in 0..CHUNK_SIZE {
-                // Cut the padding, as it's not part of the chunk
-                let mut col = depths[usize::from(dir)][z + 1][x + 1];
-                col >>= 1;
-                col &= !(1 << CHUNK_SIZE);
+                let mut col = cols[x + 1];
 
                 while col != 0 {
                     // Get the depth
@@ -336,10 +551,9 @@ pub fn depths_to_faces(depths: FaceDepths, chunks: &ChunkRefs) -> TransformedBlo
                         Dir::Forward | Dir::Backward => chunks.chunk.voxels[x][z][y],
                     };
 
-                    let block_type = voxel.get_type();
+                    let slot = faces.slot(voxel.get_type());
 
-                    let data = faces[usize::from(dir)].entry(block_type).or_default();
-                    data[y][x] |= 1 << z;
+                    faces.planes[usize::from(dir)][slot][y][x] |= 1 << z;
                 }
             }
         }
@@ -348,13 +562,13 @@ pub fn depths_to_faces(depths: FaceDepths, chunks: &ChunkRefs) -> TransformedBlo
     faces
 }
 
-pub fn culled_faces(faces: TransformedBlockDepths) -> GreedyFaces {
+pub fn culled_faces(faces: BlockFaces) -> GreedyFaces {
     let mut culled = vec![];
 
     for dir in Dir::all() {
-        for (block_type, dir_depth) in faces[usize::from(dir)].iter() {
-            for depth in 0..CHUNK_SIZE {
-                let faces = culled_face(&dir_depth[depth], depth as u8, dir, block_type);
+        for (block_type, dir_depth) in faces.planes(dir) {
+            for (depth, face) in dir_depth.iter().enumerate() {
+                let faces = culled_face(face, depth as u8, dir, block_type);
                 culled.extend(faces);
             }
         }
@@ -409,13 +623,13 @@ pub fn culled_face(
     quads
 }
 
-pub fn greedy_faces(mut depths: TransformedBlockDepths) -> GreedyFaces {
+pub fn greedy_faces(mut depths: BlockFaces) -> GreedyFaces {
     let mut greedy = vec![];
 
     for dir in Dir::all() {
-        for (block_type, block_face) in depths[usize::from(dir)].iter_mut() {
-            for depth in 0..CHUNK_SIZE {
-                let faces = greedy_face(&mut block_face[depth], depth as u8, dir, block_type);
+        for (block_type, block_face) in depths.planes_mut(dir) {
+            for (depth, face) in block_face.iter_mut().enumerate() {
+                let faces = greedy_face(face, depth as u8, dir, block_type);
                 greedy.extend(faces);
             }
         }
@@ -496,7 +710,9 @@ renderer::snippet!(get_pos, {
         vec4 color;
     }
 
-    PlaneData unpack_data(ivec3 v_pos, uint instance_data, ivec3 chunk_position) {
+    PlaneData unpack_data(ivec3 v_pos, uvec2 face, ivec3 chunk_position) {
+        uint instance_data = face.x;
+
         int v_x = v_pos.x;
         int v_y = v_pos.y;
         int v_z = v_pos.z;
@@ -511,6 +727,16 @@ renderer::snippet!(get_pos, {
         uint height = (instance_data >> 23) & 31;
 
         uint block_type = (instance_data >> 28);
+        float occlusion = 0.0;
+
+        // Wide faces have their encoding version in the top of the second word
+        if ((face.y >> 28) != 0u) {
+            block_type = face.y & 65535u;
+
+            uint corner = uint(v_x + (v_z * 2));
+            uint ao = (face.y >> (16u + (corner * 2u))) & 3u;
+            occlusion = float(ao) * 0.2;
+        }
 
         int w = int(width) + 1;
         int h = int(height) + 1;
@@ -580,6 +806,7 @@ renderer::snippet!(get_pos, {
         }
 
         vec4 color = get_block_color(block_type);
+        color = vec4(color.rgb * (1.0 - occlusion), color.a);
 
         int c_x = chunk_position.x;
         int c_y = chunk_position.y;
@@ -610,3 +837,71 @@ renderer::snippet!(get_pos, {
         return data;
     }
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(slices: &[u32]) -> u32 {
+        slices.iter().map(|s| 1 << s).sum()
+    }
+
+    fn face(x: u8, y: u8, z: u8, dir: Dir) -> WideInstanceData {
+        WideInstanceData::new(x, y, z, dir, 0, 0, BlockType::Stone)
+    }
+
+    #[test]
+    fn mark_voxel_marks_its_neighbours() {
+        let mut dirty = DirtySlices::default();
+        dirty.mark_voxel(IVec3::new(10, 4, 20));
+
+        assert_eq!(dirty.axis_mask(Axis::X), bits(&[9, 10, 11]));
+        assert_eq!(dirty.axis_mask(Axis::Y), bits(&[3, 4, 5]));
+        assert_eq!(dirty.axis_mask(Axis::Z), bits(&[19, 20, 21]));
+    }
+
+    #[test]
+    fn mark_voxel_on_the_chunk_border() {
+        let last = CHUNK_SIZE as i32 - 1;
+        let mut dirty = DirtySlices::default();
+        dirty.mark_voxel(IVec3::new(0, last, 15));
+
+        // Slices past the border belong to the neighbour, which is remeshed on its own
+        assert_eq!(dirty.axis_mask(Axis::X), bits(&[0, 1]));
+        assert_eq!(dirty.axis_mask(Axis::Y), bits(&[28, 29]));
+        assert_eq!(dirty.axis_mask(Axis::Z), bits(&[14, 15, 16]));
+    }
+
+    #[test]
+    fn mark_ignores_slices_outside_the_chunk() {
+        let mut dirty = DirtySlices::default();
+        dirty.mark(Axis::X, -1);
+        dirty.mark(Axis::Y, CHUNK_SIZE as i32);
+
+        assert!(dirty.is_empty());
+
+        for axis in Axis::all() {
+            for slice in 0..CHUNK_SIZE as i32 {
+                dirty.mark(axis, slice);
+            }
+        }
+
+        assert!(dirty.is_all());
+    }
+
+    #[test]
+    fn contains_face_checks_the_slice_along_its_direction() {
+        let mut dirty = DirtySlices::default();
+        dirty.mark_voxel(IVec3::new(10, 4, 20));
+
+        assert!(dirty.contains_face(face(11, 29, 0, Dir::Left)));
+        assert!(dirty.contains_face(face(9, 0, 0, Dir::Right)));
+        assert!(!dirty.contains_face(face(12, 4, 20, Dir::Left)));
+
+        assert!(dirty.contains_face(face(0, 5, 0, Dir::Up)));
+        assert!(!dirty.contains_face(face(10, 6, 20, Dir::Down)));
+
+        assert!(dirty.contains_face(face(0, 0, 21, Dir::Forward)));
+        assert!(!dirty.contains_face(face(10, 4, 0, Dir::Backward)));
+    }
+}
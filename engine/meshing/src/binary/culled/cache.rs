@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use common::{Voxel, WideInstanceData};
+use renderer::{Axis, ChunkTimes};
+
+use super::chunk::{MeshData, MeshFaces};
+use super::dirs::DirRanges;
+use super::visibility::Connectivity;
+use crate::binary::common::{CHUNK_SIZE, ChunkRefs, neighbour_voxel};
+
+/// Bump whenever the file layout or anything the cached meshes depend on changes
+const CACHE_VERSION: u32 = 3;
+const MAGIC: [u8; 4] = *b"VMSH";
+const CACHE_DIR: &str = "mesh_cache";
+const VERSION_FILE: &str = "version";
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64 bit FNV-1a, fast enough to hash every chunk at startup
+#[derive(Debug, Clone, Copy)]
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(FNV_OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hash of everything a chunk's CPU mesh depends on.
+/// The chunk's own voxels, whether its neighbours' bordering voxels are solid,
+/// whether it is greedy meshed and the versions of the cache and face encoding.
+pub fn mesh_key(refs: &ChunkRefs, greedy: bool) -> u64 {
+    let mut hash = Fnv::new();
+
+    hash.write(&CACHE_VERSION.to_le_bytes());
+    hash.write(&WideInstanceData::ENCODING_VERSION.to_le_bytes());
+    hash.write(&[greedy as u8]);
+
+    for plane in refs.chunk.voxels.iter() {
+        for row in plane {
+            let types = row.map(|voxel| voxel.get_type() as u8);
+            hash.write(&types);
+        }
+    }
+
+    // Only the solidity of the neighbours is read when building the depth masks,
+    // through the same mirroring for neighbours on the other side of an axis
+    let last = CHUNK_SIZE - 1;
+    let borders = [
+        (&refs.neg.x, Axis::X, last),
+        (&refs.pos.x, Axis::X, 0),
+        (&refs.neg.y, Axis::Y, last),
+        (&refs.pos.y, Axis::Y, 0),
+        (&refs.neg.z, Axis::Z, last),
+        (&refs.pos.z, Axis::Z, 0),
+    ];
+
+    for (blocks, axis, slice) in borders {
+        for a in 0..CHUNK_SIZE {
+            let mut solid = [0u8; CHUNK_SIZE];
+
+            for (b, solid) in solid.iter_mut().enumerate() {
+                let (x, y, z) = match axis {
+                    Axis::X => (slice, a, b),
+                    Axis::Y => (a, slice, b),
+                    Axis::Z => (a, b, slice),
+                };
+                *solid = neighbour_voxel(blocks, &refs.chunk.position, x, y, z).is_solid() as u8;
+            }
+
+            hash.write(&solid);
+        }
+    }
+
+    hash.finish()
+}
+
+/// Packed faces of meshed chunks, stored on disk by [`mesh_key`] so later runs can skip
+/// meshing any chunk that hasn't changed.
+/// Everything is cleared if the cache or face encoding version changes.
+#[derive(Debug)]
+pub struct MeshCache {
+    dir: PathBuf,
+}
+
+impl MeshCache {
+    /// Open the cache in `mesh_cache/`, clearing it if it was written by another version
+    pub fn open() -> io::Result<Self> {
+        let dir = PathBuf::from(CACHE_DIR);
+        let version = format!("{} {}", CACHE_VERSION, WideInstanceData::ENCODING_VERSION);
+        let version_path = dir.join(VERSION_FILE);
+
+        match fs::read_to_string(&version_path) {
+            Ok(existing) if existing == version => {}
+            Ok(_) => {
+                println!("Mesh cache is out of date, clearing it");
+                fs::remove_dir_all(&dir)?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        fs::create_dir_all(&dir)?;
+        fs::write(&version_path, version)?;
+
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", key))
+    }
+
+    /// The mesh stored under `key`, or `None` if there isn't one or it can't be read
+    pub fn load(&self, key: u64) -> Option<MeshData> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        let mut reader = Reader(&bytes);
+
+        if reader.take::<4>()? != MAGIC
+            || reader.u32()? != CACHE_VERSION
+            || reader.u32()? != WideInstanceData::ENCODING_VERSION
+            || reader.u64()? != key
+        {
+            return None;
+        }
+
+        let connectivity = Connectivity::from_bits(reader.u16()?);
+
+        let mut dir_ranges = DirRanges::default();
+        for (first, count) in dir_ranges.iter_mut() {
+            *first = reader.u32()? as usize;
+            *count = reader.u32()? as usize;
+        }
+
+        let count = reader.u32()? as usize;
+        let instances = (0..count)
+            .map(|_| Some(WideInstanceData::from([reader.u32()?, reader.u32()?])))
+            .collect::<Option<Vec<_>>>()?;
+
+        if !reader.0.is_empty() {
+            return None;
+        }
+
+        Some(MeshData {
+            faces: MeshFaces::Instances(instances, dir_ranges),
+            connectivity,
+            // Set by the chunk loading it
+            edits: 0,
+            times: ChunkTimes::default(),
+        })
+    }
+
+    /// Store a full CPU mesh under `key`, anything else isn't cached
+    pub fn store(&self, key: u64, mesh: &MeshData) -> io::Result<()> {
+        let MeshFaces::Instances(instances, dir_ranges) = &mesh.faces else {
+            return Ok(());
+        };
+
+        let mut bytes = Vec::with_capacity(4 + 4 + 4 + 8 + 2 + 6 * 8 + 4 + instances.len() * 8);
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&WideInstanceData::ENCODING_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&key.to_le_bytes());
+        bytes.extend_from_slice(&mesh.connectivity.bits().to_le_bytes());
+
+        for (first, count) in dir_ranges {
+            bytes.extend_from_slice(&(*first as u32).to_le_bytes());
+            bytes.extend_from_slice(&(*count as u32).to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(instances.len() as u32).to_le_bytes());
+        for word in instances.iter().flat_map(|i| i.words()) {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+
+        // Chunks are meshed in parallel, so never leave a half written file where another can read it
+        let path = self.path(key);
+        let temp = path.with_extension(format!(
+            "{}.tmp",
+            rayon::current_thread_index().unwrap_or_default()
+        ));
+
+        fs::write(&temp, bytes)?;
+        fs::rename(temp, path)
+    }
+}
+
+/// Reads little endian values from the front of a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+
+        Some(*head)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+}
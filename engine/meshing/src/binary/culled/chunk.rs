@@ -1,23 +1,29 @@
 use std::{
     ops::{Deref, DerefMut},
     sync::RwLock,
+    time::Instant,
 };
 
 use dashmap::DashMap;
 use glam::IVec3;
 use renderer::{
-    Axis, DrawMode, ProgramSource, SSBO, Uniforms,
+    Axis, ChunkTimes, DrawMode, ProgramSource, SSBO, Uniforms,
     bounds::BoundingHeirarchy,
-    buffers::{BlankVao, ShaderBuffer},
+    buffers::{BlankVao, BufferError, ShaderBuffer},
     mesh::{Mesh, ninstanced::NInstancedMesh},
 };
 
 use crate::binary::common::{
-    AxisDepths, CHUNK_SIZE, ChunkRefs, VoxelArray, VoxelArrayRef, VoxelRef, build_depths,
-    make_faces,
+    AxisDepths, CHUNK_SIZE, ChunkRefs, DirtySlices, VoxelArray, VoxelArrayRef, VoxelRef,
+    build_depths, make_slice_faces, padded_voxels,
 };
-use common::{BasicVoxel, BlockType, InstanceData, Voxel};
+use common::{BasicVoxel, BlockType, Voxel, WideInstanceData};
 
+use super::cache::{MeshCache, mesh_key};
+use super::dirs::{DirRanges, bucket_by_dir, visible_ranges};
+use super::faces::{FaceEncoding, NarrowFace, WideFace};
+use super::gpu::GpuMesh;
+use super::visibility::Connectivity;
 use super::voxel::{
     culled_voxel,
     culled_voxel_vertex_pull::{self, uses::vertex_pull_face_data::buffers::FaceData},
@@ -66,123 +72,229 @@ impl VoxelData {
         }
     }
 
-    pub fn build_depths(&self, chunks: &DashMap<IVec3, Chunk>, position: &IVec3) {
-        if self.depth_mask.read().unwrap().is_none() {
-            macro_rules! get_chunk {
-                ($chunk_name:ident, $block_name:ident,$pos:expr) => {
-                    let $chunk_name = chunks.get($pos);
-                    let $chunk_name = if let Some(ref chunk) = $chunk_name {
-                        let voxels = chunk.voxels();
-                        let read = voxels.voxels.read().expect("Failed to read blocks");
-
-                        Some((chunk, voxels, read))
-                    } else {
-                        None
-                    };
-                    let $block_name = if let Some(ref read) = $chunk_name {
-                        let read = &read.2;
-                        read.as_ref()
-                    } else {
-                        &crate::binary::common::BLANK_VOXELS
-                    };
+    /// Build the depth masks unless they already are, returning whether they had to be
+    pub fn build_depths(&self, chunks: &DashMap<IVec3, Chunk>, position: &IVec3) -> bool {
+        if self.depth_mask.read().unwrap().is_some() {
+            return false;
+        }
+
+        let mask = self.with_refs(chunks, position, build_depths);
+        *self.depth_mask.write().unwrap() = Some(mask);
+
+        true
+    }
+
+    /// Flatten this chunk and its neighbours' faces for the GPU mesher
+    pub fn padded_voxels(&self, chunks: &DashMap<IVec3, Chunk>, position: &IVec3) -> Box<[u32]> {
+        self.with_refs(chunks, position, padded_voxels)
+    }
+
+    /// Run `f` with references to this chunk's voxels and those of its neighbours
+    pub fn with_refs<R>(
+        &self,
+        chunks: &DashMap<IVec3, Chunk>,
+        position: &IVec3,
+        f: impl FnOnce(&ChunkRefs) -> R,
+    ) -> R {
+        macro_rules! get_chunk {
+            ($chunk_name:ident, $block_name:ident,$pos:expr) => {
+                let $chunk_name = chunks.get($pos);
+                let $chunk_name = if let Some(ref chunk) = $chunk_name {
+                    let voxels = chunk.voxels();
+                    let read = voxels.voxels.read().expect("Failed to read blocks");
+
+                    Some((chunk, voxels, read))
+                } else {
+                    None
+                };
+                let $block_name = if let Some(ref read) = $chunk_name {
+                    let read = &read.2;
+                    read.as_ref()
+                } else {
+                    &crate::binary::common::BLANK_VOXELS
                 };
-            }
-            let chunk_lock = self.voxels.read().unwrap();
-            let blocks_center = chunk_lock.as_ref();
-
-            get_chunk!(
-                chunk_x_neg,
-                blocks_x_neg,
-                &IVec3::new(position.x - 1, position.y, position.z)
-            );
-            get_chunk!(
-                chunk_y_neg,
-                blocks_y_neg,
-                &IVec3::new(position.x, position.y - 1, position.z)
-            );
-            get_chunk!(
-                chunk_z_neg,
-                blocks_z_neg,
-                &IVec3::new(position.x, position.y, position.z - 1)
-            );
-            get_chunk!(
-                chunk_x_pos,
-                blocks_x_pos,
-                &IVec3::new(position.x + 1, position.y, position.z)
-            );
-            get_chunk!(
-                chunk_y_pos,
-                blocks_y_pos,
-                &IVec3::new(position.x, position.y + 1, position.z)
-            );
-            get_chunk!(
-                chunk_z_pos,
-                blocks_z_pos,
-                &IVec3::new(position.x, position.y, position.z + 1)
-            );
-            let x_pos = VoxelRef {
-                voxels: blocks_x_pos,
-                position: IVec3::new(position.x + 1, position.y, position.z),
             };
+        }
+        let chunk_lock = self.voxels.read().unwrap();
+        let blocks_center = chunk_lock.as_ref();
 
-            let y_pos = VoxelRef {
-                voxels: blocks_y_pos,
-                position: IVec3::new(position.x, position.y + 1, position.z),
-            };
+        get_chunk!(
+            chunk_x_neg,
+            blocks_x_neg,
+            &IVec3::new(position.x - 1, position.y, position.z)
+        );
+        get_chunk!(
+            chunk_y_neg,
+            blocks_y_neg,
+            &IVec3::new(position.x, position.y - 1, position.z)
+        );
+        get_chunk!(
+            chunk_z_neg,
+            blocks_z_neg,
+            &IVec3::new(position.x, position.y, position.z - 1)
+        );
+        get_chunk!(
+            chunk_x_pos,
+            blocks_x_pos,
+            &IVec3::new(position.x + 1, position.y, position.z)
+        );
+        get_chunk!(
+            chunk_y_pos,
+            blocks_y_pos,
+            &IVec3::new(position.x, position.y + 1, position.z)
+        );
+        get_chunk!(
+            chunk_z_pos,
+            blocks_z_pos,
+            &IVec3::new(position.x, position.y, position.z + 1)
+        );
+        let x_pos = VoxelRef {
+            voxels: blocks_x_pos,
+            position: IVec3::new(position.x + 1, position.y, position.z),
+        };
 
-            let z_pos = VoxelRef {
-                voxels: blocks_z_pos,
-                position: IVec3::new(position.x, position.y, position.z + 1),
-            };
+        let y_pos = VoxelRef {
+            voxels: blocks_y_pos,
+            position: IVec3::new(position.x, position.y + 1, position.z),
+        };
 
-            let pos = VoxelArrayRef {
-                x: x_pos,
-                y: y_pos,
-                z: z_pos,
-            };
+        let z_pos = VoxelRef {
+            voxels: blocks_z_pos,
+            position: IVec3::new(position.x, position.y, position.z + 1),
+        };
 
-            let x_neg = VoxelRef {
-                voxels: blocks_x_neg,
-                position: IVec3::new(position.x - 1, position.y, position.z),
-            };
+        let pos = VoxelArrayRef {
+            x: x_pos,
+            y: y_pos,
+            z: z_pos,
+        };
 
-            let y_neg = VoxelRef {
-                voxels: blocks_y_neg,
-                position: IVec3::new(position.x, position.y - 1, position.z),
-            };
+        let x_neg = VoxelRef {
+            voxels: blocks_x_neg,
+            position: IVec3::new(position.x - 1, position.y, position.z),
+        };
 
-            let z_neg = VoxelRef {
-                voxels: blocks_z_neg,
-                position: IVec3::new(position.x, position.y, position.z - 1),
-            };
+        let y_neg = VoxelRef {
+            voxels: blocks_y_neg,
+            position: IVec3::new(position.x, position.y - 1, position.z),
+        };
 
-            let neg = VoxelArrayRef {
-                x: x_neg,
-                y: y_neg,
-                z: z_neg,
-            };
+        let z_neg = VoxelRef {
+            voxels: blocks_z_neg,
+            position: IVec3::new(position.x, position.y, position.z - 1),
+        };
 
-            let chunks_center = VoxelRef {
-                voxels: blocks_center,
-                position: *position,
-            };
+        let neg = VoxelArrayRef {
+            x: x_neg,
+            y: y_neg,
+            z: z_neg,
+        };
 
-            let refs = ChunkRefs {
-                chunk: chunks_center,
-                pos,
-                neg,
-            };
+        let chunks_center = VoxelRef {
+            voxels: blocks_center,
+            position: *position,
+        };
 
-            let mask = build_depths(&refs);
+        let refs = ChunkRefs {
+            chunk: chunks_center,
+            pos,
+            neg,
+        };
+
+        f(&refs)
+    }
+}
+
+/// Instanced mesh of a chunk, typed by how its faces are packed
+enum InstanceMesh {
+    Narrow(NInstancedMesh<culled_voxel::Vertex, NarrowFace>),
+    Wide(NInstancedMesh<culled_voxel::Vertex, WideFace>),
+}
+
+impl InstanceMesh {
+    fn new(encoding: FaceEncoding, frustum_cull: bool) -> Self {
+        let vertices = vec![
+            culled_voxel::Vertex::new([0, 0, 0]),
+            culled_voxel::Vertex::new([1, 0, 0]),
+            culled_voxel::Vertex::new([0, 0, 1]),
+            culled_voxel::Vertex::new([1, 0, 1]),
+        ];
 
-            *self.depth_mask.write().unwrap() = Some(mask);
+        let mut mesh = match encoding {
+            FaceEncoding::Narrow => Self::Narrow(
+                NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
+                    .expect("Failed to make chunk NInstancedMesh"),
+            ),
+            FaceEncoding::Wide => Self::Wide(
+                NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
+                    .expect("Failed to make chunk NInstancedMesh"),
+            ),
+        };
+
+        mesh.set_bounds(BoundingHeirarchy::default());
+        mesh.set_frustum_cull(frustum_cull);
+
+        mesh
+    }
+
+    fn set_bounds(&mut self, bounds: BoundingHeirarchy) {
+        match self {
+            Self::Narrow(mesh) => mesh.set_bounds(bounds),
+            Self::Wide(mesh) => mesh.set_bounds(bounds),
+        }
+    }
+
+    fn set_frustum_cull(&mut self, cull: bool) {
+        match self {
+            Self::Narrow(mesh) => mesh.set_frustum_cull(cull),
+            Self::Wide(mesh) => mesh.set_frustum_cull(cull),
+        }
+    }
+
+    fn set_faces(&mut self, faces: &[WideInstanceData]) -> Result<(), BufferError> {
+        match self {
+            Self::Narrow(mesh) => mesh.set_instances(&FaceEncoding::encode_narrow(faces)),
+            Self::Wide(mesh) => mesh.set_instances(&FaceEncoding::encode_wide(faces)),
+        }
+    }
+
+    fn clear_faces(&mut self) {
+        match self {
+            Self::Narrow(mesh) => mesh.clear_instances(),
+            Self::Wide(mesh) => mesh.clear_instances(),
+        }
+    }
+
+    fn render(
+        &mut self,
+        state: &mut renderer::State,
+        uniforms: &culled_voxel::Uniforms,
+        ranges: Option<Vec<(usize, usize)>>,
+    ) {
+        let program = culled_voxel::Program::get();
+
+        if let Some(ranges) = ranges {
+            program.bind();
+            uniforms.bind(&program);
+            state.cameras.bind_camera_uniforms();
+
+            let frustum = state.cameras.game_frustum();
+            match self {
+                Self::Narrow(mesh) => mesh.render_ranges(&frustum, &ranges),
+                Self::Wide(mesh) => mesh.render_ranges(&frustum, &ranges),
+            }
+        } else {
+            match self {
+                Self::Narrow(mesh) => state.draw(mesh, &program, uniforms),
+                Self::Wide(mesh) => state.draw(mesh, &program, uniforms),
+            }
         }
     }
 }
 
 enum RenderData {
     None,
-    Instance(NInstancedMesh<culled_voxel::Vertex, culled_voxel::Instance>),
+    Instance(InstanceMesh),
     VertexPull(
         (
             BlankVao,
@@ -193,56 +305,74 @@ enum RenderData {
             >,
         ),
     ),
+    GpuMesh(GpuMesh),
 }
 
 pub struct Chunk {
     voxels: VoxelData,
     bounds: RwLock<BoundingHeirarchy>,
-    instances: RwLock<Vec<culled_voxel::Instance>>,
+    instances: RwLock<Vec<WideInstanceData>>,
+    encoding: FaceEncoding,
+    dir_ranges: RwLock<DirRanges>,
+    connectivity: RwLock<Connectivity>,
+    dirty_slices: RwLock<DirtySlices>,
+    gpu_voxels: RwLock<Option<Box<[u32]>>>,
     render_data: RwLock<RenderData>,
     greedy: RwLock<bool>,
     needs_update: RwLock<bool>,
     needs_mesh_written: RwLock<bool>,
     frustum_cull: RwLock<bool>,
+    times: RwLock<ChunkTimes>,
+    /// Counts every invalidation, so a mesh can tell which edits it includes
+    edits: RwLock<u64>,
+    /// Edits included in the mesh data
+    meshed_edits: RwLock<u64>,
+    /// Edits included in the mesh on the GPU
+    drawn_edits: RwLock<u64>,
+}
+
+/// The output of meshing a chunk
+pub struct MeshData {
+    pub faces: MeshFaces,
+    pub connectivity: Connectivity,
+    /// The chunk's edit count when meshing started
+    pub edits: u64,
+    pub times: ChunkTimes,
+}
+
+pub enum MeshFaces {
+    /// Instances grouped by direction
+    Instances(Vec<WideInstanceData>, DirRanges),
+    /// Padded voxels to be meshed by the compute shader
+    Gpu(Box<[u32]>),
+    /// Replacement faces for only the given slices
+    Slices(DirtySlices, Vec<WideInstanceData>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RenderType {
     Instance,
     VertexPull,
+    GpuMesh,
     None,
 }
 
 impl Chunk {
-    fn new(
+    pub fn new(
         voxels: Box<[[[BasicVoxel; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>,
         render_type: RenderType,
         greedy: bool,
         frustum_cull: bool,
+        encoding: FaceEncoding,
     ) -> Self {
-        let vertices = vec![
-            culled_voxel::Vertex::new([0, 0, 0]),
-            culled_voxel::Vertex::new([1, 0, 0]),
-            culled_voxel::Vertex::new([0, 0, 1]),
-            culled_voxel::Vertex::new([1, 0, 1]),
-        ];
-
         let render_data = match render_type {
             RenderType::None => RenderData::None,
-            RenderType::Instance => {
-                let mut mesh =
-                    NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
-                        .expect("Failed to make chunk NInstancedMesh");
-                mesh.set_bounds(BoundingHeirarchy::default());
-                if frustum_cull {
-                    mesh.enable_frustum_culling();
-                }
-                RenderData::Instance(mesh)
-            }
+            RenderType::Instance => RenderData::Instance(InstanceMesh::new(encoding, frustum_cull)),
             RenderType::VertexPull => {
                 let vao = BlankVao::new();
                 RenderData::VertexPull((vao, None))
             }
+            RenderType::GpuMesh => RenderData::GpuMesh(GpuMesh::new()),
         };
 
         Self {
@@ -250,10 +380,20 @@ impl Chunk {
             render_data: RwLock::new(render_data),
             bounds: RwLock::new(BoundingHeirarchy::default()),
             instances: RwLock::new(vec![]),
+            encoding,
+            dir_ranges: RwLock::new(DirRanges::default()),
+            // Until meshed, assume the chunk hides nothing behind it
+            connectivity: RwLock::new(Connectivity::OPEN),
+            dirty_slices: RwLock::new(DirtySlices::ALL),
+            gpu_voxels: RwLock::new(None),
             greedy: RwLock::new(greedy),
             needs_update: RwLock::new(true),
             needs_mesh_written: RwLock::new(false),
             frustum_cull: RwLock::new(frustum_cull),
+            times: RwLock::new(ChunkTimes::default()),
+            edits: RwLock::new(0),
+            meshed_edits: RwLock::new(0),
+            drawn_edits: RwLock::new(0),
         }
     }
 
@@ -330,7 +470,7 @@ impl Chunk {
             }
         }
 
-        self.invalidate();
+        self.invalidate_voxel(pos);
     }
 
     pub fn fill(
@@ -338,14 +478,32 @@ impl Chunk {
         render_type: RenderType,
         greedy: bool,
         frustum_cull: bool,
+        encoding: FaceEncoding,
     ) -> Self {
         let voxels =
             Box::new([[[BasicVoxel::new(block_type); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
-        Self::new(voxels, render_type, greedy, frustum_cull)
+        Self::new(voxels, render_type, greedy, frustum_cull, encoding)
     }
 
+    /// Remesh the whole chunk
     pub fn invalidate(&self) {
+        *self.dirty_slices.write().unwrap() = DirtySlices::ALL;
+        *self.needs_update.write().unwrap() = true;
+        *self.edits.write().unwrap() += 1;
+    }
+
+    /// Remesh only the slices an edit at `pos` touches
+    pub fn invalidate_voxel(&self, pos: IVec3) {
+        self.dirty_slices.write().unwrap().mark_voxel(pos);
         *self.needs_update.write().unwrap() = true;
+        *self.edits.write().unwrap() += 1;
+    }
+
+    /// Remesh a single slice, as when a neighbour's edge voxel changes
+    pub fn invalidate_slice(&self, axis: Axis, slice: i32) {
+        self.dirty_slices.write().unwrap().mark(axis, slice);
+        *self.needs_update.write().unwrap() = true;
+        *self.edits.write().unwrap() += 1;
     }
 
     pub fn bounds(&self) -> BoundingHeirarchy {
@@ -365,11 +523,32 @@ impl Chunk {
         }
     }
 
-    pub fn set_vertex_pull(&self, pull: bool) {
+    pub fn set_gpu_mesh(&self, gpu: bool) {
         let mut data = self.render_data.write().unwrap();
         let data = data.deref_mut();
         match data {
             RenderData::None => {}
+            RenderData::Instance(_) | RenderData::VertexPull(_) => {
+                if gpu {
+                    *data = RenderData::GpuMesh(GpuMesh::new());
+                    self.invalidate();
+                }
+            }
+            RenderData::GpuMesh(_) => {
+                if !gpu {
+                    let vao = BlankVao::new();
+                    *data = RenderData::VertexPull((vao, None));
+                    self.invalidate();
+                }
+            }
+        }
+    }
+
+    pub fn set_vertex_pull(&self, pull: bool) {
+        let mut data = self.render_data.write().unwrap();
+        let data = data.deref_mut();
+        match data {
+            RenderData::None | RenderData::GpuMesh(_) => {}
             RenderData::Instance(_) => {
                 if pull {
                     let vao = BlankVao::new();
@@ -379,20 +558,7 @@ impl Chunk {
             }
             RenderData::VertexPull(_) => {
                 if !pull {
-                    let vertices = vec![
-                        culled_voxel::Vertex::new([0, 0, 0]),
-                        culled_voxel::Vertex::new([1, 0, 0]),
-                        culled_voxel::Vertex::new([0, 0, 1]),
-                        culled_voxel::Vertex::new([1, 0, 1]),
-                    ];
-
-                    let mut mesh =
-                        NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
-                            .expect("Failed to make chunk NInstancedMesh");
-                    mesh.set_bounds(BoundingHeirarchy::default());
-                    if *self.frustum_cull.read().unwrap() {
-                        mesh.enable_frustum_culling();
-                    }
+                    let mesh = InstanceMesh::new(self.encoding, *self.frustum_cull.read().unwrap());
                     *data = RenderData::Instance(mesh);
                 }
             }
@@ -404,37 +570,187 @@ impl Chunk {
             return false;
         }
 
-        self.voxels.build_depths(chunks, position);
+        let mesh = self.mesh(position, chunks);
+        self.apply_mesh(mesh);
+
+        *self.needs_update.write().unwrap() = false;
+
+        true
+    }
+
+    /// [`Chunk::update`], loading the mesh from `cache` if it has been meshed before
+    pub fn update_cached(
+        &self,
+        position: &IVec3,
+        chunks: &DashMap<IVec3, Self>,
+        cache: &MeshCache,
+    ) -> bool {
+        if !self.take_update() {
+            return false;
+        }
+
+        let mesh = self.mesh_cached(position, chunks, cache);
+        self.apply_mesh(mesh);
 
-        let raw_faces = make_faces(
+        true
+    }
+
+    pub fn needs_update(&self) -> bool {
+        *self.needs_update.read().unwrap()
+    }
+
+    /// Clear the update flag, returning whether it was set.
+    /// Any edit after this will set it again.
+    pub fn take_update(&self) -> bool {
+        std::mem::replace(&mut *self.needs_update.write().unwrap(), false)
+    }
+
+    /// Build the mesh data for this chunk without touching what is currently drawn,
+    /// so it can be run off the main thread.
+    /// Only the dirty slices are meshed, unless every slice is dirty.
+    pub fn mesh(&self, position: &IVec3, chunks: &DashMap<IVec3, Self>) -> MeshData {
+        // Read before taking the slices, so an edit in between is never counted as meshed
+        let edits = self.edits();
+        let slices = std::mem::take(&mut *self.dirty_slices.write().unwrap());
+        let connectivity = Connectivity::compute(&self.voxels.voxels.read().unwrap());
+
+        // The compute shader makes the faces, which is timed as part of the upload
+        if matches!(*self.render_data.read().unwrap(), RenderData::GpuMesh(_)) {
+            return MeshData {
+                faces: MeshFaces::Gpu(self.voxels.padded_voxels(chunks, position)),
+                connectivity,
+                edits,
+                times: ChunkTimes::default(),
+            };
+        }
+
+        let start = Instant::now();
+        let built = self.voxels.build_depths(chunks, position);
+        let build_depths = built.then(|| start.elapsed().as_millis_f64());
+
+        let start = Instant::now();
+        let mut raw_faces = make_slice_faces(
             chunks,
             position,
             self.voxels.depth_mask.read().unwrap().as_ref().unwrap(),
             *self.greedy.read().expect("Failed to read greedy"),
+            slices,
         );
 
-        let mut instances = self.instances.write().unwrap();
-        instances.clear();
-
-        for face in raw_faces.iter() {
-            let data = InstanceData::new(
-                face.x,
-                face.y,
-                face.z,
-                face.dir,
-                face.width,
-                face.height,
-                face.block_type,
-            )
-            .rotate_on_dir();
-
-            instances.push(culled_voxel::Instance { data: data.into() });
+        let dir_ranges = bucket_by_dir(&mut raw_faces, |face| usize::from(face.dir));
+
+        let instances: Vec<_> = raw_faces
+            .iter()
+            .map(|face| {
+                WideInstanceData::new(
+                    face.x,
+                    face.y,
+                    face.z,
+                    face.dir,
+                    face.width,
+                    face.height,
+                    face.block_type,
+                )
+                .rotate_on_dir()
+            })
+            .collect();
+
+        let faces = if slices.is_all() {
+            MeshFaces::Instances(instances, dir_ranges)
+        } else {
+            MeshFaces::Slices(slices, instances)
+        };
+
+        MeshData {
+            faces,
+            connectivity,
+            edits,
+            times: ChunkTimes {
+                build_depths,
+                faces: Some(start.elapsed().as_millis_f64()),
+                upload: None,
+            },
         }
+    }
 
-        *self.needs_update.write().unwrap() = false;
-        *self.needs_mesh_written.write().unwrap() = true;
+    /// [`Chunk::mesh`], but full CPU meshes are loaded from or stored in `cache`
+    pub fn mesh_cached(
+        &self,
+        position: &IVec3,
+        chunks: &DashMap<IVec3, Self>,
+        cache: &MeshCache,
+    ) -> MeshData {
+        let gpu = matches!(*self.render_data.read().unwrap(), RenderData::GpuMesh(_));
+        if gpu || !self.dirty_slices.read().unwrap().is_all() {
+            return self.mesh(position, chunks);
+        }
 
-        true
+        let edits = self.edits();
+        let greedy = *self.greedy.read().expect("Failed to read greedy");
+        let key = self
+            .voxels
+            .with_refs(chunks, position, |refs| mesh_key(refs, greedy));
+
+        if let Some(mut mesh) = cache.load(key) {
+            *self.dirty_slices.write().unwrap() = DirtySlices::default();
+            mesh.edits = edits;
+            return mesh;
+        }
+
+        let mesh = self.mesh(position, chunks);
+
+        if let Err(e) = cache.store(key, &mesh) {
+            eprintln!("Error writing mesh cache: {:?}", e);
+        }
+
+        mesh
+    }
+
+    /// Use faces made outside of the chunk's own mesher, as for the naive baseline
+    pub fn set_faces(&self, mut instances: Vec<WideInstanceData>) {
+        let dir_ranges = bucket_by_dir(&mut instances, |i| usize::from(i.dir()));
+        let connectivity = Connectivity::compute(&self.voxels.voxels.read().unwrap());
+        let edits = self.edits();
+
+        self.take_update();
+        *self.dirty_slices.write().unwrap() = DirtySlices::default();
+
+        self.apply_mesh(MeshData {
+            faces: MeshFaces::Instances(instances, dir_ranges),
+            connectivity,
+            edits,
+            times: ChunkTimes::default(),
+        });
+    }
+
+    /// Replace the chunk's mesh data, the next [`Chunk::write_mesh`] will upload it
+    pub fn apply_mesh(&self, mesh: MeshData) {
+        *self.connectivity.write().unwrap() = mesh.connectivity;
+        *self.meshed_edits.write().unwrap() = mesh.edits;
+        *self.times.write().unwrap() = mesh.times;
+
+        match mesh.faces {
+            MeshFaces::Instances(instances, dir_ranges) => {
+                *self.instances.write().unwrap() = instances;
+                *self.dir_ranges.write().unwrap() = dir_ranges;
+            }
+            MeshFaces::Slices(slices, faces) => {
+                let mut instances = self.instances.write().unwrap();
+
+                instances.retain(|i| !slices.contains_face(*i));
+                instances.extend(faces);
+
+                *self.dir_ranges.write().unwrap() =
+                    bucket_by_dir(instances.as_mut_slice(), |i| usize::from(i.dir()));
+            }
+            MeshFaces::Gpu(voxels) => {
+                *self.gpu_voxels.write().unwrap() = Some(voxels);
+                self.instances.write().unwrap().clear();
+                *self.dir_ranges.write().unwrap() = DirRanges::default();
+            }
+        }
+
+        *self.needs_mesh_written.write().unwrap() = true;
     }
 
     pub fn write_mesh(&self) -> bool {
@@ -442,21 +758,55 @@ impl Chunk {
             return false;
         }
 
+        let start = Instant::now();
+
+        if let RenderData::GpuMesh(mesh) = self.render_data.write().unwrap().deref_mut() {
+            let Some(voxels) = self.gpu_voxels.write().unwrap().take() else {
+                return false;
+            };
+
+            if let Err(e) = mesh.mesh(voxels) {
+                eprintln!("Error: {:?}", e);
+                return false;
+            }
+
+            *self.needs_mesh_written.write().unwrap() = false;
+            self.mesh_written(start.elapsed().as_millis_f64());
+
+            return true;
+        }
+
+        // An edit can leave the chunk with no faces, so the old ones must stop being drawn
         if self.instances.read().unwrap().is_empty() {
-            return false;
+            match self.render_data.write().unwrap().deref_mut() {
+                RenderData::None => {}
+                RenderData::Instance(mesh) => mesh.clear_faces(),
+                RenderData::GpuMesh(_) => unreachable!("GPU meshes are written above"),
+                RenderData::VertexPull((_, buffer)) => *buffer = None,
+            }
+
+            *self.needs_mesh_written.write().unwrap() = false;
+
+            // Nothing left to draw is still the edit drawn
+            if !matches!(*self.render_data.read().unwrap(), RenderData::None) {
+                self.mesh_written(start.elapsed().as_millis_f64());
+            }
+
+            return true;
         }
 
         match self.render_data.write().unwrap().deref_mut() {
             RenderData::None => {}
             RenderData::Instance(mesh) => {
-                if let Err(e) = mesh.set_instances(self.instances.read().unwrap().as_slice()) {
+                if let Err(e) = mesh.set_faces(self.instances.read().unwrap().as_slice()) {
                     eprintln!("Error: {:?}", e);
                     return false;
                 }
             }
+            RenderData::GpuMesh(_) => unreachable!("GPU meshes are written above"),
             RenderData::VertexPull((_, buffer)) => {
                 let instances = self.instances.read().unwrap();
-                let faces = instances.iter().map(|i| i.data).collect::<Vec<_>>();
+                let faces = self.encoding.encode(&instances);
 
                 let face_data = FaceData { face_data: faces };
 
@@ -477,18 +827,59 @@ impl Chunk {
 
         *self.needs_mesh_written.write().unwrap() = false;
 
+        // Combined draws upload the faces themselves, and call this when they have
+        if !matches!(*self.render_data.read().unwrap(), RenderData::None) {
+            self.mesh_written(start.elapsed().as_millis_f64());
+        }
+
         true
     }
 
+    /// Record that the mesh data is on the GPU, taking `upload` milliseconds to get there
+    pub fn mesh_written(&self, upload: f64) {
+        self.times.write().unwrap().upload = Some(upload);
+        *self.drawn_edits.write().unwrap() = *self.meshed_edits.read().unwrap();
+    }
+
+    pub fn times(&self) -> ChunkTimes {
+        *self.times.read().unwrap()
+    }
+
+    /// Edits made to the chunk so far
+    pub fn edits(&self) -> u64 {
+        *self.edits.read().unwrap()
+    }
+
+    /// Edits included in the mesh that is drawn
+    pub fn drawn_edits(&self) -> u64 {
+        *self.drawn_edits.read().unwrap()
+    }
+
     pub fn voxels(&self) -> &VoxelData {
         &self.voxels
     }
 
-    pub fn instances(&self) -> &RwLock<Vec<culled_voxel::Instance>> {
+    pub fn instances(&self) -> &RwLock<Vec<WideInstanceData>> {
         &self.instances
     }
 
-    pub fn render(&self, ipos: &IVec3, state: &mut renderer::State) {
+    pub fn encoding(&self) -> FaceEncoding {
+        self.encoding
+    }
+
+    pub fn dir_ranges(&self) -> DirRanges {
+        *self.dir_ranges.read().unwrap()
+    }
+
+    pub fn connectivity(&self) -> Connectivity {
+        *self.connectivity.read().unwrap()
+    }
+
+    /// Draw the chunk, only drawing the directions set in `visible` if given.
+    /// GPU meshes aren't grouped by direction so always draw every face.
+    pub fn render(&self, ipos: &IVec3, visible: Option<[bool; 6]>, state: &mut renderer::State) {
+        let ranges = visible.map(|visible| visible_ranges(&self.dir_ranges(), visible));
+
         match self.render_data.write().as_mut().unwrap().deref_mut() {
             RenderData::None => {
                 panic!("No render data");
@@ -498,9 +889,7 @@ impl Chunk {
                     chunk_position: ipos.to_array(),
                 };
 
-                let program = culled_voxel::Program::get();
-
-                state.draw(mesh, &program, &uniforms)
+                mesh.render(state, &uniforms, ranges);
             }
             RenderData::VertexPull((vao, buffer)) => {
                 if buffer.is_none() {
@@ -517,19 +906,40 @@ impl Chunk {
 
                 vao.bind();
 
-                let uniforms = culled_voxel::Uniforms {
+                let uniforms = culled_voxel_vertex_pull::Uniforms {
                     chunk_position: ipos.to_array(),
+                    face_words: self.encoding.words() as u32,
                 };
                 uniforms.bind(&program);
 
-                unsafe {
-                    gl::DrawArrays(
-                        DrawMode::Triangles.into(),
-                        0,
-                        (self.instances.read().unwrap().len() * 6) as i32,
-                    );
+                let ranges =
+                    ranges.unwrap_or_else(|| vec![(0, self.instances.read().unwrap().len())]);
+
+                for (first, count) in ranges {
+                    unsafe {
+                        gl::DrawArrays(
+                            DrawMode::Triangles.into(),
+                            (first * 6) as i32,
+                            (count * 6) as i32,
+                        );
+                    }
                 }
             }
+            RenderData::GpuMesh(mesh) => {
+                let program = culled_voxel_vertex_pull::Program::get();
+                program.bind();
+
+                state.cameras.bind_camera_uniforms();
+
+                // The compute shader always writes narrow faces
+                let uniforms = culled_voxel_vertex_pull::Uniforms {
+                    chunk_position: ipos.to_array(),
+                    face_words: FaceEncoding::Narrow.words() as u32,
+                };
+                uniforms.bind(&program);
+
+                mesh.draw();
+            }
         }
     }
 }
@@ -0,0 +1,404 @@
+use glam::Vec3;
+use renderer::{
+    ComputeProgram, SSBO,
+    buffers::{ReadBack, ShaderBuffer},
+    camera::frustum::Frustum,
+    hiz::DepthPyramid,
+    indirect::{self, DrawArraysIndirectCommand},
+};
+
+use super::slots::FaceSlots;
+use super::voxel::combined_chunk_data::buffers::ChunkData;
+use crate::binary::common::CHUNK_SIZE;
+use gpu_cull::{
+    buffers::{CullChunks, CullCommands, CullParams, FrustumPlanes},
+    structs::{CullChunk, DrawCommand},
+};
+
+const GROUP_SIZE: u32 = 64;
+/// Each axis hides at most one of its directions,
+/// so the visible directions of a chunk split into at most 3 draws
+const MAX_DRAWS_PER_CHUNK: usize = 3;
+
+/// Frustum and occlusion culls the combined draws in a compute shader, writing the
+/// surviving draw commands and a draw count for `MultiDrawArraysIndirectCount`.
+/// Where the driver lacks it every command slot is drawn, with the unused ones cleared to empty.
+pub struct GpuCull {
+    chunks: ShaderBuffer<CullChunks>,
+    planes: ShaderBuffer<FrustumPlanes>,
+    params: ShaderBuffer<CullParams>,
+    commands: ShaderBuffer<CullCommands>,
+    chunk_count: usize,
+    /// Occluded counts copied out of the params, read once the GPU is done with them
+    occluded_read_backs: ReadBack<()>,
+    occluded: usize,
+}
+
+impl GpuCull {
+    pub fn new() -> Self {
+        let mut chunks = ShaderBuffer::single(&CullChunks { chunks: vec![] })
+            .expect("Failed to make cull chunk buffer");
+        chunks.set_label("GPU cull chunk buffer");
+
+        let mut planes = ShaderBuffer::single(&FrustumPlanes { planes: vec![] })
+            .expect("Failed to make cull plane buffer");
+        planes.set_label("GPU cull plane buffer");
+
+        let mut params = ShaderBuffer::single(&CullParams {
+            draw_count: 0,
+            vertex_pull: 0,
+            plane_count: 0,
+            chunk_count: 0,
+            hiz: 0,
+            occluded: 0,
+            dir_cull: 0,
+            padding: 0,
+            camera_position: [0.0; 4],
+        })
+        .expect("Failed to make cull params buffer");
+        params.set_label("GPU cull params buffer");
+
+        let mut commands = ShaderBuffer::single(&CullCommands { commands: vec![] })
+            .expect("Failed to make cull command buffer");
+        commands.set_label("GPU cull command buffer");
+
+        Self {
+            chunks,
+            planes,
+            params,
+            commands,
+            chunk_count: 0,
+            occluded_read_backs: ReadBack::new(),
+            occluded: 0,
+        }
+    }
+
+    /// Upload where each chunk's faces are in the combined buffer.
+    /// Only needs calling when a chunk's slot changes.
+    pub fn set_chunks(&mut self, faces: &FaceSlots, chunk_data: &mut ShaderBuffer<ChunkData>) {
+        let chunks = faces
+            .slots()
+            .iter()
+            .map(|(pos, slot)| {
+                let position = *pos * CHUNK_SIZE as i32;
+                let counts = slot.dirs.map(|(_, count)| count as i32);
+                CullChunk {
+                    position: position.extend(0).to_array(),
+                    range: [slot.first() as i32, slot.count as i32, 0, 0],
+                    dirs_low: [counts[0], counts[1], counts[2], counts[3]],
+                    dirs_high: [counts[4], counts[5], 0, 0],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.chunk_count = chunks.len();
+        let max_draws = self.chunk_count * MAX_DRAWS_PER_CHUNK;
+
+        if let Err(e) = self.chunks.set_single(&CullChunks { chunks }, 0) {
+            eprintln!("Error setting cull chunks: {:?}", e);
+        }
+
+        let commands = CullCommands {
+            commands: (0..max_draws)
+                .map(|_| DrawCommand {
+                    vertex_count: 0,
+                    instance_count: 0,
+                    first: 0,
+                    base_instance: 0,
+                })
+                .collect(),
+        };
+        if let Err(e) = self.commands.set_single(&commands, 0) {
+            eprintln!("Error sizing cull commands: {:?}", e);
+        }
+
+        // The shader writes the positions, the buffer just has to be big enough
+        let positions = ChunkData {
+            chunk_positions: vec![[0; 3]; max_draws],
+        };
+        if let Err(e) = chunk_data.set_single(&positions, 0) {
+            eprintln!("Error sizing chunk data: {:?}", e);
+        }
+    }
+
+    /// Cull against `frustum` and `hiz`, or just compact the commands if both are `None`.
+    /// If `camera` is set, directions that face away from it are left out of the draws.
+    /// Returns how many chunks a cull a frame or two ago occluded, so the count never stalls.
+    pub fn cull(
+        &mut self,
+        frustum: Option<&Frustum>,
+        hiz: Option<&DepthPyramid>,
+        camera: Option<Vec3>,
+        vertex_pull: bool,
+    ) -> usize {
+        renderer::profiler::event!("GPU cull");
+
+        if let Some(((), occluded)) = self.occluded_read_backs.poll::<u32>() {
+            self.occluded = occluded[0] as usize;
+        }
+
+        let planes = frustum
+            .map(|f| f.iter().map(|p| p.to_vec4().to_array()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let params = CullParams {
+            draw_count: 0,
+            vertex_pull: vertex_pull as u32,
+            plane_count: planes.len() as u32,
+            chunk_count: self.chunk_count as u32,
+            hiz: hiz.is_some() as u32,
+            occluded: 0,
+            dir_cull: camera.is_some() as u32,
+            padding: 0,
+            camera_position: camera.unwrap_or_default().extend(0.0).to_array(),
+        };
+
+        if let Err(e) = self.params.set_single(&params, 0) {
+            eprintln!("Error setting cull params: {:?}", e);
+        }
+
+        if !planes.is_empty()
+            && let Err(e) = self.planes.set_single(&FrustumPlanes { planes }, 0)
+        {
+            eprintln!("Error setting cull planes: {:?}", e);
+        }
+
+        if self.chunk_count == 0 {
+            self.occluded_read_backs.discard();
+            self.occluded = 0;
+            return 0;
+        }
+
+        self.chunks.bind();
+        self.planes.bind();
+        self.params.bind();
+        self.commands.bind();
+
+        if let Some(hiz) = hiz {
+            hiz.bind();
+        }
+
+        if !indirect::has_indirect_count() {
+            // Slots past the draw count would otherwise keep last frame's commands
+            unsafe {
+                gl::ClearNamedBufferData(
+                    self.commands.id(),
+                    gl::R32UI,
+                    gl::RED_INTEGER,
+                    gl::UNSIGNED_INT,
+                    std::ptr::null(),
+                );
+            }
+        }
+
+        let program = gpu_cull::cull_chunks::get();
+        program.dispatch_with_barrier(
+            (self.chunk_count as u32).div_ceil(GROUP_SIZE),
+            1,
+            1,
+            gl::SHADER_STORAGE_BARRIER_BIT | gl::COMMAND_BARRIER_BIT,
+        );
+
+        // occluded is the sixth uint of CullParams in the shader's layout
+        self.occluded_read_backs.copy(
+            self.params.id(),
+            5 * std::mem::size_of::<u32>(),
+            std::mem::size_of::<u32>(),
+            (),
+        );
+
+        self.occluded
+    }
+
+    /// Draw the culled commands, the combined program and buffers must already be bound
+    pub fn draw(&self, draw_mode: renderer::DrawMode) {
+        renderer::profiler::event!("GPU cull multidraw");
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.commands.id());
+            gl::BindBuffer(indirect::PARAMETER_BUFFER, self.params.id());
+            indirect::multi_draw_arrays_indirect_count(
+                draw_mode.into(),
+                0,
+                self.chunk_count * MAX_DRAWS_PER_CHUNK,
+                std::mem::size_of::<DrawArraysIndirectCommand>(),
+            );
+            gl::BindBuffer(indirect::PARAMETER_BUFFER, 0);
+        }
+    }
+}
+
+impl Default for GpuCull {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+renderer::compute!(gpu_cull, {
+    #snippet crate::binary::culled::voxel::combined_chunk_data
+    #snippet renderer::hiz::hiz_depths
+    #snippet renderer::hiz::hiz_test
+
+    struct CullChunk {
+        ivec4 position;
+        // x: first face, y: face count
+        ivec4 range;
+        // Face count of each direction, in direction order
+        ivec4 dirs_low;
+        ivec4 dirs_high;
+    }
+
+    // Laid out as a DrawArraysIndirectCommand
+    struct DrawCommand {
+        uint vertex_count;
+        uint instance_count;
+        uint first;
+        uint base_instance;
+    }
+
+    #bind 5
+    buffer CullCommands {
+        DrawCommand commands[];
+    };
+
+    #bind 6
+    buffer CullChunks {
+        CullChunk chunks[];
+    };
+
+    #bind 7
+    buffer FrustumPlanes {
+        vec4 planes[];
+    };
+
+    // draw_count must stay first, it is read as the indirect parameter buffer
+    #bind 8
+    buffer CullParams {
+        uint draw_count;
+        uint vertex_pull;
+        uint plane_count;
+        uint chunk_count;
+        uint hiz;
+        uint occluded;
+        uint dir_cull;
+        uint padding;
+        vec4 camera_position;
+    };
+
+    bool in_frustum(vec3 center, vec3 extents) {
+        for (uint i = 0u; i < plane_count; i++) {
+            vec4 plane = planes[i];
+
+            float r = dot(extents, abs(plane.xyz));
+            float s = dot(plane.xyz, center) - plane.w;
+
+            if (s < -r) {
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    uint dir_count(CullChunk chunk, uint dir) {
+        if (dir < 4u) {
+            return uint(chunk.dirs_low[dir]);
+        }
+
+        return uint(chunk.dirs_high[dir - 4u]);
+    }
+
+    // Even directions sit on the low side of a block, odd ones on the high side
+    bool dir_visible(vec3 min_pos, vec3 max_pos, uint dir) {
+        if (dir_cull == 0u) {
+            return true;
+        }
+
+        uint axis = dir / 2u;
+
+        if ((dir % 2u) == 0u) {
+            return (camera_position[axis] < max_pos[axis]);
+        }
+
+        return (camera_position[axis] > min_pos[axis]);
+    }
+
+    void emit_draw(CullChunk chunk, uint first, uint count) {
+        uint slot = atomicAdd(draw_count, 1u);
+
+        chunk_positions[slot] = chunk.position.xyz;
+
+        DrawCommand command;
+
+        if (vertex_pull != 0u) {
+            command.vertex_count = count * 6u;
+            command.instance_count = 1u;
+            command.first = first * 6u;
+            command.base_instance = 0u;
+        } else {
+            command.vertex_count = 4u;
+            command.instance_count = count;
+            command.first = 0u;
+            command.base_instance = first;
+        }
+
+        commands[slot] = command;
+    }
+
+    #kernel cull_chunks
+    #size 64 1 1
+    void cull_chunks() {
+        uint index = gl_GlobalInvocationID.x;
+
+        if (index >= chunk_count) {
+            return;
+        }
+
+        CullChunk chunk = chunks[index];
+
+        if (chunk.range.y == 0) {
+            return;
+        }
+
+        vec3 extents = vec3(15.0, 15.0, 15.0);
+        vec3 center = vec3(chunk.position.xyz) + extents;
+
+        if (!in_frustum(center, extents)) {
+            return;
+        }
+
+        if (hiz != 0u) {
+            if (!hiz_visible(center - extents, center + extents)) {
+                atomicAdd(occluded, 1u);
+                return;
+            }
+        }
+
+        vec3 min_pos = center - extents;
+        vec3 max_pos = center + extents;
+
+        // Merge neighbouring visible directions into as few draws as possible
+        uint offset = uint(chunk.range.x);
+        uint run_first = offset;
+        uint run_count = 0u;
+
+        for (uint dir = 0u; dir < 6u; dir++) {
+            uint count = dir_count(chunk, dir);
+
+            if (dir_visible(min_pos, max_pos, dir)) {
+                if (run_count == 0u) {
+                    run_first = offset;
+                }
+                run_count += count;
+            } else if (run_count != 0u) {
+                emit_draw(chunk, run_first, run_count);
+                run_count = 0u;
+            }
+
+            offset += count;
+        }
+
+        if (run_count != 0u) {
+            emit_draw(chunk, run_first, run_count);
+        }
+    }
+});
@@ -0,0 +1,110 @@
+use glam::Vec3;
+
+/// `(first, count)` of each direction's faces, indexed by [`renderer::Dir`].
+/// The ranges are contiguous and in direction order.
+pub type DirRanges = [(usize, usize); 6];
+
+/// Sort faces into direction order and work out where each direction's faces are
+pub fn bucket_by_dir<T>(faces: &mut [T], dir: impl Fn(&T) -> usize) -> DirRanges {
+    faces.sort_by_key(&dir);
+
+    let mut ranges = [(0, 0); 6];
+    let mut first = 0;
+    for (d, range) in ranges.iter_mut().enumerate() {
+        let count = faces[first..].iter().take_while(|f| dir(*f) == d).count();
+        *range = (first, count);
+        first += count;
+    }
+
+    ranges
+}
+
+/// Which directions of faces in the box `min` to `max` can face `camera`.
+/// Even directions sit on the low side of a block, odd ones on the high side.
+pub fn visible_dirs(camera: Vec3, min: Vec3, max: Vec3) -> [bool; 6] {
+    let mut visible = [false; 6];
+
+    for axis in 0..3 {
+        visible[axis * 2] = camera[axis] < max[axis];
+        visible[axis * 2 + 1] = camera[axis] > min[axis];
+    }
+
+    visible
+}
+
+/// Merge the visible directions into as few `(first, count)` ranges as possible
+pub fn visible_ranges(ranges: &DirRanges, visible: [bool; 6]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = vec![];
+
+    for ((first, count), _) in ranges
+        .iter()
+        .zip(visible)
+        .filter(|(range, visible)| *visible && range.1 > 0)
+    {
+        match runs.last_mut() {
+            Some(run) if run.0 + run.1 == *first => run.1 += count,
+            _ => runs.push((*first, *count)),
+        }
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_by_dir_sorts_and_counts() {
+        let mut faces = vec![5, 0, 2, 2, 5, 0, 2];
+        let ranges = bucket_by_dir(&mut faces, |f| *f);
+
+        assert_eq!(faces, vec![0, 0, 2, 2, 2, 5, 5]);
+        assert_eq!(ranges, [(0, 2), (2, 0), (2, 3), (5, 0), (5, 0), (5, 2)]);
+    }
+
+    #[test]
+    fn bucket_by_dir_without_faces() {
+        let ranges = bucket_by_dir(&mut Vec::<usize>::new(), |f| *f);
+
+        assert_eq!(ranges, [(0, 0); 6]);
+    }
+
+    #[test]
+    fn visible_dirs_from_inside_and_outside() {
+        let (min, max) = (Vec3::ZERO, Vec3::splat(32.0));
+
+        assert_eq!(visible_dirs(Vec3::splat(16.0), min, max), [true; 6]);
+        assert_eq!(
+            visible_dirs(Vec3::new(-10.0, 16.0, 40.0), min, max),
+            [true, false, true, true, false, true]
+        );
+    }
+
+    #[test]
+    fn visible_ranges_merges_neighbours() {
+        let ranges = [(0, 2), (2, 3), (5, 1), (6, 4), (10, 2), (12, 1)];
+
+        assert_eq!(visible_ranges(&ranges, [true; 6]), vec![(0, 13)]);
+        assert_eq!(
+            visible_ranges(&ranges, [true, true, false, true, true, false]),
+            vec![(0, 5), (6, 6)]
+        );
+        assert_eq!(visible_ranges(&ranges, [false; 6]), vec![]);
+    }
+
+    #[test]
+    fn visible_ranges_joins_across_empty_dirs() {
+        // Hiding an empty direction doesn't leave a gap between its neighbours
+        let ranges = [(0, 2), (2, 0), (2, 3), (5, 0), (5, 0), (5, 2)];
+
+        assert_eq!(
+            visible_ranges(&ranges, [true, false, true, false, false, true]),
+            vec![(0, 7)]
+        );
+        assert_eq!(
+            visible_ranges(&ranges, [false, true, false, true, true, false]),
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Instant;
+
+use dashmap::DashMap;
+use glam::IVec3;
+
+use common::{BlockType, join_global_pos, seperate_global_pos};
+
+use super::Chunk;
+use crate::binary::common::CHUNK_SIZE;
+
+/// Same edits every run, so techniques remesh exactly the same blocks
+const SEED: u64 = 0x9e3779b97f4a7c15;
+
+/// An edit waiting for its chunk's new mesh to be drawn
+struct PendingEdit {
+    chunk: IVec3,
+    /// The chunk's edit count once this edit was made
+    edits: u64,
+    time: Instant,
+}
+
+/// Toggles random blocks every frame and tracks how long each takes to be drawn
+pub struct EditStress {
+    per_frame: u32,
+    rng: u64,
+    /// Sorted, so the edits don't depend on the map's order
+    chunks: Vec<IVec3>,
+    pending: Vec<PendingEdit>,
+}
+
+impl EditStress {
+    pub fn new(per_frame: u32) -> Self {
+        Self {
+            per_frame,
+            rng: SEED,
+            chunks: vec![],
+            pending: vec![],
+        }
+    }
+
+    pub fn per_frame(&self) -> u32 {
+        self.per_frame
+    }
+
+    /// Xorshift64*
+    fn next_u64(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Global positions of this frame's edits, with the block each is toggled to
+    pub fn next_edits(&mut self, chunks: &DashMap<IVec3, Chunk>) -> Vec<(IVec3, BlockType)> {
+        if self.chunks.len() != chunks.len() {
+            self.chunks = chunks.iter().map(|e| *e.key()).collect();
+            self.chunks.sort_by_key(|p| p.to_array());
+        }
+
+        if self.chunks.is_empty() {
+            return vec![];
+        }
+
+        (0..self.per_frame)
+            .filter_map(|_| {
+                let index = self.next_u64() as usize % self.chunks.len();
+                let chunk_pos = self.chunks[index];
+                let local = IVec3::new(
+                    (self.next_u64() % CHUNK_SIZE as u64) as i32,
+                    (self.next_u64() % CHUNK_SIZE as u64) as i32,
+                    (self.next_u64() % CHUNK_SIZE as u64) as i32,
+                );
+
+                // Negative chunks are shifted along by one, so the edit can land in a neighbour.
+                // Check the voxel the edit will actually change.
+                let global = join_global_pos(&chunk_pos, &local);
+                let (chunk_pos, local) = seperate_global_pos(&global);
+
+                let chunk = chunks.get(&chunk_pos)?;
+                let block = if chunk
+                    .get(local.x as usize, local.y as usize, local.z as usize)
+                    .is_solid()
+                {
+                    BlockType::Air
+                } else {
+                    BlockType::Stone
+                };
+
+                Some((global, block))
+            })
+            .collect()
+    }
+
+    /// Wait for the edit just made to the chunk at `chunk` to be drawn
+    pub fn track(&mut self, chunk: IVec3, chunks: &DashMap<IVec3, Chunk>, time: Instant) {
+        if let Some(c) = chunks.get(&chunk) {
+            self.pending.push(PendingEdit {
+                chunk,
+                edits: c.edits(),
+                time,
+            });
+        }
+    }
+
+    /// Hand the edits whose new mesh is now on the GPU to `state`, to time once it is presented
+    pub fn drawn(&mut self, chunks: &DashMap<IVec3, Chunk>, state: &mut renderer::State) {
+        self.pending.retain(|edit| {
+            let Some(chunk) = chunks.get(&edit.chunk) else {
+                return false;
+            };
+
+            let drawn = chunk.drawn_edits() >= edit.edits;
+            if drawn {
+                state.stats.drawn_edits.push(edit.time);
+            }
+
+            !drawn
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use common::{InstanceData, WideInstanceData};
+use renderer::vertex::{
+    Vertex,
+    format::{VertexAtrib, VertexFormat},
+};
+
+/// How faces are packed when they are uploaded.
+/// Chunks always keep their faces as [`WideInstanceData`], and narrow them on upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaceEncoding {
+    /// One word per face, as [`InstanceData`]
+    #[default]
+    Narrow,
+    /// Two words per face, as [`WideInstanceData`]
+    Wide,
+}
+
+impl FaceEncoding {
+    pub fn new(wide: bool) -> Self {
+        if wide { Self::Wide } else { Self::Narrow }
+    }
+
+    /// Words each face takes up
+    pub fn words(&self) -> usize {
+        match self {
+            Self::Narrow => 1,
+            Self::Wide => 2,
+        }
+    }
+
+    /// Bytes each face takes up
+    pub fn stride(&self) -> usize {
+        self.words() * std::mem::size_of::<u32>()
+    }
+
+    /// Layout of the face instance attribute, the shaders read it as a `uvec2`
+    pub fn bindings(&self) -> VertexFormat {
+        match self {
+            Self::Narrow => NarrowFace::bindings(),
+            Self::Wide => WideFace::bindings(),
+        }
+    }
+
+    /// Pack faces into words to upload.
+    /// Narrow faces whose block type doesn't fit are drawn as invalid blocks.
+    pub fn encode(&self, faces: &[WideInstanceData]) -> Vec<u32> {
+        match self {
+            Self::Narrow => {
+                let lost = faces
+                    .iter()
+                    .filter(|face| InstanceData::try_from(**face).is_err())
+                    .count();
+
+                if lost > 0 {
+                    eprintln!(
+                        "{} faces have block types too large for the narrow encoding",
+                        lost
+                    );
+                }
+
+                faces.iter().map(|face| face.narrow().into()).collect()
+            }
+            Self::Wide => faces.iter().flat_map(|face| face.words()).collect(),
+        }
+    }
+
+    /// Faces as the narrow instanced meshes upload them
+    pub fn encode_narrow(faces: &[WideInstanceData]) -> Vec<NarrowFace> {
+        faces
+            .iter()
+            .map(|face| NarrowFace(face.narrow().into()))
+            .collect()
+    }
+
+    /// Faces as the wide instanced meshes upload them
+    pub fn encode_wide(faces: &[WideInstanceData]) -> Vec<WideFace> {
+        faces.iter().map(|face| WideFace(face.words())).collect()
+    }
+}
+
+/// Faces are the first instance attribute, after the vertex position
+const FACE_LOCATION: u32 = 1;
+
+/// A face in the one word encoding.
+/// The shaders read a `uvec2`, and the missing second word reads as 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NarrowFace(pub u32);
+
+impl Vertex for NarrowFace {
+    fn bindings() -> VertexFormat {
+        &[VertexAtrib {
+            location: FACE_LOCATION,
+            is_int: true,
+            elements: 1,
+            ty: gl::UNSIGNED_INT,
+            offset: 0,
+        }]
+    }
+}
+
+/// A face in the two word encoding
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WideFace(pub [u32; 2]);
+
+impl Vertex for WideFace {
+    fn bindings() -> VertexFormat {
+        &[VertexAtrib {
+            location: FACE_LOCATION,
+            is_int: true,
+            elements: 2,
+            ty: gl::UNSIGNED_INT,
+            offset: 0,
+        }]
+    }
+}
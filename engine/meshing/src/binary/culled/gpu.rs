@@ -0,0 +1,185 @@
+use renderer::{
+    ComputeProgram, DrawMode, SSBO,
+    buffers::{BlankVao, BufferError, ShaderBuffer},
+};
+
+use super::voxel::vertex_pull_face_data::buffers::FaceData;
+use gpu_mesh::buffers::{ChunkVoxels, MeshCommand};
+
+/// Number of work groups needed to cover a chunk with the kernel's 8x8x8 local size
+const GROUPS: u32 = 4;
+
+/// A chunk meshed by a compute shader.
+/// The faces never leave the GPU, the draw count is read from `command` by an indirect draw.
+pub struct GpuMesh {
+    vao: BlankVao,
+    voxels: Option<ShaderBuffer<ChunkVoxels>>,
+    faces: Option<ShaderBuffer<FaceData>>,
+    command: ShaderBuffer<MeshCommand>,
+}
+
+impl GpuMesh {
+    pub fn new() -> Self {
+        let mut command =
+            ShaderBuffer::single(&empty_command()).expect("Failed to make mesh command buffer");
+        command.set_label("Chunk GPU mesh command buffer");
+
+        Self {
+            vao: BlankVao::new(),
+            voxels: None,
+            faces: None,
+            command,
+        }
+    }
+
+    /// Upload padded voxels (see [`crate::binary::common::padded_voxels`]) and
+    /// generate culled faces for them.
+    pub fn mesh(&mut self, voxels: Box<[u32]>) -> Result<(), BufferError> {
+        renderer::profiler::event!("GPU mesh chunk");
+
+        // Every face sits between a solid and an air block,
+        // and each block can only touch 6 others
+        let solid = voxels.iter().filter(|v| **v != 0).count();
+        let air = voxels.len() - solid;
+        let capacity = 6 * solid.min(air);
+
+        self.command.set_single(&empty_command(), 0)?;
+
+        if capacity == 0 {
+            return Ok(());
+        }
+
+        let chunk_voxels = ChunkVoxels {
+            voxels: voxels.into_vec(),
+        };
+
+        if let Some(buffer) = &mut self.voxels {
+            buffer.set_single(&chunk_voxels, 0)?;
+        } else {
+            let mut buffer = ShaderBuffer::single(&chunk_voxels)?;
+            buffer.set_label("Chunk GPU mesh voxel buffer");
+            self.voxels = Some(buffer);
+        }
+
+        let face_size = capacity * std::mem::size_of::<u32>();
+        if self.faces.as_ref().is_none_or(|f| f.size() < face_size) {
+            let mut buffer = ShaderBuffer::empty(face_size)?;
+            buffer.set_label("Chunk GPU mesh face buffer");
+            self.faces = Some(buffer);
+        }
+
+        self.voxels.as_ref().unwrap().bind();
+        self.faces.as_ref().unwrap().bind();
+        self.command.bind();
+
+        let program = gpu_mesh::mesh_chunk::get();
+        program.dispatch_with_barrier(
+            GROUPS,
+            GROUPS,
+            GROUPS,
+            gl::SHADER_STORAGE_BARRIER_BIT | gl::COMMAND_BARRIER_BIT,
+        );
+
+        Ok(())
+    }
+
+    /// Draw the generated faces, the vertex pull program must already be bound
+    pub fn draw(&self) {
+        let Some(faces) = &self.faces else {
+            return;
+        };
+
+        self.vao.bind();
+        faces.bind();
+
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.command.id());
+            gl::DrawArraysIndirect(DrawMode::Triangles.into(), std::ptr::null());
+        }
+    }
+}
+
+impl Default for GpuMesh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn empty_command() -> MeshCommand {
+    MeshCommand {
+        vertex_count: 0,
+        instance_count: 1,
+        first: 0,
+        base_instance: 0,
+    }
+}
+
+renderer::compute!(gpu_mesh, {
+    #snippet crate::binary::culled::voxel::vertex_pull_face_data
+
+    #bind 3
+    buffer ChunkVoxels {
+        uint voxels[];
+    };
+
+    // Laid out as a DrawArraysIndirectCommand
+    #bind 4
+    buffer MeshCommand {
+        uint vertex_count;
+        uint instance_count;
+        uint first;
+        uint base_instance;
+    };
+
+    uint padded_voxel(ivec3 p) {
+        return voxels[(p.x * 32 + p.y) * 32 + p.z];
+    }
+
+    void emit_face(uvec3 pos, uint dir, uint block_type) {
+        // Counts vertices so the command can be drawn directly
+        uint index = atomicAdd(vertex_count, 6u) / 6u;
+
+        if (index < uint(face_data.length())) {
+            face_data[index] = (pos.x << 10u) | (pos.y << 5u) | pos.z | (dir << 15u) | (block_type << 28u);
+        }
+    }
+
+    #kernel mesh_chunk
+    #size 8 8 8
+    void mesh_chunk() {
+        uvec3 pos = gl_GlobalInvocationID;
+
+        if (pos.x >= 30u || pos.y >= 30u || pos.z >= 30u) {
+            return;
+        }
+
+        // Add one to compensate for padding
+        ivec3 p = ivec3(pos) + ivec3(1, 1, 1);
+
+        uint block_type = padded_voxel(p);
+
+        if (block_type == 0u) {
+            return;
+        }
+
+        // Even directions face down their axis, odd directions face up it
+        if (padded_voxel(p - ivec3(1, 0, 0)) == 0u) {
+            emit_face(pos, 0u, block_type);
+        }
+        if (padded_voxel(p + ivec3(1, 0, 0)) == 0u) {
+            emit_face(pos, 1u, block_type);
+        }
+        if (padded_voxel(p - ivec3(0, 1, 0)) == 0u) {
+            emit_face(pos, 2u, block_type);
+        }
+        if (padded_voxel(p + ivec3(0, 1, 0)) == 0u) {
+            emit_face(pos, 3u, block_type);
+        }
+        if (padded_voxel(p - ivec3(0, 0, 1)) == 0u) {
+            emit_face(pos, 4u, block_type);
+        }
+        if (padded_voxel(p + ivec3(0, 0, 1)) == 0u) {
+            emit_face(pos, 5u, block_type);
+        }
+    }
+});
@@ -0,0 +1,150 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender, channel},
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use glam::IVec3;
+
+use super::{Chunk, chunk::MeshData, chunk_bounds};
+
+type HashSet<T> = hashbrown::HashSet<T>;
+
+/// Time per frame that can be spent uploading finished meshes
+const APPLY_BUDGET: Duration = Duration::from_millis(2);
+
+struct MeshResult {
+    position: IVec3,
+    mesh: Option<MeshData>,
+}
+
+/// Meshes dirty chunks on the rayon pool.
+/// Chunks keep drawing their old mesh until the new one has been applied on the main thread.
+pub struct MeshJobs {
+    dirty: HashSet<IVec3>,
+    in_flight: HashSet<IVec3>,
+    ready: VecDeque<MeshResult>,
+    sender: Sender<MeshResult>,
+    receiver: Receiver<MeshResult>,
+    budget: Duration,
+}
+
+impl MeshJobs {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+
+        Self {
+            dirty: HashSet::new(),
+            in_flight: HashSet::new(),
+            ready: VecDeque::new(),
+            sender,
+            receiver,
+            budget: APPLY_BUDGET,
+        }
+    }
+
+    pub fn mark_dirty(&mut self, position: IVec3) {
+        self.dirty.insert(position);
+    }
+
+    /// Mark every chunk that has been invalidated as dirty
+    pub fn mark_invalidated(&mut self, chunks: &DashMap<IVec3, Chunk>) {
+        for e in chunks.iter() {
+            if e.value().needs_update() {
+                self.dirty.insert(*e.key());
+            }
+        }
+    }
+
+    /// Start a job for every dirty chunk that isn't already being meshed.
+    /// Chunks that are in flight stay dirty, so results always arrive in order.
+    pub fn schedule(&mut self, chunks: &Arc<DashMap<IVec3, Chunk>>) {
+        renderer::profiler::event!("Schedule mesh jobs");
+
+        let in_flight = &self.in_flight;
+        let ready = self
+            .dirty
+            .extract_if(|position| !in_flight.contains(position))
+            .collect::<Vec<_>>();
+
+        for position in ready {
+            match chunks.get(&position) {
+                Some(chunk) if chunk.take_update() => {}
+                _ => continue,
+            }
+
+            self.in_flight.insert(position);
+
+            let chunks = chunks.clone();
+            let sender = self.sender.clone();
+
+            rayon::spawn(move || {
+                renderer::profiler::event!("Mesh job");
+
+                let mesh = chunks
+                    .get(&position)
+                    .map(|chunk| chunk.mesh(&position, &chunks));
+
+                // The receiver is only gone if the jobs were dropped
+                let _ = sender.send(MeshResult { position, mesh });
+            });
+        }
+    }
+
+    /// Upload finished meshes until the frame's budget runs out.
+    /// Returns the positions of the chunks that were updated.
+    pub fn apply(&mut self, chunks: &DashMap<IVec3, Chunk>) -> Vec<IVec3> {
+        renderer::profiler::event!("Apply mesh jobs");
+
+        while let Ok(result) = self.receiver.try_recv() {
+            self.in_flight.remove(&result.position);
+            self.ready.push_back(result);
+        }
+
+        let start = Instant::now();
+        let mut applied = vec![];
+
+        while start.elapsed() < self.budget {
+            let Some(MeshResult { position, mesh }) = self.ready.pop_front() else {
+                break;
+            };
+
+            let (Some(chunk), Some(mesh)) = (chunks.get(&position), mesh) else {
+                continue;
+            };
+
+            chunk.apply_mesh(mesh);
+            chunk.update_bounds(chunk_bounds(&position));
+            chunk.write_mesh();
+
+            applied.push(position);
+        }
+
+        applied
+    }
+
+    /// Stop tracking all jobs, invalidating any chunks that
+    /// had been taken so they are remeshed by whatever comes next.
+    pub fn cancel(self, chunks: &DashMap<IVec3, Chunk>) {
+        let pending = self
+            .in_flight
+            .iter()
+            .chain(self.ready.iter().map(|r| &r.position));
+
+        for position in pending {
+            if let Some(chunk) = chunks.get(position) {
+                chunk.invalidate();
+            }
+        }
+    }
+}
+
+impl Default for MeshJobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,19 +1,30 @@
+use std::{rc::Rc, sync::Arc, time::Instant};
+
+use cache::MeshCache;
 use chunk::RenderType;
+use cull::GpuCull;
+use dirs::{visible_dirs, visible_ranges};
+use edits::EditStress;
+use faces::FaceEncoding;
+use jobs::MeshJobs;
 use rayon::prelude::*;
 
 pub use chunk::Chunk;
 use dashmap::DashMap;
-use glam::{IVec3, Mat4, ivec3, vec3};
+use glam::{IVec3, Vec3, ivec3, vec3};
 use rayon::iter::IntoParallelRefIterator;
 use renderer::{
-    DrawMode, ProgramSource, Renderable, SSBO, State,
+    Axis, DrawMode, LayoutBlock, MeshingStats, ProgramSource, RenderPass, Renderable, SSBO, State,
+    Uniforms,
     bounds::{BoundingHeirarchy, BoundingVolume},
-    buffers::{BlankVao, Buffer, BufferMode, GpuBuffer, ShaderBuffer},
+    buffers::{BlankVao, Buffer, BufferMode, GpuBuffer, ShaderBuffer, Vao, Vbo},
     camera::frustum::Frustum,
     draw::line::Line,
+    hiz::DepthPyramid,
     indirect::DrawArraysIndirectCommand,
-    mesh::{Mesh, ninstanced::NInstancedMesh},
 };
+use slots::FaceSlots;
+use visibility::{ChunkQueries, visible_chunks};
 use voxel::{
     combined_chunk_data::buffers::ChunkData,
     culled_voxel_combined::{self},
@@ -23,14 +34,42 @@ use voxel::{
 
 use common::{
     Args, BlockType, seperate_global_pos,
-    tests::{Test, test_scene},
+    tests::{SceneGenerator, Test, report_peak_memory, test_scene},
 };
 
 use super::common::CHUNK_SIZE;
 
+mod cache;
 mod chunk;
+mod cull;
+mod dirs;
+mod edits;
+mod faces;
+mod gpu;
+mod jobs;
+mod naive;
+mod slots;
+mod visibility;
 mod voxel;
 
+/// Whether seperate chunks are meshed in the compute shader,
+/// which only writes narrow faces and can't mesh from the naive scene
+fn gpu_mesh(args: &Args) -> bool {
+    args.gpu_mesh && args.test != Test::NaiveCulled && !args.wide_faces
+}
+
+fn render_type(args: &Args) -> RenderType {
+    if args.combine {
+        RenderType::None
+    } else if gpu_mesh(args) {
+        RenderType::GpuMesh
+    } else if args.vertex_pull {
+        RenderType::VertexPull
+    } else {
+        RenderType::Instance
+    }
+}
+
 pub fn chunk_data(data: &DashMap<IVec3, BlockType>, args: &Args, chunks: &DashMap<IVec3, Chunk>) {
     data.into_iter().for_each(|e| {
         let (pos, block) = e.pair();
@@ -38,52 +77,148 @@ pub fn chunk_data(data: &DashMap<IVec3, BlockType>, args: &Args, chunks: &DashMa
 
         let chunk = chunks.entry(chunk_pos).or_insert(Chunk::fill(
             BlockType::Air,
-            if args.combine {
-                RenderType::None
-            } else if args.vertex_pull {
-                RenderType::VertexPull
-            } else {
-                RenderType::Instance
-            },
+            render_type(args),
             args.test == Test::Greedy,
             args.frustum_cull,
+            FaceEncoding::new(args.wide_faces),
         ));
 
         chunk.set(in_chunk_pos, *block, chunks, &chunk_pos, false);
     });
 }
 
-pub fn mesh_chunks(chunks: &DashMap<IVec3, Chunk>) {
+/// Generate the scene straight into chunks, without going through a map of every block.
+/// The voxels are generated in parallel, then the chunks are made here as they own GL objects.
+pub fn generate_chunks(args: &Args, chunks: &DashMap<IVec3, Chunk>) {
+    let start = Instant::now();
+
+    let generated = SceneGenerator::new(args).generate_chunks();
+
+    println!(
+        "Generated {} chunks in {:.2}ms",
+        generated.len(),
+        start.elapsed().as_millis_f64()
+    );
+
+    for (position, voxels) in generated {
+        let chunk = Chunk::new(
+            voxels,
+            render_type(args),
+            args.test == Test::Greedy,
+            args.frustum_cull,
+            FaceEncoding::new(args.wide_faces),
+        );
+
+        chunks.insert(position, chunk);
+    }
+
+    report_peak_memory();
+}
+
+/// World space corners of a chunk
+pub fn chunk_min_max(position: &IVec3) -> (Vec3, Vec3) {
+    let pos =
+        vec3(position[0] as f32, position[1] as f32, position[2] as f32) * (CHUNK_SIZE as f32);
+    let end_pos = pos + (CHUNK_SIZE as f32);
+
+    (pos, end_pos)
+}
+
+pub fn chunk_bounds(position: &IVec3) -> BoundingHeirarchy {
+    let (pos, end_pos) = chunk_min_max(position);
+
+    BoundingHeirarchy::from_min_max(pos, end_pos)
+}
+
+/// Squared distance from `camera` to the centre of a chunk, for sorting draws front to back
+fn chunk_distance(position: &IVec3, camera: Vec3) -> f32 {
+    let (min, max) = chunk_min_max(position);
+
+    ((min + max) / 2.0).distance_squared(camera)
+}
+
+pub fn mesh_chunks(chunks: &DashMap<IVec3, Chunk>, cache: Option<&MeshCache>) {
     chunks.par_iter().for_each(|e| {
         let position = e.key();
         let chunk = e.value();
-        let pos =
-            vec3(position[0] as f32, position[1] as f32, position[2] as f32) * (CHUNK_SIZE as f32);
-        let end_pos = pos + (CHUNK_SIZE as f32);
 
-        chunk.update(position, chunks);
-        chunk.update_bounds(BoundingHeirarchy::from_min_max(pos, end_pos));
+        match cache {
+            Some(cache) => chunk.update_cached(position, chunks, cache),
+            None => chunk.update(position, chunks),
+        };
+        chunk.update_bounds(chunk_bounds(position));
     });
 }
 
 pub fn setup(args: &Args, _state: &State) -> ChunkManager {
-    let mut manager = ChunkManager::new(args.combine, args.frustum_cull, args.vertex_pull);
+    let mut manager = ChunkManager::new(args);
+
+    if args.gpu_cull && !args.combine {
+        eprintln!("GPU culling is only supported for combined draws");
+    }
+
+    if args.query_cull && args.combine {
+        eprintln!("Occlusion queries are only supported for seperate draws");
+    }
 
-    let data = test_scene(args);
+    if args.sort_chunks && args.combine && args.gpu_cull {
+        eprintln!("GPU culled draws are written in shader order, so can't be sorted");
+    }
 
-    chunk_data(&data, args, &manager.chunks);
+    if args.cave_cull && args.combine && args.gpu_cull {
+        eprintln!("Cave culling runs on the CPU, so isn't applied to GPU culled draws");
+    }
 
-    setup_chunks(&mut manager);
+    if args.gpu_mesh {
+        if args.combine {
+            eprintln!("GPU meshing is only supported for seperate draws, meshing on the CPU");
+        } else if args.wide_faces {
+            eprintln!("GPU meshing only writes narrow faces, meshing on the CPU");
+        } else if args.test == Test::Greedy {
+            eprintln!("GPU meshing only generates culled faces");
+        }
+
+        if args.dir_cull && !args.combine {
+            eprintln!("GPU meshes aren't grouped by direction, drawing every face");
+        }
+    }
+
+    // The naive baseline meshes from the scene, so has to be done here
+    let naive = args.test == Test::NaiveCulled;
+
+    if naive && (args.gpu_mesh || args.async_mesh) {
+        eprintln!("Naive culling always meshes on the CPU at startup");
+    }
+
+    let data = if naive {
+        let data = test_scene(args);
+        chunk_data(&data, args, &manager.chunks);
+        Some(data)
+    } else {
+        generate_chunks(args, &manager.chunks);
+        None
+    };
+
+    // Let the jobs fill the chunks in over the next frames instead of blocking here
+    match &mut manager.jobs {
+        Some(jobs) if !naive => jobs.mark_invalidated(&manager.chunks),
+        _ => setup_chunks(&mut manager, data.as_ref()),
+    }
 
     manager
 }
 
-fn setup_chunks(manager: &mut ChunkManager) {
-    let mut instance_data: Vec<culled_voxel_combined::Instance> = vec![];
+/// Mesh every chunk, from the `naive` scene with neighbour lookups if given
+fn setup_chunks(manager: &mut ChunkManager, naive: Option<&DashMap<IVec3, BlockType>>) {
+    let start = Instant::now();
 
-    manager.combined.pos_order.clear();
+    match naive {
+        Some(data) => naive::mesh_chunks(data, &manager.chunks),
+        None => mesh_chunks(&manager.chunks, manager.mesh_cache.as_ref()),
+    }
 
-    mesh_chunks(&manager.chunks);
+    let mesh_time = start.elapsed().as_millis_f64();
+    let start = Instant::now();
 
     // Need another loop as we can't flush the buffer from another thread since the OpenGL context
     // is current on the main thread
@@ -91,113 +226,210 @@ fn setup_chunks(manager: &mut ChunkManager) {
         e.value().write_mesh();
     });
 
-    for e in manager.chunks.iter() {
-        let position = e.key();
-        let chunk = e.value();
+    upload_combined(manager);
 
-        let order = &mut manager.combined.pos_order;
+    // Wait for any compute work so the timing covers the whole remesh
+    unsafe {
+        gl::Finish();
+    }
+    let upload_time = start.elapsed().as_millis_f64();
 
-        let instances = chunk.instances().read().unwrap();
+    println!(
+        "Meshed {} chunks in {:.2}ms, uploaded in {:.2}ms",
+        manager.chunks.len(),
+        mesh_time,
+        upload_time
+    );
 
-        instance_data.extend(
-            instances
-                .iter()
-                .map(|i| culled_voxel_combined::Instance { data: i.data }),
-        );
-        order.push((*position, instances.len()));
+    manager.scene_mesh = Some(mesh_time);
+    manager.scene_upload = Some(upload_time);
+}
+
+/// Lay every chunk's faces out in the combined buffer again
+fn upload_combined(manager: &mut ChunkManager) {
+    let chunks = manager.chunks.iter().map(|e| {
+        let chunk = e.value();
+        let faces = chunk_faces(chunk);
+
+        (*e.key(), faces, chunk.dir_ranges())
+    });
+
+    if let Err(e) = manager.combined.faces.rebuild(chunks) {
+        eprintln!("Error setting combined faces: {:?}", e);
     }
 
-    match &mut manager.combined.render_data {
-        RenderData::Instance(mesh) => {
-            if let Err(e) = mesh.set_instances(&instance_data) {
-                eprintln!("Error setting instances: {:?}", e);
-            }
-        }
-        RenderData::VertexPull(_, buffer) => {
-            let instance_data = FaceData {
-                face_data: instance_data
-                    .into_iter()
-                    .map(|i| i.data)
-                    .collect::<Vec<_>>(),
-            };
+    manager.combined.update_cull();
+}
 
-            if let Some(buffer) = buffer {
-                if let Err(e) = buffer.set_single(&instance_data, 0) {
-                    eprintln!("Error setting vertex pull data: {:?}", e);
-                }
-            } else {
-                let buf = ShaderBuffer::single(&instance_data)
-                    .expect("Failed to make shader buffer for vertex pull data");
-                *buffer = Some(buf);
-                println!("Setup vertex pull buffer");
-            }
+/// Rewrite only the slots of the chunks at `positions`
+fn upload_slots(manager: &mut ChunkManager, positions: &[IVec3]) {
+    for position in positions {
+        let Some(chunk) = manager.chunks.get(position) else {
+            continue;
+        };
+
+        let start = Instant::now();
+        let faces = chunk_faces(&chunk);
+
+        if let Err(e) = manager
+            .combined
+            .faces
+            .write(*position, &faces, chunk.dir_ranges())
+        {
+            eprintln!("Error setting chunk faces: {:?}", e);
+            continue;
         }
+
+        chunk.mesh_written(start.elapsed().as_millis_f64());
     }
+
+    manager.combined.update_cull();
+}
+
+fn chunk_faces(chunk: &Chunk) -> Vec<u32> {
+    chunk.encoding().encode(&chunk.instances().read().unwrap())
 }
 
 pub struct ChunkManager {
-    chunks: DashMap<IVec3, chunk::Chunk>,
+    chunks: Arc<DashMap<IVec3, chunk::Chunk>>,
+    jobs: Option<MeshJobs>,
+    hiz: Option<DepthPyramid>,
     combined: CombinedData,
     combine: bool,
     frustum_cull: bool,
+    dir_cull: bool,
+    cave_cull: bool,
+    queries: Option<ChunkQueries>,
+    sort_chunks: bool,
+    prepass_draws: Vec<ChunkDraw>,
+    mesh_cache: Option<MeshCache>,
+    edits: Option<EditStress>,
+    /// Wall times of meshing and uploading every chunk at startup
+    scene_mesh: Option<f64>,
+    scene_upload: Option<f64>,
+}
+
+/// A chunk the depth prepass drew, so the colour pass can draw it again without culling
+struct ChunkDraw {
+    position: IVec3,
+    visible: Option<[bool; 6]>,
+    queried: bool,
 }
 
+/// How the combined face buffer is read, both share the same face data
 enum RenderData {
-    Instance(NInstancedMesh<culled_voxel_combined::Vertex, culled_voxel_combined::Instance>),
-    VertexPull(
-        BlankVao,
-        Option<
-            ShaderBuffer<
-                culled_voxel_vertex_pull_combined::uses::vertex_pull_face_data::buffers::FaceData,
-            >,
-        >,
-    ),
+    Instance {
+        vao: Vao<culled_voxel_combined::Vertex, culled_voxel_combined::Instance>,
+        /// Never read, but the VAO's vertices live in it so it has to be kept alive
+        _vbo: Vbo<culled_voxel_combined::Vertex>,
+    },
+    VertexPull(BlankVao),
+}
+
+impl RenderData {
+    fn new(vertex_pull: bool, encoding: FaceEncoding) -> Self {
+        if vertex_pull {
+            return RenderData::VertexPull(BlankVao::new());
+        }
+
+        let vertices = vec![
+            culled_voxel_combined::Vertex::new([0, 0, 0]),
+            culled_voxel_combined::Vertex::new([1, 0, 0]),
+            culled_voxel_combined::Vertex::new([0, 0, 1]),
+            culled_voxel_combined::Vertex::new([1, 0, 1]),
+        ];
+
+        let vbo = Vbo::with_data(&vertices, BufferMode::Immutable)
+            .expect("Failed to create greedy ChunkManager vertices");
+
+        let vao = Vao::new(DrawMode::TriangleStrip);
+        vao.setup_vertices(&vbo);
+        // The face buffer is attached when binding, as growing it changes its id
+        vao.setup_vbo(0, encoding.stride(), 1, true, encoding.bindings());
+
+        RenderData::Instance { vao, _vbo: vbo }
+    }
 }
 
 pub struct CombinedData {
-    pub pos_order: Vec<(IVec3, usize)>,
+    faces: FaceSlots,
     chunk_data_buffer:
         ShaderBuffer<culled_voxel_combined::uses::combined_chunk_data::buffers::ChunkData>,
     indirect_buffer: GpuBuffer,
     render_data: RenderData,
+    gpu_cull: Option<GpuCull>,
+    /// Commands written to the indirect buffer by the last CPU cull
+    draw_count: i32,
 }
 
 impl CombinedData {
-    pub fn bind(&self) {
-        match &self.render_data {
-            RenderData::Instance(mesh) => mesh.bind(),
-            RenderData::VertexPull(vao, buffer) => {
-                if let Some(buffer) = buffer {
-                    vao.bind();
-                    buffer.bind();
+    pub fn bind(&mut self) {
+        match &mut self.render_data {
+            RenderData::Instance { vao, .. } => {
+                vao.set_instance_vbo_strided(self.faces.id(), self.faces.encoding().stride());
+                vao.bind();
+            }
+            RenderData::VertexPull(vao) => {
+                vao.bind();
+                unsafe {
+                    gl::BindBufferBase(
+                        gl::SHADER_STORAGE_BUFFER,
+                        FaceData::bind_point(),
+                        self.faces.id(),
+                    );
                 }
             }
         }
     }
 
     pub fn is_vertex_pull(&self) -> bool {
-        matches!(self.render_data, RenderData::VertexPull(_, _))
+        matches!(self.render_data, RenderData::VertexPull(_))
+    }
+
+    fn program(&self) -> Rc<renderer::Program> {
+        if self.is_vertex_pull() {
+            culled_voxel_vertex_pull_combined::Program::get()
+        } else {
+            culled_voxel_combined::Program::get()
+        }
+    }
+
+    /// Bind the program, telling vertex pulling how many words each face takes
+    fn bind_program(&self) -> Rc<renderer::Program> {
+        let program = self.program();
+        program.bind();
+
+        if self.is_vertex_pull() {
+            let uniforms = culled_voxel_vertex_pull_combined::Uniforms {
+                face_words: self.faces.encoding().words() as u32,
+            };
+            uniforms.bind(&program);
+        }
+
+        program
+    }
+
+    fn draw_mode(&self) -> DrawMode {
+        if self.is_vertex_pull() {
+            DrawMode::Triangles
+        } else {
+            DrawMode::TriangleStrip
+        }
+    }
+
+    /// Send the chunk layout to the GPU cull, if there is one
+    fn update_cull(&mut self) {
+        if let Some(cull) = &mut self.gpu_cull {
+            cull.set_chunks(&self.faces, &mut self.chunk_data_buffer);
+        }
     }
 }
 
 impl ChunkManager {
-    pub fn new(combine: bool, frustum_cull: bool, vertex_pull: bool) -> Self {
-        let vertices = vec![
-            culled_voxel_combined::Vertex::new([0, 0, 0]),
-            culled_voxel_combined::Vertex::new([1, 0, 0]),
-            culled_voxel_combined::Vertex::new([0, 0, 1]),
-            culled_voxel_combined::Vertex::new([1, 0, 1]),
-        ];
-
+    pub fn new(args: &Args) -> Self {
         let combined = {
-            let render_data = if !vertex_pull {
-                RenderData::Instance(
-                    NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
-                        .expect("Failed to create greedy ChunkManager mesh"),
-                )
-            } else {
-                RenderData::VertexPull(BlankVao::new(), None)
-            };
+            let encoding = FaceEncoding::new(args.wide_faces);
+            let render_data = RenderData::new(args.vertex_pull, encoding);
             let chunk_data_buffer =
                 ShaderBuffer::new(&[]).expect("Failed to make shader buffer for chunk positions");
             let indirect_buffer = GpuBuffer::empty(
@@ -208,19 +440,41 @@ impl ChunkManager {
 
             CombinedData {
                 render_data,
-                pos_order: vec![],
+                faces: FaceSlots::new(encoding),
                 chunk_data_buffer,
                 indirect_buffer,
+                gpu_cull: args.gpu_cull.then(GpuCull::new),
+                draw_count: 0,
             }
         };
 
+        let mesh_cache = if args.mesh_cache {
+            MeshCache::open()
+                .inspect_err(|e| eprintln!("Error opening mesh cache: {:?}", e))
+                .ok()
+        } else {
+            None
+        };
+
         Self {
-            chunks: DashMap::new(),
+            chunks: Arc::new(DashMap::new()),
+            jobs: args.async_mesh.then(MeshJobs::new),
+            hiz: args.hiz_cull.then(DepthPyramid::new),
             combined,
-            frustum_cull,
-            combine,
+            frustum_cull: args.frustum_cull,
+            dir_cull: args.dir_cull,
+            cave_cull: args.cave_cull,
+            queries: args.query_cull.then(ChunkQueries::default),
+            sort_chunks: args.sort_chunks,
+            prepass_draws: vec![],
+            mesh_cache,
+            combine: args.combine,
+            edits: (args.edits > 0).then(|| EditStress::new(args.edits)),
+            scene_mesh: None,
+            scene_upload: None,
         }
     }
+
     pub fn get_block_at(&self, pos: &IVec3) -> BlockType {
         let (chunk_pos, in_chunk_pos) = seperate_global_pos(pos);
 
@@ -236,158 +490,454 @@ impl ChunkManager {
             in_chunk_pos.z as usize,
         )
     }
+
+    /// Set a block, remeshing its chunk and any neighbours that share the edited face
+    pub fn set_block(&mut self, pos: &IVec3, block_type: BlockType) {
+        let (chunk_pos, in_chunk_pos) = seperate_global_pos(pos);
+
+        let Some(chunk) = self.chunks.get(&chunk_pos) else {
+            return;
+        };
+
+        chunk.set(in_chunk_pos, block_type, &self.chunks, &chunk_pos, true);
+        drop(chunk);
+
+        let mut dirty = vec![chunk_pos];
+        for axis in 0..3 {
+            let offset = match in_chunk_pos[axis] {
+                0 => -1,
+                v if v == CHUNK_SIZE as i32 - 1 => 1,
+                _ => continue,
+            };
+
+            let mut neighbour = chunk_pos;
+            neighbour[axis] += offset;
+
+            if let Some(chunk) = self.chunks.get(&neighbour) {
+                // Only the neighbour's slice against the edit can change
+                let slice = if offset < 0 { CHUNK_SIZE as i32 - 1 } else { 0 };
+                chunk.invalidate_slice(Axis::all()[axis], slice);
+                dirty.push(neighbour);
+            }
+        }
+
+        if let Some(jobs) = &mut self.jobs {
+            for position in dirty {
+                jobs.mark_dirty(position);
+            }
+        }
+    }
+
+    /// Make this frame's random edits, timing each until its chunk's new mesh is drawn
+    fn stress_edits(&mut self, state: &mut renderer::State) {
+        let Some(mut stress) = self.edits.take() else {
+            return;
+        };
+
+        for (pos, block) in stress.next_edits(&self.chunks) {
+            let time = Instant::now();
+            self.set_block(&pos, block);
+
+            let (chunk_pos, _) = seperate_global_pos(&pos);
+            stress.track(chunk_pos, &self.chunks, time);
+            state.stats.edits += 1;
+        }
+
+        self.edits = Some(stress);
+    }
 }
 
 impl Renderable for ChunkManager {
     fn render(&mut self, state: &mut renderer::State) {
         renderer::profiler::event!("Greedy Render");
+
+        // The depth prepass already updated and culled everything, so just draw it again
+        if state.pass() == RenderPass::Color {
+            if self.combine {
+                redraw_combined(self);
+            } else {
+                redraw_seperate(self, state);
+            }
+            return;
+        }
+
+        self.stress_edits(state);
+
         if self.combine {
             render_combined(self, state);
         } else {
             render_seperate(self, state);
         }
+
+        if let Some(stress) = &mut self.edits {
+            stress.drawn(&self.chunks, state);
+        }
+
+        capture_depth(self, state);
     }
 
     fn args(&mut self, args: &Args) {
         self.frustum_cull = args.frustum_cull;
+        self.dir_cull = args.dir_cull;
+        self.cave_cull = args.cave_cull;
+        self.sort_chunks = args.sort_chunks;
+
+        if args.edits != self.edits.as_ref().map_or(0, EditStress::per_frame) {
+            self.edits = (args.edits > 0).then(|| EditStress::new(args.edits));
+        }
+
         for e in self.chunks.iter() {
             e.value().set_frustum_culling(args.frustum_cull);
+            e.value().set_gpu_mesh(gpu_mesh(args));
             e.value().set_vertex_pull(args.vertex_pull);
         }
 
+        if args.async_mesh != self.jobs.is_some() {
+            if let Some(jobs) = self.jobs.take() {
+                jobs.cancel(&self.chunks);
+            } else {
+                self.jobs = Some(MeshJobs::new());
+            }
+        }
+
+        if let Some(jobs) = &mut self.jobs {
+            jobs.mark_invalidated(&self.chunks);
+        }
+
         self.combine = args.combine;
 
-        if self.combine {
-            match self.combined.render_data {
-                RenderData::Instance(_) => {
-                    if args.vertex_pull {
-                        self.combined.render_data = RenderData::VertexPull(BlankVao::new(), None);
-                        setup_chunks(self);
-                    }
-                }
-                RenderData::VertexPull(_, _) => {
-                    if !args.vertex_pull {
-                        let vertices = vec![
-                            culled_voxel_combined::Vertex::new([0, 0, 0]),
-                            culled_voxel_combined::Vertex::new([1, 0, 0]),
-                            culled_voxel_combined::Vertex::new([0, 0, 1]),
-                            culled_voxel_combined::Vertex::new([1, 0, 1]),
-                        ];
-                        self.combined.render_data = RenderData::Instance(
-                            NInstancedMesh::with_vertices(&vertices, None, DrawMode::TriangleStrip)
-                                .expect("Failed to create greedy ChunkManager mesh"),
-                        );
-                        setup_chunks(self);
-                    }
-                }
-            }
+        if args.hiz_cull != self.hiz.is_some() {
+            self.hiz = args.hiz_cull.then(DepthPyramid::new);
+        }
+
+        if args.query_cull != self.queries.is_some() {
+            self.queries = args.query_cull.then(ChunkQueries::default);
+        }
+
+        if args.gpu_cull != self.combined.gpu_cull.is_some() {
+            self.combined.gpu_cull = args.gpu_cull.then(GpuCull::new);
+            self.combined.update_cull();
+        }
+
+        // The face data is the same either way, so only how it is read changes
+        if self.combine && args.vertex_pull != self.combined.is_vertex_pull() {
+            let encoding = self.combined.faces.encoding();
+            self.combined.render_data = RenderData::new(args.vertex_pull, encoding);
         }
     }
+
+    /// Startup times, with each chunk's stages from its most recent mesh
+    fn meshing_stats(&self) -> Option<MeshingStats> {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|e| e.value().times())
+            .collect::<Vec<_>>();
+
+        Some(MeshingStats::new(
+            self.scene_mesh,
+            self.scene_upload,
+            &chunks,
+        ))
+    }
 }
 
 fn render_seperate(manager: &mut ChunkManager, state: &mut renderer::State) {
-    manager.chunks.par_iter().for_each(|e| {
-        let chunk = e.value();
-        chunk.update(e.key(), &manager.chunks);
-    });
+    if let Some(jobs) = &mut manager.jobs {
+        jobs.schedule(&manager.chunks);
+        jobs.apply(&manager.chunks);
+    } else {
+        manager.chunks.par_iter().for_each(|e| {
+            let chunk = e.value();
+            chunk.update(e.key(), &manager.chunks);
+        });
+    }
+
+    let camera = state.cameras.game().transform().position;
+    let reachable = manager
+        .cave_cull
+        .then(|| visible_chunks(&manager.chunks, camera, &state.cameras.game_frustum()));
+
+    let record = state.pass() == RenderPass::Depth;
+    manager.prepass_draws.clear();
+
+    if let Some(queries) = &mut manager.queries {
+        queries.next_frame();
+    }
+
+    let mut entries = manager.chunks.iter().collect::<Vec<_>>();
+    if manager.sort_chunks {
+        entries.sort_by(|a, b| {
+            chunk_distance(a.key(), camera).total_cmp(&chunk_distance(b.key(), camera))
+        });
+    }
 
-    for e in manager.chunks.iter() {
+    for e in entries {
         let pos = e.key();
         let chunk = e.value();
         let ipos = ivec3(pos[0], pos[1], pos[2]) * CHUNK_SIZE as i32;
+        let (min, max) = chunk_min_max(pos);
 
         chunk.write_mesh();
 
-        chunk.render(&ipos, state);
+        if reachable.as_ref().is_some_and(|r| !r.contains(pos)) {
+            continue;
+        }
+
+        if let Some(hiz) = &manager.hiz
+            && !hiz.is_visible(min, max)
+        {
+            state.stats.occlusion_culled += 1;
+            continue;
+        }
+
+        let visible = manager.dir_cull.then(|| visible_dirs(camera, min, max));
+
+        if let Some(queries) = &mut manager.queries {
+            // Queries test against the depth buffer of the camera being drawn from
+            let camera = state.cameras.active().transform().position;
+            state.cameras.bind_camera_uniforms();
+
+            let queried = queries.draw(*pos, min, max, camera, || {
+                chunk.render(&ipos, visible, state)
+            });
+
+            if record {
+                manager.prepass_draws.push(ChunkDraw {
+                    position: *pos,
+                    visible,
+                    queried,
+                });
+            }
+            continue;
+        }
+
+        chunk.render(&ipos, visible, state);
+
+        if record {
+            manager.prepass_draws.push(ChunkDraw {
+                position: *pos,
+                visible,
+                queried: false,
+            });
+        }
     }
 }
 
+/// Draw the chunks the depth prepass drew, in the same order
+fn redraw_seperate(manager: &ChunkManager, state: &mut renderer::State) {
+    renderer::profiler::event!("Greedy Redraw");
+
+    for draw in &manager.prepass_draws {
+        let Some(chunk) = manager.chunks.get(&draw.position) else {
+            continue;
+        };
+        let ipos = draw.position * CHUNK_SIZE as i32;
+
+        match &manager.queries {
+            Some(queries) if draw.queried => {
+                queries.redraw(&draw.position, || chunk.render(&ipos, draw.visible, state));
+            }
+            _ => chunk.render(&ipos, draw.visible, state),
+        }
+    }
+}
+
+/// Draw the commands the depth prepass left in the indirect buffers
+fn redraw_combined(manager: &mut ChunkManager) {
+    renderer::profiler::event!("Greedy Redraw Combined");
+
+    manager.combined.bind();
+    manager.combined.bind_program();
+    manager.combined.chunk_data_buffer.bind();
+
+    if let Some(cull) = &manager.combined.gpu_cull {
+        cull.draw(manager.combined.draw_mode());
+        return;
+    }
+
+    unsafe {
+        gl::BindBuffer(
+            gl::DRAW_INDIRECT_BUFFER,
+            manager.combined.indirect_buffer.id(),
+        );
+    }
+
+    draw_combined(manager.combined.draw_count, manager.combined.draw_mode());
+}
+
+fn draw_combined(len: i32, draw_mode: DrawMode) {
+    renderer::profiler::event!("Greedy multidraw");
+    unsafe {
+        gl::MultiDrawArraysIndirect(draw_mode.into(), std::ptr::null(), len, 0);
+    }
+}
+
+/// Build the depth pyramid from what was just drawn, for next frame's occlusion culling
+fn capture_depth(manager: &mut ChunkManager, state: &renderer::State) {
+    let Some(hiz) = &mut manager.hiz else {
+        return;
+    };
+
+    // The depth buffer only matches the game camera when it is the one being drawn from
+    if !state.cameras.is_game_active() {
+        hiz.invalidate();
+        return;
+    }
+
+    let camera = state.cameras.game();
+    let view_projection = camera.get_projection() * camera.get_view().inverse();
+    let size = state.display().get_window().inner_size();
+
+    // The GPU cull reads the pyramid directly, everything else tests on the CPU
+    let read_back = !manager.combine || manager.combined.gpu_cull.is_none();
+
+    hiz.capture(size.width, size.height, view_projection, read_back);
+}
+
 fn render_combined(manager: &mut ChunkManager, state: &mut renderer::State) {
     renderer::profiler::event!("Greedy Render Combined");
     let frustum = &state.cameras.game_frustum();
 
-    if let RenderData::VertexPull(_, None) = &mut manager.combined.render_data {
-        println!("Vertex pull buffer not set up");
-        return;
+    let updated = if let Some(jobs) = &mut manager.jobs {
+        jobs.schedule(&manager.chunks);
+        jobs.apply(&manager.chunks)
+    } else {
+        let updated = manager
+            .chunks
+            .par_iter()
+            .filter(|e| e.value().update(e.key(), &manager.chunks))
+            .map(|e| *e.key())
+            .collect::<Vec<_>>();
+
+        for position in &updated {
+            if let Some(chunk) = manager.chunks.get(position) {
+                chunk.update_bounds(chunk_bounds(position));
+            }
+        }
+
+        updated
+    };
+
+    if !updated.is_empty() {
+        upload_slots(manager, &updated);
     }
 
+    // Bind after uploading, as growing the face buffer replaces it
     manager.combined.bind();
-    let program = if manager.combined.is_vertex_pull() {
-        culled_voxel_vertex_pull_combined::Program::get()
-    } else {
-        culled_voxel_combined::Program::get()
-    };
-    program.bind();
+    let program = manager.combined.bind_program();
+
+    let vertex_pull = manager.combined.is_vertex_pull();
+    let draw_mode = manager.combined.draw_mode();
+
+    if let Some(cull) = &mut manager.combined.gpu_cull {
+        state.stats.occlusion_culled += cull.cull(
+            manager.frustum_cull.then_some(frustum),
+            manager.hiz.as_ref(),
+            manager
+                .dir_cull
+                .then(|| state.cameras.game().transform().position),
+            vertex_pull,
+        );
 
-    if manager
-        .chunks
-        .par_iter()
-        .any(|e| e.value().update(e.key(), &manager.chunks))
-    {
-        setup_chunks(manager);
+        // Culling binds the compute program
+        program.bind();
+        manager.combined.chunk_data_buffer.bind();
+        cull.draw(draw_mode);
+        return;
     }
 
     fn setup_multidraw(
-        chunks: &DashMap<IVec3, Chunk>,
-        combined: &CombinedData,
+        manager: &ChunkManager,
         frustum: &Frustum,
-        cull: bool,
-    ) -> (ChunkData, Vec<DrawArraysIndirectCommand>) {
+        camera: Vec3,
+        reachable: Option<&hashbrown::HashSet<IVec3>>,
+    ) -> (ChunkData, Vec<DrawArraysIndirectCommand>, usize) {
         renderer::profiler::event!("Greedy setup multidraw");
 
-        let mut instance = 0;
+        let chunks = &manager.chunks;
+        let combined = &manager.combined;
+
+        let mut occluded = 0;
 
         let mut chunk_data = ChunkData {
             chunk_positions: vec![],
         };
         let mut draw_params = vec![];
 
-        for (pos, count) in combined.pos_order.iter() {
-            let chunk = if let Some(chunk) = chunks.get(pos) {
-                chunk
-            } else {
-                instance += *count as u32;
+        let mut slots = combined.faces.slots().iter().collect::<Vec<_>>();
+        if manager.sort_chunks {
+            slots.sort_by(|(a, _), (b, _)| {
+                chunk_distance(a, camera).total_cmp(&chunk_distance(b, camera))
+            });
+        }
+
+        for (pos, slot) in slots {
+            let Some(chunk) = chunks.get(pos) else {
                 continue;
             };
 
-            if cull && chunk.bounds().intersects(frustum).is_none() {
-                instance += *count as u32;
+            if reachable.is_some_and(|r| !r.contains(pos)) {
+                continue;
+            }
+
+            if manager.frustum_cull && chunk.bounds().intersects(frustum).is_none() {
+                continue;
+            }
+
+            let (min, max) = chunk_min_max(pos);
+
+            if let Some(hiz) = &manager.hiz
+                && !hiz.is_visible(min, max)
+            {
+                occluded += 1;
                 continue;
             }
 
             let vec = ivec3(pos[0], pos[1], pos[2]) * CHUNK_SIZE as i32;
 
-            chunk_data.chunk_positions.push(vec.to_array());
-
-            let indirect = match combined.render_data {
-                RenderData::Instance(_) => DrawArraysIndirectCommand {
-                    vertex_count: 4,
-                    instance_count: *count as u32,
-                    first: 0,
-                    base_instance: instance,
-                },
-                RenderData::VertexPull(_, _) => DrawArraysIndirectCommand {
-                    vertex_count: 6 * *count as u32,
-                    instance_count: 1,
-                    first: 6 * instance,
-                    base_instance: 0,
-                },
+            let ranges = if manager.dir_cull {
+                visible_ranges(&slot.dirs, visible_dirs(camera, min, max))
+            } else {
+                vec![(0, slot.count)]
             };
 
-            draw_params.push(indirect);
-
-            instance += *count as u32;
+            // Each range is its own draw, so needs its own copy of the chunk position
+            for (first, count) in ranges {
+                chunk_data.chunk_positions.push(vec.to_array());
+
+                let (first, count) = ((slot.first() + first) as u32, count as u32);
+
+                let indirect = match combined.render_data {
+                    RenderData::Instance { .. } => DrawArraysIndirectCommand {
+                        vertex_count: 4,
+                        instance_count: count,
+                        first: 0,
+                        base_instance: first,
+                    },
+                    RenderData::VertexPull(_) => DrawArraysIndirectCommand {
+                        vertex_count: 6 * count,
+                        instance_count: 1,
+                        first: 6 * first,
+                        base_instance: 0,
+                    },
+                };
+
+                draw_params.push(indirect);
+            }
         }
 
-        (chunk_data, draw_params)
+        (chunk_data, draw_params, occluded)
     }
 
-    let (uniforms, draw_params) = setup_multidraw(
-        &manager.chunks,
-        &manager.combined,
-        frustum,
-        manager.frustum_cull,
-    );
+    let camera = state.cameras.game().transform().position;
+    let reachable = manager
+        .cave_cull
+        .then(|| visible_chunks(&manager.chunks, camera, frustum));
+
+    let (uniforms, draw_params, occluded) =
+        setup_multidraw(manager, frustum, camera, reachable.as_ref());
+    state.stats.occlusion_culled += occluded;
 
     fn set_chunk_data(chunk_data: ChunkData, combined: &mut CombinedData) {
         renderer::profiler::event!("Greedy set combined chunk data");
@@ -410,6 +960,7 @@ fn render_combined(manager: &mut ChunkManager, state: &mut renderer::State) {
 
     let len = draw_params.len() as i32;
     set_indirect_commands(draw_params, &mut manager.combined);
+    manager.combined.draw_count = len;
 
     unsafe {
         gl::BindBuffer(
@@ -418,19 +969,7 @@ fn render_combined(manager: &mut ChunkManager, state: &mut renderer::State) {
         );
     }
 
-    fn draw_combined(len: i32, vertex_pull: bool) {
-        renderer::profiler::event!("Greedy multidraw");
-        let draw_mode = if vertex_pull {
-            DrawMode::Triangles
-        } else {
-            DrawMode::TriangleStrip
-        };
-        unsafe {
-            gl::MultiDrawArraysIndirect(draw_mode.into(), std::ptr::null(), len, 0);
-        }
-    }
-
-    draw_combined(len, manager.combined.is_vertex_pull());
+    draw_combined(len, draw_mode);
 
     // if let Some(global_pos) = get_looked_at_block(state.cameras.active(), |pos: &IVec3| {
     //     manager.get_block_at(pos)
@@ -0,0 +1,75 @@
+use dashmap::DashMap;
+use glam::IVec3;
+use rayon::prelude::*;
+use renderer::Dir;
+
+use common::{BlockType, WideInstanceData, seperate_global_pos};
+
+use super::{Chunk, chunk_bounds};
+
+type HashMap<K, V> = hashbrown::HashMap<K, V>;
+type ChunkFaces = HashMap<IVec3, Vec<WideInstanceData>>;
+
+/// Step to the block that would hide a face, even directions sit on the low side of a block
+fn neighbour_offset(dir: Dir) -> IVec3 {
+    let dir = usize::from(dir);
+
+    let mut offset = IVec3::ZERO;
+    offset[dir / 2] = if dir % 2 == 0 { -1 } else { 1 };
+
+    offset
+}
+
+/// Baseline culled meshing without depth masks.
+/// Every face of every block is kept unless looking its neighbour up in the scene finds
+/// a solid block, then the faces are handed to their chunks to draw as usual.
+pub fn mesh_chunks(data: &DashMap<IVec3, BlockType>, chunks: &DashMap<IVec3, Chunk>) {
+    renderer::profiler::event!("Naive cull");
+
+    let mut faces = data
+        .par_iter()
+        .fold(ChunkFaces::new, |mut faces, e| {
+            let (pos, block) = e.pair();
+            if !block.is_solid() {
+                return faces;
+            }
+
+            let (chunk_pos, in_chunk_pos) = seperate_global_pos(pos);
+
+            for dir in Dir::all() {
+                let neighbour = pos + neighbour_offset(dir);
+
+                if data.get(&neighbour).is_some_and(|b| b.is_solid()) {
+                    continue;
+                }
+
+                let face = WideInstanceData::new(
+                    in_chunk_pos.x as u8,
+                    in_chunk_pos.y as u8,
+                    in_chunk_pos.z as u8,
+                    dir,
+                    0,
+                    0,
+                    *block,
+                );
+
+                faces.entry(chunk_pos).or_default().push(face);
+            }
+
+            faces
+        })
+        .reduce(ChunkFaces::new, |mut a, b| {
+            for (pos, faces) in b {
+                a.entry(pos).or_default().extend(faces);
+            }
+            a
+        });
+
+    // Every chunk gets its faces, even if it has none, so none fall back to the binary mesher
+    for e in chunks.iter() {
+        let chunk = e.value();
+
+        chunk.set_faces(faces.remove(e.key()).unwrap_or_default());
+        chunk.update_bounds(chunk_bounds(e.key()));
+    }
+}
@@ -0,0 +1,196 @@
+use glam::IVec3;
+use renderer::buffers::{
+    Allocation, Buffer, BufferError, BufferMode, FreeListAllocator, GpuBuffer, RawBuffer,
+};
+
+use super::dirs::DirRanges;
+use super::faces::FaceEncoding;
+
+type HashMap<K, V> = hashbrown::HashMap<K, V>;
+
+/// Slots are rounded up to this many faces so small edits can remesh in place
+const SLOT_GRANULARITY: usize = 64;
+
+/// Where a chunk's faces live in the face buffer
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSlot {
+    pub allocation: Allocation,
+    pub count: usize,
+    pub dirs: DirRanges,
+}
+
+impl ChunkSlot {
+    /// Offset of the first face, in faces
+    pub fn first(&self) -> usize {
+        self.allocation.offset
+    }
+}
+
+/// One face buffer shared by every chunk, where each chunk owns a slot.
+/// Remeshing a chunk only rewrites its own slot.
+/// Slots are measured in faces, and faces are packed as `encoding`.
+pub struct FaceSlots {
+    faces: GpuBuffer,
+    allocator: FreeListAllocator,
+    slots: HashMap<IVec3, ChunkSlot>,
+    encoding: FaceEncoding,
+}
+
+impl FaceSlots {
+    pub fn new(encoding: FaceEncoding) -> Self {
+        Self {
+            faces: GpuBuffer::empty(0, BufferMode::Default).expect("Failed to make face buffer"),
+            allocator: FreeListAllocator::new(0),
+            slots: HashMap::new(),
+            encoding,
+        }
+    }
+
+    pub fn encoding(&self) -> FaceEncoding {
+        self.encoding
+    }
+
+    fn face_count(&self, words: &[u32]) -> usize {
+        words.len() / self.encoding.words()
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.faces.id()
+    }
+
+    pub fn slots(&self) -> &HashMap<IVec3, ChunkSlot> {
+        &self.slots
+    }
+
+    /// Lay every chunk out again from scratch in a single upload, faces are already encoded
+    pub fn rebuild(
+        &mut self,
+        chunks: impl IntoIterator<Item = (IVec3, Vec<u32>, DirRanges)>,
+    ) -> Result<(), BufferError> {
+        renderer::profiler::event!("Rebuild face slots");
+
+        let chunks = chunks
+            .into_iter()
+            .filter(|(_, faces, _)| !faces.is_empty())
+            .collect::<Vec<_>>();
+
+        let needed = chunks
+            .iter()
+            .map(|(_, faces, _)| slot_size(self.face_count(faces)))
+            .sum::<usize>();
+        let face_size = self.encoding.stride();
+
+        // Leave room to grow so the first edits don't reallocate
+        let capacity = needed + needed / 4;
+        if capacity > self.allocator.capacity() {
+            self.faces = GpuBuffer::empty(capacity * face_size, BufferMode::Default)?;
+            self.faces.set_label("Combined face buffer");
+        }
+
+        self.allocator = FreeListAllocator::new(self.allocator.capacity().max(capacity));
+        self.slots.clear();
+
+        let mut data = Vec::with_capacity(needed * self.encoding.words());
+
+        for (position, faces, dirs) in chunks {
+            let count = self.face_count(&faces);
+            let allocation = self
+                .allocator
+                .alloc(slot_size(count))
+                .expect("Face buffer was sized for every slot");
+
+            data.extend_from_slice(&faces);
+            data.resize(allocation.end() * self.encoding.words(), 0);
+
+            self.slots.insert(
+                position,
+                ChunkSlot {
+                    allocation,
+                    count,
+                    dirs,
+                },
+            );
+        }
+
+        if !data.is_empty() {
+            self.faces.set_offset_data(0, &data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace a chunk's faces, reusing its slot if they still fit
+    pub fn write(
+        &mut self,
+        position: IVec3,
+        faces: &[u32],
+        dirs: DirRanges,
+    ) -> Result<(), BufferError> {
+        renderer::profiler::event!("Write face slot");
+
+        let old = self.slots.remove(&position);
+        let count = self.face_count(faces);
+
+        if count == 0 {
+            if let Some(old) = old {
+                self.allocator.free(old.allocation);
+            }
+            return Ok(());
+        }
+
+        let allocation = match old {
+            Some(old) if old.allocation.size >= count => old.allocation,
+            _ => {
+                if let Some(old) = old {
+                    self.allocator.free(old.allocation);
+                }
+                self.alloc(slot_size(count))?
+            }
+        };
+
+        self.faces
+            .set_offset_data(allocation.offset * self.encoding.stride(), faces)?;
+
+        self.slots.insert(
+            position,
+            ChunkSlot {
+                allocation,
+                count,
+                dirs,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<Allocation, BufferError> {
+        if let Some(allocation) = self.allocator.alloc(size) {
+            return Ok(allocation);
+        }
+
+        let capacity = (self.allocator.capacity() * 2).max(self.allocator.capacity() + size);
+
+        if self.allocator.capacity() == 0 {
+            self.faces = GpuBuffer::empty(capacity * self.encoding.stride(), BufferMode::Default)?;
+            self.faces.set_label("Combined face buffer");
+        } else {
+            // Copies the old contents, so every other slot stays where it is
+            self.faces
+                .reallocate_with_size(capacity * self.encoding.stride())?;
+        }
+
+        self.allocator.grow(capacity);
+
+        self.allocator.alloc(size).ok_or(BufferError::OutOfMemory)
+    }
+}
+
+impl Default for FaceSlots {
+    fn default() -> Self {
+        Self::new(FaceEncoding::default())
+    }
+}
+
+fn slot_size(count: usize) -> usize {
+    count.next_multiple_of(SLOT_GRANULARITY)
+}
@@ -0,0 +1,338 @@
+use std::collections::VecDeque;
+
+use dashmap::DashMap;
+use glam::{IVec3, Vec3};
+use renderer::{
+    bounds::BoundingVolume,
+    camera::frustum::Frustum,
+    query::{ConditionalMode, OcclusionBoxes, Query, QueryTarget},
+};
+
+use crate::binary::common::{CHUNK_SIZE, VoxelArray};
+use common::Voxel;
+
+use super::{Chunk, chunk_bounds};
+
+type HashMap<K, V> = hashbrown::HashMap<K, V>;
+type HashSet<T> = hashbrown::HashSet<T>;
+
+/// Which faces of a chunk can see each other through the air inside it.
+/// Faces are numbered `axis * 2` for the low side and `axis * 2 + 1` for the high side,
+/// with one bit for each of the 15 pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Connectivity(u16);
+
+impl Connectivity {
+    /// Every face sees every other, as for an empty chunk
+    pub const OPEN: Self = Self((1 << 15) - 1);
+
+    /// Flood fill each pocket of air, joining every chunk face it touches
+    pub fn compute(voxels: &VoxelArray) -> Self {
+        renderer::profiler::event!("Compute connectivity");
+
+        let index = |pos: [usize; 3]| (pos[0] * CHUNK_SIZE + pos[1]) * CHUNK_SIZE + pos[2];
+        let is_air = |pos: [usize; 3]| !voxels[pos[0]][pos[1]][pos[2]].get_type().is_solid();
+
+        let mut visited = vec![false; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        let mut stack = vec![];
+        let mut connectivity = Self::default();
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let start = [x, y, z];
+                    if visited[index(start)] || !is_air(start) {
+                        continue;
+                    }
+
+                    visited[index(start)] = true;
+                    stack.push(start);
+
+                    let mut faces = 0u8;
+
+                    while let Some(pos) = stack.pop() {
+                        for face in 0..6 {
+                            let axis = face / 2;
+                            let high = face % 2 == 1;
+
+                            let edge = if high { CHUNK_SIZE - 1 } else { 0 };
+                            if pos[axis] == edge {
+                                faces |= 1 << face;
+                                continue;
+                            }
+
+                            let mut next = pos;
+                            next[axis] = if high { pos[axis] + 1 } else { pos[axis] - 1 };
+
+                            if !visited[index(next)] && is_air(next) {
+                                visited[index(next)] = true;
+                                stack.push(next);
+                            }
+                        }
+                    }
+
+                    connectivity.join(faces);
+                }
+            }
+        }
+
+        connectivity
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits & Self::OPEN.0)
+    }
+
+    pub fn connects(&self, a: usize, b: usize) -> bool {
+        a != b && self.0 & Self::bit(a, b) != 0
+    }
+
+    /// Connect every pair of faces set in `faces`
+    fn join(&mut self, faces: u8) {
+        for a in (0..6).filter(|a| faces & (1 << a) != 0) {
+            for b in (a + 1..6).filter(|b| faces & (1 << b) != 0) {
+                self.0 |= Self::bit(a, b);
+            }
+        }
+    }
+
+    fn bit(a: usize, b: usize) -> u16 {
+        let (a, b) = (a.min(b), a.max(b));
+        // Pairs are numbered in order, (0, 1), (0, 2) .. (4, 5)
+        let index = a * (11 - a) / 2 + b - a - 1;
+        1 << index
+    }
+}
+
+/// Chunk positions the camera could see through open chunk faces, searching outwards
+/// from the camera's chunk and never turning back on a direction already taken.
+/// Anywhere without a loaded chunk counts as air, up to one chunk past the loaded ones.
+pub fn visible_chunks(
+    chunks: &DashMap<IVec3, Chunk>,
+    camera: Vec3,
+    frustum: &Frustum,
+) -> HashSet<IVec3> {
+    renderer::profiler::event!("Cave cull");
+
+    let mut seen = HashSet::new();
+
+    let Some((low, high)) = chunk_extent(chunks) else {
+        return seen;
+    };
+    let (low, high) = (low - IVec3::ONE, high + IVec3::ONE);
+
+    let start = (camera / CHUNK_SIZE as f32)
+        .floor()
+        .as_ivec3()
+        .clamp(low, high);
+
+    // Chunk, the face it was entered through and the faces the search has left through
+    let mut queue = VecDeque::from([(start, None, 0u8)]);
+    seen.insert(start);
+
+    while let Some((pos, entered, travelled)) = queue.pop_front() {
+        let connectivity = chunks
+            .get(&pos)
+            .map(|chunk| chunk.connectivity())
+            .unwrap_or(Connectivity::OPEN);
+
+        for face in 0..6 {
+            if travelled & (1 << (face ^ 1)) != 0 {
+                continue;
+            }
+
+            if entered.is_some_and(|entered| !connectivity.connects(entered, face)) {
+                continue;
+            }
+
+            let mut next = pos;
+            next[face / 2] += if face % 2 == 1 { 1 } else { -1 };
+
+            if next.cmplt(low).any() || next.cmpgt(high).any() || seen.contains(&next) {
+                continue;
+            }
+
+            if chunk_bounds(&next).intersects(frustum).is_none() {
+                continue;
+            }
+
+            seen.insert(next);
+            queue.push_back((next, Some(face ^ 1), travelled | (1 << face)));
+        }
+    }
+
+    seen
+}
+
+/// Smallest and largest loaded chunk positions
+fn chunk_extent(chunks: &DashMap<IVec3, Chunk>) -> Option<(IVec3, IVec3)> {
+    chunks.iter().map(|e| *e.key()).fold(None, |extent, pos| {
+        Some(match extent {
+            Some((low, high)) => (pos.min(low), pos.max(high)),
+            None => (pos, pos),
+        })
+    })
+}
+
+/// A chunk's box queries, alternated so a frame can draw against the last frame's result
+/// while testing its own
+struct ChunkQuery {
+    queries: [Query; 2],
+    current: usize,
+    /// Frame the current query was made in
+    frame: Option<u64>,
+}
+
+impl ChunkQuery {
+    fn new() -> Self {
+        Self {
+            queries: [(); 2].map(|_| Query::new(QueryTarget::AnySamplesPassedConservative)),
+            current: 0,
+            frame: None,
+        }
+    }
+}
+
+/// Occlusion queries per chunk, made the first time the chunk is drawn.
+/// Draws are conditional on the previous frame's query, so the GPU never waits on a box
+/// it was just given.
+#[derive(Default)]
+pub struct ChunkQueries {
+    boxes: OcclusionBoxes,
+    queries: HashMap<IVec3, ChunkQuery>,
+    frame: u64,
+}
+
+impl ChunkQueries {
+    /// Start a new frame, queries from before the last one are too old to draw against
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Draw the chunk's box into a new query, then only keep what `draw` draws if its box
+    /// was visible last frame. Chunks that weren't queried last frame are always drawn.
+    /// The camera uniforms must already be bound.
+    /// Returns whether the chunk was queried.
+    pub fn draw(
+        &mut self,
+        position: IVec3,
+        min: Vec3,
+        max: Vec3,
+        camera: Vec3,
+        draw: impl FnOnce(),
+    ) -> bool {
+        // The near plane would clip the box away, so always draw chunks the camera is in
+        if camera.cmpge(min - Vec3::ONE).all() && camera.cmple(max + Vec3::ONE).all() {
+            self.queries.remove(&position);
+            draw();
+            return false;
+        }
+
+        let chunk = self.queries.entry(position).or_insert_with(ChunkQuery::new);
+        let previous = chunk.frame.is_some_and(|frame| frame + 1 == self.frame);
+
+        chunk.current ^= 1;
+        chunk.frame = Some(self.frame);
+        self.boxes.query(&chunk.queries[chunk.current], min, max);
+
+        if previous {
+            // Draws anyway if the GPU hasn't got to last frame's box yet
+            chunk.queries[chunk.current ^ 1].conditional(ConditionalMode::NoWait, draw);
+        } else {
+            draw();
+        }
+
+        true
+    }
+
+    /// Draw again against the query the chunk's box was just drawn into.
+    /// Every box was queried during the earlier pass, so the result is ready by now.
+    pub fn redraw(&self, position: &IVec3, draw: impl FnOnce()) {
+        match self.queries.get(position) {
+            Some(chunk) => chunk.queries[chunk.current].conditional(ConditionalMode::Wait, draw),
+            None => draw(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{BasicVoxel, BlockType};
+
+    use super::*;
+
+    fn filled(block_type: BlockType) -> Box<VoxelArray> {
+        Box::new([[[BasicVoxel::new(block_type); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE])
+    }
+
+    #[test]
+    fn pairs_have_their_own_bit_in_order() {
+        let mut index = 0;
+        for a in 0..6 {
+            for b in a + 1..6 {
+                assert_eq!(Connectivity::bit(a, b), 1 << index, "faces {} and {}", a, b);
+                assert_eq!(Connectivity::bit(b, a), Connectivity::bit(a, b));
+                index += 1;
+            }
+        }
+
+        assert_eq!(index, 15);
+    }
+
+    #[test]
+    fn empty_chunk_is_open() {
+        assert_eq!(
+            Connectivity::compute(&filled(BlockType::Air)),
+            Connectivity::OPEN
+        );
+    }
+
+    #[test]
+    fn solid_chunk_connects_nothing() {
+        let connectivity = Connectivity::compute(&filled(BlockType::Stone));
+
+        assert_eq!(connectivity, Connectivity::default());
+        assert!((0..6).all(|a| (0..6).all(|b| !connectivity.connects(a, b))));
+    }
+
+    #[test]
+    fn wall_splits_low_and_high_x() {
+        let mut voxels = filled(BlockType::Air);
+        for row in voxels[CHUNK_SIZE / 2].iter_mut() {
+            row.fill(BasicVoxel::new(BlockType::Stone));
+        }
+
+        let connectivity = Connectivity::compute(&voxels);
+
+        assert!(!connectivity.connects(0, 1));
+        assert!(!connectivity.connects(1, 0));
+        assert!(connectivity.connects(0, 2));
+        assert!(connectivity.connects(1, 5));
+        assert!(connectivity.connects(2, 3));
+        assert_eq!(connectivity.bits(), Connectivity::OPEN.bits() & !1);
+    }
+
+    #[test]
+    fn tunnel_only_joins_its_ends() {
+        let mut voxels = filled(BlockType::Stone);
+        for y in 0..CHUNK_SIZE {
+            voxels[5][y][5] = BasicVoxel::new(BlockType::Air);
+        }
+
+        let connectivity = Connectivity::compute(&voxels);
+
+        assert_eq!(connectivity.bits(), Connectivity::bit(2, 3));
+        assert!(connectivity.connects(3, 2));
+        assert!(!connectivity.connects(2, 2));
+    }
+
+    #[test]
+    fn from_bits_drops_unused_bits() {
+        assert_eq!(Connectivity::from_bits(u16::MAX), Connectivity::OPEN);
+        assert_eq!(Connectivity::from_bits(1 << 15), Connectivity::default());
+    }
+}
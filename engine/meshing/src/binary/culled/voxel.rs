@@ -23,7 +23,7 @@ renderer::program!(culled_voxel, {
     }
 
     struct iIn {
-        uint data;
+        uvec2 data;
     }
 
     struct v2f {
@@ -66,6 +66,15 @@ renderer::snippet!(vertex_pull_face_data, {
     buffer FaceData {
         uint face_data[];
     };
+
+    // Faces take up `words` words, narrow faces read 0 for the second
+    uvec2 load_face(uint index, uint words) {
+        if (words == 2u) {
+            return uvec2(face_data[index * 2u], face_data[(index * 2u) + 1u]);
+        }
+
+        return uvec2(face_data[index], 0u);
+    }
 });
 
 renderer::program!(culled_voxel_vertex_pull, {
@@ -73,6 +82,7 @@ renderer::program!(culled_voxel_vertex_pull, {
     #fragment frag
 
     uniform ivec3 chunk_position;
+    uniform uint face_words;
 
     #snippet crate::binary::culled::voxel::vertex_pull_face_data
     #snippet renderer::camera_matrices
@@ -87,7 +97,7 @@ renderer::program!(culled_voxel_vertex_pull, {
         mat4 vp = camera.projection * camera.inverse_view;
 
         ivec3 v_pos = vertices[indices[gl_VertexID % 6]];
-        uint face = face_data[gl_VertexID / 6];
+        uvec2 face = load_face(uint(gl_VertexID / 6), face_words);
 
         PlaneData data = unpack_data(v_pos, face, chunk_position);
 
@@ -107,6 +117,8 @@ renderer::program!(culled_voxel_vertex_pull_combined, {
     #vertex vert
     #fragment frag
 
+    uniform uint face_words;
+
     #snippet crate::binary::culled::voxel::vertex_pull_face_data
     #snippet crate::binary::culled::voxel::combined_chunk_data
     #snippet renderer::camera_matrices
@@ -121,7 +133,7 @@ renderer::program!(culled_voxel_vertex_pull_combined, {
         mat4 vp = camera.projection * camera.inverse_view;
 
         ivec3 v_pos = vertices[indices[gl_VertexID % 6]];
-        uint face = face_data[gl_VertexID / 6];
+        uvec2 face = load_face(uint(gl_VertexID / 6), face_words);
 
         ivec3 chunk_position = chunk_positions[gl_DrawID];
 
@@ -159,7 +171,7 @@ renderer::program!(culled_voxel_combined, {
     }
 
     struct iIn {
-        uint data;
+        uvec2 data;
     }
 
     struct v2f {
@@ -1,9 +1,10 @@
-#![feature(portable_simd)]
+#![feature(portable_simd, duration_millis_float)]
 use glam::Mat4;
 use renderer::{ProgramSource, Renderable, buffers::ShaderBuffer, mesh::basic::BasicMesh};
 
 pub mod basic;
 pub mod binary;
+pub mod smooth;
 
 const VERTICES: [[f32; 2]; 3] = [[-0.5, -0.5], [0.0, 0.5], [0.5, -0.5]];
 
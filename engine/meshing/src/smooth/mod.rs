@@ -0,0 +1,193 @@
+use std::time::Instant;
+
+use dashmap::DashMap;
+use glam::{IVec3, Vec3};
+use rayon::prelude::*;
+use renderer::{
+    ChunkTimes, DrawMode, MeshingStats, ProgramSource, Renderable, bounds::BoundingHeirarchy,
+    mesh::basic::BasicMesh,
+};
+
+use crate::binary::common::CHUNK_SIZE;
+use common::{Args, BlockType, tests::test_scene};
+pub use surface_nets::{SmoothMesh, surface_nets};
+
+mod surface_nets;
+
+type HashSet<T> = hashbrown::HashSet<T>;
+
+struct SmoothChunk {
+    mesh: BasicMesh<smooth_voxel::Vertex>,
+}
+
+impl SmoothChunk {
+    fn new(position: IVec3, data: &SmoothMesh, frustum_cull: bool) -> Self {
+        let min = (position * CHUNK_SIZE as i32).as_vec3();
+        let max = min + Vec3::splat(CHUNK_SIZE as f32);
+
+        let mut mesh = BasicMesh::from_data(
+            &data.vertices,
+            Some(&data.indices),
+            None,
+            Some(BoundingHeirarchy::from_min_max(min, max)),
+            false,
+            false,
+            DrawMode::Triangles,
+        );
+        mesh.set_frustum_cull(frustum_cull);
+
+        Self { mesh }
+    }
+}
+
+/// Smooth isosurface chunks, meshed once at startup
+pub struct SmoothManager {
+    chunks: Vec<SmoothChunk>,
+    meshing: MeshingStats,
+}
+
+pub fn setup(args: &Args) -> SmoothManager {
+    if args.combine || args.vertex_pull || args.gpu_mesh {
+        eprintln!("Smooth meshes are always meshed on the CPU and drawn seperately");
+    }
+
+    if args.edits > 0 {
+        eprintln!("Smooth meshes are only meshed at startup, so can't be edited");
+    }
+
+    let data = test_scene(args);
+
+    let start = Instant::now();
+
+    let meshes = mesh_chunks(&data);
+    let triangles = meshes
+        .iter()
+        .map(|(_, m, _)| m.triangle_count())
+        .sum::<usize>();
+
+    let mesh_time = start.elapsed().as_millis_f64();
+    println!(
+        "Meshed {} chunks into {} triangles in {:.2}ms",
+        meshes.len(),
+        triangles,
+        mesh_time
+    );
+
+    let start = Instant::now();
+    let mut times = vec![];
+
+    let chunks = meshes
+        .iter()
+        .map(|(pos, mesh, faces)| {
+            let upload = Instant::now();
+            let chunk = SmoothChunk::new(*pos, mesh, args.frustum_cull);
+
+            times.push(ChunkTimes {
+                build_depths: None,
+                faces: Some(*faces),
+                upload: Some(upload.elapsed().as_millis_f64()),
+            });
+
+            chunk
+        })
+        .collect();
+
+    let meshing = MeshingStats::new(
+        Some(mesh_time),
+        Some(start.elapsed().as_millis_f64()),
+        &times,
+    );
+
+    SmoothManager { chunks, meshing }
+}
+
+/// Mesh every chunk the surface could pass through, leaving out empty meshes.
+/// Each mesh comes with how long it took in milliseconds.
+pub fn mesh_chunks(data: &DashMap<IVec3, BlockType>) -> Vec<(IVec3, SmoothMesh, f64)> {
+    let mut positions = HashSet::new();
+
+    for e in data.iter() {
+        let chunk = e.key().div_euclid(IVec3::splat(CHUNK_SIZE as i32));
+        positions.insert(chunk);
+
+        // The chunks below own the edges leading into this one
+        for offset in [IVec3::NEG_X, IVec3::NEG_Y, IVec3::NEG_Z] {
+            positions.insert(chunk + offset);
+        }
+    }
+
+    positions
+        .into_iter()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|pos| {
+            let start = Instant::now();
+            let mesh = surface_nets(data, pos);
+            (pos, mesh, start.elapsed().as_millis_f64())
+        })
+        .filter(|(_, mesh, _)| !mesh.is_empty())
+        .collect()
+}
+
+impl Renderable for SmoothManager {
+    fn render(&mut self, state: &mut renderer::State) {
+        renderer::profiler::event!("Smooth Render");
+
+        let program = smooth_voxel::Program::get();
+        // Left to the defaults in lighting.glsl, which the culled meshes light with too
+        let uniforms = smooth_voxel::Uniforms {
+            ambient_light: None,
+            sky_light_color: None,
+            sky_light_direction: None,
+        };
+
+        for chunk in &mut self.chunks {
+            state.draw(&mut chunk.mesh, &program, &uniforms);
+        }
+    }
+
+    fn args(&mut self, args: &Args) {
+        for chunk in &mut self.chunks {
+            chunk.mesh.set_frustum_cull(args.frustum_cull);
+        }
+    }
+
+    fn meshing_stats(&self) -> Option<MeshingStats> {
+        Some(self.meshing)
+    }
+}
+
+renderer::program!(smooth_voxel, {
+    #vertex vert
+    #fragment frag
+
+    #snippet renderer::camera_matrices
+
+    #include "shaders/lighting.glsl"
+    #include "shaders/block.glsl"
+
+    struct vIn {
+        vec3 position;
+        vec3 normal;
+        uint block_type;
+    }
+
+    struct v2f {
+        vec4 color;
+    }
+
+    v2f vert(vIn i) {
+        v2f o;
+        mat4 vp = camera.projection * camera.inverse_view;
+
+        vec4 color = get_block_color(i.block_type);
+        o.color = apply_sky_lighting(color, i.normal, i.position);
+
+        gl_Position = vp * vec4(i.position, 1.0);
+        return o;
+    }
+
+    vec4 frag(v2f i) {
+        return i.color;
+    }
+});
@@ -0,0 +1,224 @@
+use dashmap::DashMap;
+use glam::{IVec3, Vec3};
+
+use common::BlockType;
+
+use super::smooth_voxel;
+use crate::binary::common::CHUNK_SIZE;
+
+/// Samples along each axis, one either side of the chunk so neighbouring chunks share cells
+const SAMPLES: usize = CHUNK_SIZE + 2;
+/// Density the surface passes through
+const ISO_LEVEL: f32 = 0.5;
+
+/// Corners of a cell, bit 0 is x, bit 1 is y and bit 2 is z
+const CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+/// Pairs of corners joined by each of a cell's 12 edges
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Triangles of a single chunk, in world space
+pub struct SmoothMesh {
+    pub vertices: Vec<smooth_voxel::Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl SmoothMesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+/// Density of each voxel around a chunk, 1 for solid and 0 for air.
+/// Sampled at voxel centres from one before the chunk to one after it.
+struct Samples {
+    density: Vec<f32>,
+    block_types: Vec<BlockType>,
+}
+
+impl Samples {
+    fn new(blocks: &DashMap<IVec3, BlockType>, chunk: IVec3) -> Self {
+        let origin = chunk * CHUNK_SIZE as i32 - IVec3::ONE;
+
+        let mut density = vec![0.0; SAMPLES * SAMPLES * SAMPLES];
+        let mut block_types = vec![BlockType::Air; SAMPLES * SAMPLES * SAMPLES];
+
+        for x in 0..SAMPLES {
+            for y in 0..SAMPLES {
+                for z in 0..SAMPLES {
+                    let pos = origin + IVec3::new(x as i32, y as i32, z as i32);
+
+                    if let Some(block) = blocks.get(&pos).filter(|b| b.is_solid()) {
+                        let index = Self::index([x, y, z]);
+                        density[index] = 1.0;
+                        block_types[index] = *block;
+                    }
+                }
+            }
+        }
+
+        Self {
+            density,
+            block_types,
+        }
+    }
+
+    fn index(pos: [usize; 3]) -> usize {
+        (pos[0] * SAMPLES + pos[1]) * SAMPLES + pos[2]
+    }
+
+    fn density(&self, pos: [usize; 3]) -> f32 {
+        self.density[Self::index(pos)]
+    }
+
+    fn is_solid(&self, pos: [usize; 3]) -> bool {
+        self.density(pos) > ISO_LEVEL
+    }
+}
+
+/// Mesh a chunk with naive Surface Nets.
+/// Each cell the surface passes through gets one vertex, the average of where the surface
+/// crosses its edges, and every crossed edge joins the four cells around it with a quad.
+/// A chunk only makes quads for the edges starting in it, and cells on its border are
+/// placed the same way by both chunks, so neighbouring meshes meet without gaps.
+pub fn surface_nets(blocks: &DashMap<IVec3, BlockType>, chunk: IVec3) -> SmoothMesh {
+    renderer::profiler::event!("Surface nets");
+
+    let samples = Samples::new(blocks, chunk);
+    // Sample 0 is the voxel before the chunk, and samples sit at voxel centres
+    let origin = (chunk * CHUNK_SIZE as i32).as_vec3() - Vec3::splat(0.5);
+
+    const CELLS: usize = SAMPLES - 1;
+    let cell_index = |pos: [usize; 3]| (pos[0] * CELLS + pos[1]) * CELLS + pos[2];
+
+    let mut cell_vertices = vec![u32::MAX; CELLS * CELLS * CELLS];
+    let mut vertices = vec![];
+
+    for x in 0..CELLS {
+        for y in 0..CELLS {
+            for z in 0..CELLS {
+                let corners = CORNERS.map(|c| samples.density([x + c[0], y + c[1], z + c[2]]));
+
+                let solid = corners.iter().filter(|d| **d > ISO_LEVEL).count();
+                if solid == 0 || solid == 8 {
+                    continue;
+                }
+
+                let mut offset = Vec3::ZERO;
+                let mut crossings = 0;
+
+                for (a, b) in EDGES {
+                    let (da, db) = (corners[a], corners[b]);
+                    if (da > ISO_LEVEL) == (db > ISO_LEVEL) {
+                        continue;
+                    }
+
+                    let t = (ISO_LEVEL - da) / (db - da);
+                    let from = Vec3::from(CORNERS[a].map(|c| c as f32));
+                    let to = Vec3::from(CORNERS[b].map(|c| c as f32));
+
+                    offset += from.lerp(to, t);
+                    crossings += 1;
+                }
+
+                offset /= crossings as f32;
+
+                // Density rises into the ground, so the normal points down the gradient
+                let gradient = Vec3::new(
+                    corners[1] + corners[3] + corners[5] + corners[7]
+                        - (corners[0] + corners[2] + corners[4] + corners[6]),
+                    corners[2] + corners[3] + corners[6] + corners[7]
+                        - (corners[0] + corners[1] + corners[4] + corners[5]),
+                    corners[4] + corners[5] + corners[6] + corners[7]
+                        - (corners[0] + corners[1] + corners[2] + corners[3]),
+                );
+                let normal = (-gradient).try_normalize().unwrap_or(Vec3::Y);
+
+                let block_type = CORNERS
+                    .iter()
+                    .map(|c| [x + c[0], y + c[1], z + c[2]])
+                    .find(|pos| samples.is_solid(*pos))
+                    .map(|pos| samples.block_types[Samples::index(pos)])
+                    .unwrap_or_default();
+
+                let position = origin + Vec3::new(x as f32, y as f32, z as f32) + offset;
+
+                cell_vertices[cell_index([x, y, z])] = vertices.len() as u32;
+                vertices.push(smooth_voxel::Vertex {
+                    position: position.to_array(),
+                    normal: normal.to_array(),
+                    block_type: block_type.into(),
+                });
+            }
+        }
+    }
+
+    let mut indices = vec![];
+
+    // Only the edges starting inside the chunk, the neighbours make the rest
+    for x in 1..=CHUNK_SIZE {
+        for y in 1..=CHUNK_SIZE {
+            for z in 1..=CHUNK_SIZE {
+                let pos = [x, y, z];
+                let solid = samples.is_solid(pos);
+
+                for axis in 0..3 {
+                    let mut next = pos;
+                    next[axis] += 1;
+
+                    if solid == samples.is_solid(next) {
+                        continue;
+                    }
+
+                    // The other two axes, in order so that u x v points along the axis
+                    let u = (axis + 1) % 3;
+                    let v = (axis + 2) % 3;
+
+                    let cell = |du: usize, dv: usize| {
+                        let mut cell = pos;
+                        cell[u] -= du;
+                        cell[v] -= dv;
+                        cell_vertices[cell_index(cell)]
+                    };
+
+                    let quad = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+
+                    // Face away from the solid side
+                    if solid {
+                        indices.extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+                    } else {
+                        indices.extend([quad[0], quad[2], quad[1], quad[0], quad[3], quad[2]]);
+                    }
+                }
+            }
+        }
+    }
+
+    SmoothMesh { vertices, indices }
+}
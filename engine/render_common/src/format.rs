@@ -250,6 +250,9 @@ impl AttributeType {
             Bool => gl::BOOL,
             I32 => gl::INT,
             U32 => gl::UNSIGNED_INT,
+            U32U32 => gl::UNSIGNED_INT_VEC2,
+            U32U32U32 => gl::UNSIGNED_INT_VEC3,
+            U32U32U32U32 => gl::UNSIGNED_INT_VEC4,
             F32 => gl::FLOAT,
             F32F32 => gl::FLOAT_VEC2,
             I32I32 => gl::INT_VEC2,
@@ -268,7 +271,7 @@ impl AttributeType {
         use AttributeType::*;
         match *self {
             I8 | I32 | I32I32 | I32I32I32 | I32I32I32I32 | Bool => gl::INT,
-            U32 => gl::UNSIGNED_INT,
+            U32 | U32U32 | U32U32U32 | U32U32U32U32 => gl::UNSIGNED_INT,
             F32 | F32F32 | F32F32F32 | F32F32F32F32 | F32x4x4 => gl::FLOAT,
             _ => panic!("TODO: Convert to OpenGL type"),
         }
@@ -277,8 +280,8 @@ impl AttributeType {
     pub fn slots_taken(&self) -> usize {
         use AttributeType::*;
         match self {
-            I8 | I32 | I32I32 | I32I32I32 | I32I32I32I32 | U32 | F32 | F32F32 | F32F32F32
-            | F32F32F32F32 | Bool => 1,
+            I8 | I32 | I32I32 | I32I32I32 | I32I32I32I32 | U32 | U32U32 | U32U32U32
+            | U32U32U32U32 | F32 | F32F32 | F32F32F32 | F32F32F32F32 | Bool => 1,
             F32x4x4 => 4,
             _ => {
                 todo!("Input layout slots for {:?}", self);
@@ -219,11 +219,17 @@ impl ComputeProgram {
     }
 
     pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        self.dispatch_with_barrier(x, y, z, gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    }
+
+    /// Dispatch the program, then issue a memory barrier for the given bits
+    /// so later reads of the written buffers see the results.
+    pub fn dispatch_with_barrier(&self, x: u32, y: u32, z: u32, barriers: gl::types::GLbitfield) {
         self.bind();
 
         unsafe {
             gl::DispatchCompute(x, y, z);
-            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+            gl::MemoryBarrier(barriers);
         }
     }
 
@@ -0,0 +1,208 @@
+/// A range handed out by a [`FreeListAllocator`], in whatever unit the allocator was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Allocation {
+    pub fn end(&self) -> usize {
+        self.offset + self.size
+    }
+}
+
+/// First fit free list for sub-allocating a buffer.
+/// Only tracks ranges, writing to and growing the buffer is left to the owner.
+#[derive(Debug)]
+pub struct FreeListAllocator {
+    /// Free ranges sorted by offset, neighbours are always merged
+    free: Vec<Allocation>,
+    capacity: usize,
+}
+
+impl FreeListAllocator {
+    pub fn new(capacity: usize) -> Self {
+        let free = if capacity > 0 {
+            vec![Allocation {
+                offset: 0,
+                size: capacity,
+            }]
+        } else {
+            vec![]
+        };
+
+        Self { free, capacity }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Total size of the free ranges
+    pub fn free_space(&self) -> usize {
+        self.free.iter().map(|a| a.size).sum()
+    }
+
+    /// Take the first free range that fits `size`, or `None` if nothing does
+    pub fn alloc(&mut self, size: usize) -> Option<Allocation> {
+        if size == 0 {
+            return None;
+        }
+
+        let index = self.free.iter().position(|a| a.size >= size)?;
+        let range = &mut self.free[index];
+
+        let allocation = Allocation {
+            offset: range.offset,
+            size,
+        };
+
+        range.offset += size;
+        range.size -= size;
+
+        if range.size == 0 {
+            self.free.remove(index);
+        }
+
+        Some(allocation)
+    }
+
+    /// Give a range back, merging it with any free neighbours
+    pub fn free(&mut self, allocation: Allocation) {
+        if allocation.size == 0 {
+            return;
+        }
+
+        debug_assert!(allocation.end() <= self.capacity);
+
+        let index = self.free.partition_point(|a| a.offset < allocation.offset);
+        self.free.insert(index, allocation);
+
+        if index + 1 < self.free.len() && self.free[index].end() == self.free[index + 1].offset {
+            self.free[index].size += self.free[index + 1].size;
+            self.free.remove(index + 1);
+        }
+
+        if index > 0 && self.free[index - 1].end() == self.free[index].offset {
+            self.free[index - 1].size += self.free[index].size;
+            self.free.remove(index);
+        }
+    }
+
+    /// Extend the managed range, the new space is free.
+    /// Existing allocations keep their offsets.
+    pub fn grow(&mut self, capacity: usize) {
+        if capacity <= self.capacity {
+            return;
+        }
+
+        let added = Allocation {
+            offset: self.capacity,
+            size: capacity - self.capacity,
+        };
+        self.capacity = capacity;
+
+        self.free(added);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(offset: usize, size: usize) -> Allocation {
+        Allocation { offset, size }
+    }
+
+    #[test]
+    fn alloc_is_first_fit() {
+        let mut allocator = FreeListAllocator::new(100);
+
+        assert_eq!(allocator.alloc(10), Some(range(0, 10)));
+        assert_eq!(allocator.alloc(20), Some(range(10, 20)));
+        assert_eq!(allocator.free_space(), 70);
+
+        allocator.free(range(0, 10));
+
+        // The hole at the start is too small, so the tail is used
+        assert_eq!(allocator.alloc(15), Some(range(30, 15)));
+        assert_eq!(allocator.alloc(10), Some(range(0, 10)));
+        assert_eq!(allocator.free_space(), 55);
+    }
+
+    #[test]
+    fn alloc_fails_without_space() {
+        let mut allocator = FreeListAllocator::new(10);
+
+        assert_eq!(allocator.alloc(0), None);
+        assert_eq!(allocator.alloc(11), None);
+        assert_eq!(allocator.alloc(10), Some(range(0, 10)));
+        assert_eq!(allocator.alloc(1), None);
+
+        assert_eq!(FreeListAllocator::new(0).alloc(1), None);
+    }
+
+    #[test]
+    fn free_coalesces_neighbours() {
+        let mut allocator = FreeListAllocator::new(30);
+        let a = allocator.alloc(10).unwrap();
+        let b = allocator.alloc(10).unwrap();
+        let c = allocator.alloc(10).unwrap();
+
+        allocator.free(a);
+        allocator.free(c);
+        assert_eq!(allocator.free, vec![range(0, 10), range(20, 10)]);
+
+        // Joins both sides into the whole range
+        allocator.free(b);
+        assert_eq!(allocator.free, vec![range(0, 30)]);
+        assert_eq!(allocator.alloc(30), Some(range(0, 30)));
+    }
+
+    #[test]
+    fn free_keeps_ranges_sorted() {
+        let mut allocator = FreeListAllocator::new(50);
+        let allocations = (0..5)
+            .map(|_| allocator.alloc(10).unwrap())
+            .collect::<Vec<_>>();
+
+        allocator.free(allocations[3]);
+        allocator.free(allocations[1]);
+        assert_eq!(allocator.free, vec![range(10, 10), range(30, 10)]);
+
+        allocator.free(allocations[2]);
+        assert_eq!(allocator.free, vec![range(10, 30)]);
+
+        allocator.free(allocations[0]);
+        allocator.free(allocations[4]);
+        assert_eq!(allocator.free, vec![range(0, 50)]);
+    }
+
+    #[test]
+    fn grow_adds_free_space_at_the_end() {
+        let mut allocator = FreeListAllocator::new(20);
+        let a = allocator.alloc(20).unwrap();
+
+        allocator.grow(50);
+        assert_eq!(allocator.capacity(), 50);
+        assert_eq!(allocator.alloc(30), Some(range(20, 30)));
+
+        // Existing allocations keep their offsets
+        allocator.free(a);
+        assert_eq!(allocator.alloc(20), Some(range(0, 20)));
+    }
+
+    #[test]
+    fn grow_merges_with_free_tail() {
+        let mut allocator = FreeListAllocator::new(20);
+        allocator.alloc(5).unwrap();
+
+        allocator.grow(40);
+        assert_eq!(allocator.free, vec![range(5, 35)]);
+
+        // Shrinking is ignored
+        allocator.grow(10);
+        assert_eq!(allocator.capacity(), 40);
+        assert_eq!(allocator.free_space(), 35);
+    }
+}
@@ -1,15 +1,19 @@
+mod allocator;
 mod ebo;
 mod fenced_buffer;
 mod gpu_buffer;
 mod mapping;
+mod read_back;
 mod ssbo;
 mod vao;
 mod vbo;
 
+pub use allocator::*;
 pub use ebo::*;
 pub use fenced_buffer::*;
 pub use gpu_buffer::*;
 pub use mapping::*;
+pub use read_back::*;
 pub use ssbo::*;
 pub use vao::*;
 pub use vbo::*;
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use super::{Buffer, BufferMode, FencedBuffer, FencedRawBuffer};
+
+/// Most copies in flight, new ones are skipped until the GPU catches up
+const MAX_PENDING: usize = 3;
+
+/// Reads ranges of GPU buffers back a frame or two late through fenced copies,
+/// so the CPU never waits on the commands that wrote them.
+/// Each copy carries a `T` saying what it holds.
+pub struct ReadBack<T> {
+    pending: VecDeque<(FencedRawBuffer, T)>,
+    free: Vec<FencedRawBuffer>,
+}
+
+impl<T> ReadBack<T> {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            free: vec![],
+        }
+    }
+
+    /// Copy `size` bytes at `offset` in `source` once the commands before it are done.
+    /// Returns false if too many copies are still in flight.
+    pub fn copy(&mut self, source: gl::types::GLuint, offset: usize, size: usize, tag: T) -> bool {
+        if self.pending.len() >= MAX_PENDING {
+            return false;
+        }
+
+        let mut buffer = match self.free.iter().position(|b| b.size() == size) {
+            Some(i) => self.free.swap_remove(i),
+            None => {
+                self.free.clear();
+                match FencedRawBuffer::empty(size, BufferMode::Default) {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        eprintln!("Error making read back buffer: {:?}", e);
+                        return false;
+                    }
+                }
+            }
+        };
+
+        unsafe {
+            // Make shader writes to the source visible to the copy
+            gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+            gl::CopyNamedBufferSubData(source, buffer.id(), offset as isize, 0, size as isize);
+        }
+        buffer.start_fence();
+
+        self.pending.push_back((buffer, tag));
+        true
+    }
+
+    /// The newest copy the GPU has finished, older finished ones are dropped
+    pub fn poll<D: Copy + Default>(&mut self) -> Option<(T, Vec<D>)> {
+        let mut newest = None;
+
+        while self.pending.front().is_some_and(|(b, _)| b.signalled()) {
+            let finished = self.pending.pop_front().unwrap();
+            if let Some((buffer, _)) = newest.replace(finished) {
+                self.free.push(buffer);
+            }
+        }
+
+        let (buffer, tag) = newest?;
+        let mut data = vec![D::default(); buffer.size() / std::mem::size_of::<D>()];
+
+        unsafe {
+            gl::GetNamedBufferSubData(
+                buffer.id(),
+                0,
+                std::mem::size_of_val(data.as_slice()) as isize,
+                data.as_mut_ptr() as *mut std::ffi::c_void,
+            );
+        }
+
+        self.free.push(buffer);
+        Some((tag, data))
+    }
+
+    /// Forget copies that haven't finished, for when they no longer apply
+    pub fn discard(&mut self) {
+        self.free
+            .extend(self.pending.drain(..).map(|(buffer, _)| buffer));
+    }
+}
+
+impl<T> Default for ReadBack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
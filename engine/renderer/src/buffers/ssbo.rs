@@ -53,10 +53,25 @@ where
         })
     }
 
+    /// Make a buffer of `size` bytes without writing any data,
+    /// for when the contents will be filled on the GPU.
+    pub fn empty(size: usize) -> Result<Self, BufferError> {
+        let buffer = FencedRawBuffer::empty(size, BufferMode::Persistent)?;
+
+        Ok(Self {
+            buffer,
+            phantom: PhantomData,
+        })
+    }
+
     pub fn id(&self) -> u32 {
         self.buffer.id()
     }
 
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
     pub fn set_single(&mut self, uniform: &U, offset: usize) -> Result<(), BufferError> {
         let size = uniform.size();
         if size + offset > self.buffer.size() {
@@ -143,8 +143,13 @@ where
     }
 
     pub fn set_instance_vbo(&mut self, id: u32) {
+        self.set_instance_vbo_strided(id, std::mem::size_of::<I>());
+    }
+
+    /// Attach an instance buffer whose elements aren't laid out as `I`
+    pub fn set_instance_vbo_strided(&mut self, id: u32, stride: usize) {
         unsafe {
-            gl::VertexArrayVertexBuffer(self.id, 1, id, 0, std::mem::size_of::<T>() as i32);
+            gl::VertexArrayVertexBuffer(self.id, 1, id, 0, stride as i32);
         }
     }
 
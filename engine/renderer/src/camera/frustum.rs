@@ -1,4 +1,4 @@
-use glam::Vec3;
+use glam::{Vec3, Vec4};
 
 pub struct Plane {
     normal: Vec3,
@@ -21,6 +21,11 @@ impl Plane {
     pub fn normal(&self) -> &Vec3 {
         &self.normal
     }
+
+    /// Pack as `(normal, distance)` for use in shaders
+    pub fn to_vec4(&self) -> Vec4 {
+        self.normal.extend(self.distance)
+    }
 }
 
 pub struct Frustum {
@@ -8,8 +8,10 @@ use frustum::FrustumCorners;
 use glam::{Mat4, Vec3, vec3, vec4};
 
 pub mod frustum;
+pub mod path;
 mod perspective;
 
+pub use path::{CameraPath, PathPlayer, PathRecorder};
 pub use perspective::PerspectiveCamera;
 use shaders::Program as _;
 use winit::keyboard::KeyCode;
@@ -37,8 +39,15 @@ pub struct CameraManager {
     frustum_mesh: BasicMesh<line::Vertex>,
 
     camera_matrices_buffer: ShaderBuffer<camera_matrices::uniforms::CameraMatrices>,
+
+    recorder: Option<PathRecorder>,
+    /// Whether the record key was down last frame, so holding it only toggles once
+    record_key_down: bool,
 }
 
+/// Where recorded camera paths are saved
+const CAMERA_PATH_DIR: &str = "camera_paths";
+
 impl CameraManager {
     pub fn bind_camera_uniforms(&self) {
         self.camera_matrices_buffer.bind();
@@ -70,6 +79,11 @@ impl CameraManager {
         self.cameras[self.game_camera].frustum()
     }
 
+    /// Whether the screen is being drawn from the game camera
+    pub fn is_game_active(&self) -> bool {
+        self.active_camera == self.game_camera
+    }
+
     pub fn handle_input(&mut self, keys: &Input, delta: f32) {
         if keys.is_pressed(&KeyCode::Digit1) {
             self.active_camera = self.scene_camera;
@@ -80,6 +94,7 @@ impl CameraManager {
         }
 
         self.active_mut().handle_input(keys, delta);
+        self.record_path(keys, delta);
 
         let active = self.active();
         let projection = active.get_projection();
@@ -98,8 +113,50 @@ impl CameraManager {
         }
     }
 
+    /// F6 starts and stops recording the active camera's path, which is saved when it stops
+    fn record_path(&mut self, keys: &Input, delta: f32) {
+        let key_down = keys.is_pressed(&KeyCode::F6);
+        let toggled = key_down && !self.record_key_down;
+        self.record_key_down = key_down;
+
+        let transform = *self.active().transform();
+
+        if toggled {
+            match self.recorder.take() {
+                Some(recorder) => {
+                    let path = recorder.finish(&transform);
+                    let time = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default();
+                    let file = std::path::PathBuf::from(CAMERA_PATH_DIR)
+                        .join(format!("recording_{}.json", time));
+
+                    match path.save(&file) {
+                        Ok(()) => println!(
+                            "Saved {} keyframes over {:.1}s to {}",
+                            path.keyframes.len(),
+                            path.duration(),
+                            file.display()
+                        ),
+                        Err(e) => eprintln!("Failed to save camera path: {}", e),
+                    }
+                }
+                None => {
+                    println!("Recording camera path, press F6 again to stop");
+                    self.recorder = Some(PathRecorder::new());
+                }
+            }
+        }
+
+        // Deltas are in milliseconds
+        if let Some(recorder) = &mut self.recorder {
+            recorder.update(&transform, delta / 1000.0);
+        }
+    }
+
     pub fn render_gizmos(state: &mut State) {
-        if state.cameras.active_camera == state.cameras.game_camera {
+        if state.cameras.is_game_active() {
             return;
         }
 
@@ -265,6 +322,8 @@ impl Default for CameraManager {
             base_camera_gizmo_mesh: gizmo_mesh,
             frustum_mesh,
             camera_matrices_buffer: cam_buf,
+            recorder: None,
+            record_key_down: false,
         }
     }
 }
@@ -0,0 +1,325 @@
+use std::{fs, io, path::Path};
+
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::Transform;
+
+/// Where a camera is at a point along a [`CameraPath`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Quaternion as `[x, y, z, w]`
+    pub rotation: [f32; 4],
+}
+
+impl Keyframe {
+    pub fn new(time: f32, transform: &Transform) -> Self {
+        Self {
+            time,
+            position: transform.position.to_array(),
+            rotation: transform.rotation.to_array(),
+        }
+    }
+
+    fn position(&self) -> Vec3 {
+        Vec3::from_array(self.position)
+    }
+
+    fn rotation(&self) -> Quat {
+        Quat::from_array(self.rotation).normalize()
+    }
+}
+
+/// Keyframes of a camera flight, stored as JSON.
+/// Positions follow a Catmull-Rom spline through the keyframes and rotations are slerped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(io::Error::other)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    /// Seconds from the first keyframe to the last
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or_default()
+    }
+
+    /// Where the camera is `time` seconds in, held at the ends of the path
+    pub fn sample(&self, time: f32) -> Transform {
+        let keys = &self.keyframes;
+
+        match keys.len() {
+            0 => return Transform::default(),
+            1 => return Transform::new(keys[0].position(), keys[0].rotation()),
+            _ => {}
+        }
+
+        // The segment ending at the first keyframe after `time`
+        let next = keys
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(keys.len() - 1)
+            .max(1);
+        let i = next - 1;
+
+        let (from, to) = (&keys[i], &keys[next]);
+        let span = to.time - from.time;
+        let t = if span > 0.0 {
+            ((time - from.time) / span).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        // The ends are repeated so the spline still passes through the first and last keys
+        let before = &keys[i.saturating_sub(1)];
+        let after = &keys[(next + 1).min(keys.len() - 1)];
+
+        let position = catmull_rom(
+            before.position(),
+            from.position(),
+            to.position(),
+            after.position(),
+            t,
+        );
+        let rotation = from.rotation().slerp(to.rotation(), t);
+
+        Transform::new(position, rotation)
+    }
+}
+
+/// Uniform Catmull-Rom between `p1` and `p2`
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Plays a [`CameraPath`] back at a fixed timestep, so every run sees exactly the same frames
+/// no matter how long each one takes
+#[derive(Debug, Clone)]
+pub struct PathPlayer {
+    path: CameraPath,
+    /// Counted in frames rather than summing timesteps, so no error builds up
+    frame: usize,
+    /// Seconds the path moves on each frame
+    timestep: f32,
+    looping: bool,
+}
+
+impl PathPlayer {
+    pub fn new(path: CameraPath, timestep: f32, looping: bool) -> Self {
+        Self {
+            path,
+            frame: 0,
+            timestep,
+            looping,
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.frame = 0;
+    }
+
+    /// Where the camera is this frame, then move on a timestep
+    pub fn step(&mut self) -> Transform {
+        let transform = self.path.sample(self.frame as f32 * self.timestep);
+
+        self.frame += 1;
+        if self.looping && self.frame >= self.frames() {
+            self.frame = 0;
+        }
+
+        transform
+    }
+
+    /// Whether every frame of the path has been played
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.frame >= self.frames()
+    }
+
+    /// Frames it takes to play the whole path
+    pub fn frames(&self) -> usize {
+        (self.path.duration() / self.timestep).floor() as usize + 1
+    }
+}
+
+/// Seconds between keyframes while recording
+const RECORD_INTERVAL: f32 = 0.25;
+
+/// Builds a [`CameraPath`] from where the camera goes while it is flown around
+#[derive(Debug, Default)]
+pub struct PathRecorder {
+    path: CameraPath,
+    time: f32,
+    last_key: Option<f32>,
+}
+
+impl PathRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move on `delta` seconds, keeping a keyframe whenever enough time has passed
+    pub fn update(&mut self, transform: &Transform, delta: f32) {
+        if self
+            .last_key
+            .is_some_and(|last| self.time - last < RECORD_INTERVAL)
+        {
+            self.time += delta;
+            return;
+        }
+
+        self.path
+            .keyframes
+            .push(Keyframe::new(self.time, transform));
+        self.last_key = Some(self.time);
+        self.time += delta;
+    }
+
+    /// The path so far, ending where the camera is now
+    pub fn finish(mut self, transform: &Transform) -> CameraPath {
+        if self.last_key != Some(self.time) {
+            self.path
+                .keyframes
+                .push(Keyframe::new(self.time, transform));
+        }
+
+        self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn key(time: f32, x: f32, rotation: Quat) -> Keyframe {
+        Keyframe::new(time, &Transform::new(Vec3::new(x, 1.0, 2.0), rotation))
+    }
+
+    /// Evenly spaced keys along a line, turning a quarter turn over the first second
+    fn line() -> CameraPath {
+        let turned = Quat::from_rotation_y(FRAC_PI_2);
+
+        CameraPath {
+            keyframes: vec![
+                key(0.0, 0.0, Quat::IDENTITY),
+                key(1.0, 10.0, turned),
+                key(2.0, 20.0, turned),
+                key(3.0, 30.0, turned),
+            ],
+        }
+    }
+
+    fn assert_at(transform: Transform, x: f32, rotation: Quat) {
+        let expected = Vec3::new(x, 1.0, 2.0);
+
+        assert!(
+            transform.position.abs_diff_eq(expected, 1e-4),
+            "{} != {}",
+            transform.position,
+            expected
+        );
+        assert!(transform.rotation.abs_diff_eq(rotation, 1e-4));
+    }
+
+    #[test]
+    fn passes_through_every_key() {
+        let path = line();
+
+        for key in &path.keyframes {
+            assert_at(path.sample(key.time), key.position[0], key.rotation());
+        }
+    }
+
+    #[test]
+    fn even_keys_on_a_line_move_at_a_constant_speed() {
+        let path = line();
+        let turned = Quat::from_rotation_y(FRAC_PI_2);
+
+        // Only away from the ends, where the repeated end key eases the spline in and out
+        assert_at(path.sample(1.5), 15.0, turned);
+        assert_at(path.sample(1.25), 12.5, turned);
+
+        let end = path.sample(2.5).position.x;
+        assert!(end > 20.0 && end < 30.0);
+    }
+
+    #[test]
+    fn rotation_turns_evenly_between_keys() {
+        let halfway = line().sample(0.5).rotation;
+
+        assert!(halfway.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2 / 2.0), 1e-4));
+    }
+
+    #[test]
+    fn holds_at_the_ends() {
+        let path = line();
+        let turned = Quat::from_rotation_y(FRAC_PI_2);
+
+        assert_eq!(path.duration(), 3.0);
+        assert_at(path.sample(3.0), 30.0, turned);
+        assert_at(path.sample(10.0), 30.0, turned);
+        assert_at(path.sample(-1.0), 0.0, Quat::IDENTITY);
+    }
+
+    #[test]
+    fn keys_at_the_same_time_jump_to_the_later() {
+        let path = CameraPath {
+            keyframes: vec![
+                key(0.0, 0.0, Quat::IDENTITY),
+                key(1.0, 5.0, Quat::IDENTITY),
+                key(1.0, 8.0, Quat::IDENTITY),
+            ],
+        };
+
+        assert_at(path.sample(1.0), 8.0, Quat::IDENTITY);
+        assert_at(path.sample(2.0), 8.0, Quat::IDENTITY);
+    }
+
+    #[test]
+    fn too_few_keys() {
+        assert_eq!(CameraPath::default().sample(1.0), Transform::default());
+
+        let single = CameraPath {
+            keyframes: vec![key(2.0, 4.0, Quat::IDENTITY)],
+        };
+        assert_at(single.sample(0.0), 4.0, Quat::IDENTITY);
+        assert_at(single.sample(5.0), 4.0, Quat::IDENTITY);
+    }
+
+    #[test]
+    fn player_plays_each_timestep_once() {
+        let mut player = PathPlayer::new(line(), 0.5, false);
+
+        assert_eq!(player.frames(), 7);
+
+        let xs = std::iter::from_fn(|| (!player.is_finished()).then(|| player.step()))
+            .map(|t| t.position.x)
+            .collect::<Vec<_>>();
+
+        assert_eq!(xs.len(), 7);
+        assert!((xs[6] - 30.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,427 @@
+use glam::{Mat4, Vec3, vec3};
+
+use crate::{
+    ComputeProgram,
+    buffers::{Buffer, BufferMode, GpuBuffer, RawBuffer, ReadBack},
+    framebuffer::Framebuffer,
+};
+
+const BUILD_GROUP_SIZE: u32 = 8;
+/// Levels at or below this width are read back for CPU tests
+const CPU_LEVEL_WIDTH: u32 = 64;
+
+/// Bind points of the pyramid buffers, must match the snippets below
+const DEPTHS_BIND: u32 = 9;
+const LEVELS_BIND: u32 = 10;
+const PARAMS_BIND: u32 = 11;
+const BUILD_BIND: u32 = 12;
+
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    offset: u32,
+    width: u32,
+    height: u32,
+}
+
+/// A coarse level read back for CPU tests, with the matrix it was drawn with
+struct CpuLevel {
+    level: Level,
+    view_projection: Mat4,
+    depths: Vec<f32>,
+}
+
+/// A max depth pyramid built from the last captured depth buffer,
+/// used to cull bounds hidden behind what was drawn last frame.
+pub struct DepthPyramid {
+    depths: GpuBuffer,
+    levels: GpuBuffer,
+    params: GpuBuffer,
+    build: GpuBuffer,
+    level_info: Vec<Level>,
+    view_projection: Mat4,
+    cpu_level: Option<CpuLevel>,
+    read_backs: ReadBack<(Level, Mat4)>,
+    valid: bool,
+}
+
+impl DepthPyramid {
+    pub fn new() -> Self {
+        let mut params = GpuBuffer::empty(
+            std::mem::size_of::<[f32; 16]>() + std::mem::size_of::<[i32; 4]>(),
+            BufferMode::Default,
+        )
+        .expect("Failed to make depth pyramid params buffer");
+        params.set_label("Depth pyramid params");
+
+        let mut build = GpuBuffer::empty(std::mem::size_of::<i32>(), BufferMode::Default)
+            .expect("Failed to make depth pyramid build buffer");
+        build.set_label("Depth pyramid build level");
+
+        let mut pyramid = Self {
+            depths: GpuBuffer::empty(0, BufferMode::Default)
+                .expect("Failed to make depth pyramid buffer"),
+            levels: GpuBuffer::empty(0, BufferMode::Default)
+                .expect("Failed to make depth pyramid level buffer"),
+            params,
+            build,
+            level_info: vec![],
+            view_projection: Mat4::IDENTITY,
+            cpu_level: None,
+            read_backs: ReadBack::new(),
+            valid: false,
+        };
+        pyramid.write_params();
+
+        pyramid
+    }
+
+    /// Drop the current pyramid, for when the depth buffer was drawn from another view
+    pub fn invalidate(&mut self) {
+        if self.valid {
+            self.valid = false;
+            self.cpu_level = None;
+            self.read_backs.discard();
+            self.write_params();
+        }
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if self
+            .level_info
+            .first()
+            .is_some_and(|l| l.width == width && l.height == height)
+        {
+            return;
+        }
+
+        self.level_info.clear();
+
+        let (mut w, mut h) = (width, height);
+        let mut offset = 0;
+        loop {
+            self.level_info.push(Level {
+                offset,
+                width: w,
+                height: h,
+            });
+            offset += w * h;
+
+            if w == 1 && h == 1 {
+                break;
+            }
+            w = w.div_ceil(2);
+            h = h.div_ceil(2);
+        }
+
+        let size = offset as usize * std::mem::size_of::<f32>();
+        self.depths = GpuBuffer::empty(size, BufferMode::Default)
+            .expect("Failed to make depth pyramid buffer");
+        self.depths.set_label("Depth pyramid");
+
+        let levels = self
+            .level_info
+            .iter()
+            .map(|l| [l.offset as i32, l.width as i32, l.height as i32, 0])
+            .collect::<Vec<_>>();
+        self.levels = GpuBuffer::with_data(&levels, BufferMode::Default)
+            .expect("Failed to make depth pyramid level buffer");
+        self.levels.set_label("Depth pyramid levels");
+    }
+
+    /// Copy the bound depth buffer into the pyramid and build its levels.
+    /// `view_projection` must be the matrix the depth buffer was drawn with.
+    /// If `read_back` is set, a coarse level is copied back for [`DepthPyramid::is_visible`],
+    /// which tests against it a frame or two later once the GPU has finished with it.
+    pub fn capture(&mut self, width: u32, height: u32, view_projection: Mat4, read_back: bool) {
+        crate::profiler::event!("Build depth pyramid");
+
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.resize(width, height);
+
+        Framebuffer::unbind_read();
+
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.depths.id());
+            gl::ReadPixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::DEPTH_COMPONENT,
+                gl::FLOAT,
+                std::ptr::null_mut(),
+            );
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+
+        self.bind_pyramid();
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, BUILD_BIND, self.build.id());
+        }
+
+        let program = hiz_build::build_level::get();
+        for (i, level) in self.level_info.iter().enumerate().skip(1) {
+            if let Err(e) = self.build.set_data(&[i as i32]) {
+                eprintln!("Error setting depth pyramid level: {:?}", e);
+                return;
+            }
+
+            program.dispatch_with_barrier(
+                level.width.div_ceil(BUILD_GROUP_SIZE),
+                level.height.div_ceil(BUILD_GROUP_SIZE),
+                1,
+                gl::SHADER_STORAGE_BARRIER_BIT,
+            );
+        }
+
+        if read_back {
+            self.read_level(view_projection);
+        } else {
+            self.cpu_level = None;
+            self.read_backs.discard();
+        }
+
+        self.view_projection = view_projection;
+        self.valid = true;
+        self.write_params();
+    }
+
+    /// Start copying back the coarse level, and take the newest copy that has finished
+    fn read_level(&mut self, view_projection: Mat4) {
+        crate::profiler::event!("Read back depth pyramid");
+
+        if let Some(&level) = self.level_info.iter().find(|l| l.width <= CPU_LEVEL_WIDTH) {
+            self.read_backs.copy(
+                self.depths.id(),
+                level.offset as usize * std::mem::size_of::<f32>(),
+                (level.width * level.height) as usize * std::mem::size_of::<f32>(),
+                (level, view_projection),
+            );
+        }
+
+        if let Some(((level, view_projection), depths)) = self.read_backs.poll() {
+            self.cpu_level = Some(CpuLevel {
+                level,
+                view_projection,
+                depths,
+            });
+        }
+    }
+
+    fn write_params(&mut self) {
+        let info = [self.level_info.len() as i32, self.valid as i32, 0, 0];
+        let matrix = self.view_projection.to_cols_array();
+
+        // Written through the command stream, so draws already queued keep the old values
+        if let Err(e) = self.params.set_offset_data(0, &matrix) {
+            eprintln!("Error setting depth pyramid matrix: {:?}", e);
+        }
+        if let Err(e) = self
+            .params
+            .set_offset_data(std::mem::size_of_val(&matrix), &info)
+        {
+            eprintln!("Error setting depth pyramid info: {:?}", e);
+        }
+    }
+
+    fn bind_pyramid(&self) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, DEPTHS_BIND, self.depths.id());
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, LEVELS_BIND, self.levels.id());
+        }
+    }
+
+    /// Bind the pyramid for use with the `hiz_test` snippet
+    pub fn bind(&self) {
+        self.bind_pyramid();
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, PARAMS_BIND, self.params.id());
+        }
+    }
+
+    /// Test bounds against the read back level, matching `hiz_visible` in the shader.
+    /// Always visible if nothing has been read back.
+    pub fn is_visible(&self, min: Vec3, max: Vec3) -> bool {
+        let Some(CpuLevel {
+            level,
+            view_projection,
+            depths,
+        }) = self.cpu_level.as_ref().filter(|_| self.valid)
+        else {
+            return true;
+        };
+
+        let mut min_uv = glam::Vec2::ONE;
+        let mut max_uv = glam::Vec2::ZERO;
+        let mut min_depth = 1.0f32;
+
+        for i in 0..8 {
+            let corner = vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+
+            let clip = *view_projection * corner.extend(1.0);
+
+            // Crosses the near plane, so can't be projected
+            if clip.w <= 0.0 {
+                return true;
+            }
+
+            let ndc = clip.truncate() / clip.w;
+            let uv = ndc.truncate() * 0.5 + 0.5;
+
+            min_uv = min_uv.min(uv);
+            max_uv = max_uv.max(uv);
+            min_depth = min_depth.min(ndc.z * 0.5 + 0.5);
+        }
+
+        // Off screen bounds are left to frustum culling
+        if max_uv.cmplt(glam::Vec2::ZERO).any() || min_uv.cmpgt(glam::Vec2::ONE).any() {
+            return true;
+        }
+
+        let min_uv = min_uv.clamp(glam::Vec2::ZERO, glam::Vec2::ONE);
+        let max_uv = max_uv.clamp(glam::Vec2::ZERO, glam::Vec2::ONE);
+
+        let x0 = (min_uv.x * level.width as f32) as u32;
+        let y0 = (min_uv.y * level.height as f32) as u32;
+        let x1 = ((max_uv.x * level.width as f32) as u32).min(level.width - 1);
+        let y1 = ((max_uv.y * level.height as f32) as u32).min(level.height - 1);
+
+        let mut max_depth = 0.0f32;
+        for y in y0.min(y1)..=y1 {
+            for x in x0.min(x1)..=x1 {
+                max_depth = max_depth.max(depths[(y * level.width + x) as usize]);
+            }
+        }
+
+        min_depth <= max_depth
+    }
+}
+
+impl Default for DepthPyramid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+crate::snippet!(hiz_depths, {
+    #bind 9
+    buffer HizDepths {
+        float depths[];
+    };
+
+    // x: offset, y: width, z: height
+    #bind 10
+    buffer HizLevels {
+        ivec4 levels[];
+    };
+
+    float hiz_sample(int level, int x, int y) {
+        ivec4 info_level = levels[level];
+
+        int cx = clamp(x, 0, info_level.y - 1);
+        int cy = clamp(y, 0, info_level.z - 1);
+
+        return depths[info_level.x + cy * info_level.y + cx];
+    }
+}, true);
+
+// Needs hiz_depths to be included first
+crate::snippet!(hiz_test, {
+    #bind 11
+    buffer HizParams {
+        mat4 hiz_view_projection;
+        // x: level count, y: valid
+        ivec4 hiz_info;
+    };
+
+    bool hiz_visible(vec3 min_pos, vec3 max_pos) {
+        if (hiz_info.y == 0) {
+            return true;
+        }
+
+        vec2 min_uv = vec2(1.0, 1.0);
+        vec2 max_uv = vec2(0.0, 0.0);
+        float min_depth = 1.0;
+
+        for (int i = 0; i < 8; i++) {
+            vec3 corner = vec3(
+                ((i & 1) == 0) ? min_pos.x : max_pos.x,
+                ((i & 2) == 0) ? min_pos.y : max_pos.y,
+                ((i & 4) == 0) ? min_pos.z : max_pos.z
+            );
+
+            vec4 clip = hiz_view_projection * vec4(corner, 1.0);
+
+            // Crosses the near plane, so can't be projected
+            if (clip.w <= 0.0) {
+                return true;
+            }
+
+            vec3 ndc = clip.xyz / clip.w;
+            vec2 uv = ndc.xy * 0.5 + 0.5;
+
+            min_uv = min(min_uv, uv);
+            max_uv = max(max_uv, uv);
+            min_depth = min(min_depth, ndc.z * 0.5 + 0.5);
+        }
+
+        min_uv = clamp(min_uv, vec2(0.0, 0.0), vec2(1.0, 1.0));
+        max_uv = clamp(max_uv, vec2(0.0, 0.0), vec2(1.0, 1.0));
+
+        ivec4 base = levels[0];
+        vec2 size = (max_uv - min_uv) * vec2(float(base.y), float(base.z));
+
+        // Pick the level where the bounds cover at most 2x2 texels
+        int level = clamp(int(ceil(log2(max(max(size.x, size.y), 1.0)))), 0, hiz_info.x - 1);
+
+        int x0 = (int(min_uv.x * float(base.y)) >> level);
+        int y0 = (int(min_uv.y * float(base.z)) >> level);
+        int x1 = (int(max_uv.x * float(base.y)) >> level);
+        int y1 = (int(max_uv.y * float(base.z)) >> level);
+
+        float max_depth = max(
+            max(hiz_sample(level, x0, y0), hiz_sample(level, x1, y0)),
+            max(hiz_sample(level, x0, y1), hiz_sample(level, x1, y1))
+        );
+
+        return (min_depth <= max_depth);
+    }
+}, true);
+
+crate::compute!(hiz_build, {
+    #snippet crate::hiz::hiz_depths
+
+    #bind 12
+    buffer HizBuild {
+        int level;
+    };
+
+    #kernel build_level
+    #size 8 8 1
+    void build_level() {
+        ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+        ivec4 dst = levels[level];
+
+        if (pos.x >= dst.y || pos.y >= dst.z) {
+            return;
+        }
+
+        int src = level - 1;
+        int sx = pos.x * 2;
+        int sy = pos.y * 2;
+
+        float depth = max(
+            max(hiz_sample(src, sx, sy), hiz_sample(src, sx + 1, sy)),
+            max(hiz_sample(src, sx, sy + 1), hiz_sample(src, sx + 1, sy + 1))
+        );
+
+        depths[dst.x + pos.y * dst.y + pos.x] = depth;
+    }
+}, true);
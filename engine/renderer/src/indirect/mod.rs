@@ -1,3 +1,7 @@
+use std::{ffi::c_void, sync::OnceLock};
+
+use gl::types::{GLenum, GLintptr, GLsizei};
+
 #[derive(Debug, Clone)]
 pub struct DrawArraysIndirectCommand {
     pub vertex_count: u32,
@@ -5,3 +9,69 @@ pub struct DrawArraysIndirectCommand {
     pub first: u32,
     pub base_instance: u32,
 }
+
+/// `GL_PARAMETER_BUFFER`, GL 4.6 and `ARB_indirect_parameters` aren't in the generated bindings
+pub const PARAMETER_BUFFER: GLenum = 0x80EE;
+
+type MultiDrawArraysIndirectCount =
+    extern "system" fn(GLenum, *const c_void, GLintptr, GLsizei, GLsizei);
+
+static MULTI_DRAW_ARRAYS_INDIRECT_COUNT: OnceLock<Option<MultiDrawArraysIndirectCount>> =
+    OnceLock::new();
+
+/// Load the entry points missing from the bindings, alongside `gl::load_with`
+pub fn load_with(mut loader: impl FnMut(&str) -> *const c_void) {
+    let function = [
+        "glMultiDrawArraysIndirectCount",
+        "glMultiDrawArraysIndirectCountARB",
+    ]
+    .into_iter()
+    .map(&mut loader)
+    .find(|f| !f.is_null())
+    .map(|f| unsafe { std::mem::transmute::<*const c_void, MultiDrawArraysIndirectCount>(f) });
+
+    if function.is_none() {
+        eprintln!("MultiDrawArraysIndirectCount unavailable, drawing every indirect command");
+    }
+
+    let _ = MULTI_DRAW_ARRAYS_INDIRECT_COUNT.set(function);
+}
+
+/// Whether draws can take their count from [`PARAMETER_BUFFER`]
+pub fn has_indirect_count() -> bool {
+    MULTI_DRAW_ARRAYS_INDIRECT_COUNT
+        .get()
+        .is_some_and(Option::is_some)
+}
+
+/// Draw the commands in the bound `DRAW_INDIRECT_BUFFER`, reading the count from the uint at
+/// `count_offset` in the bound [`PARAMETER_BUFFER`].
+/// Without the GL 4.6 entry point all `max_draws` commands are drawn instead,
+/// so the ones past the count must have an instance count of zero.
+///
+/// # Safety
+/// Both buffers must be bound and hold at least `max_draws` commands and the count.
+pub unsafe fn multi_draw_arrays_indirect_count(
+    mode: GLenum,
+    count_offset: usize,
+    max_draws: usize,
+    stride: usize,
+) {
+    match MULTI_DRAW_ARRAYS_INDIRECT_COUNT.get().copied().flatten() {
+        Some(draw) => draw(
+            mode,
+            std::ptr::null(),
+            count_offset as GLintptr,
+            max_draws as GLsizei,
+            stride as GLsizei,
+        ),
+        None => unsafe {
+            gl::MultiDrawArraysIndirect(
+                mode,
+                std::ptr::null(),
+                max_draws as GLsizei,
+                stride as GLsizei,
+            )
+        },
+    }
+}
@@ -23,25 +23,28 @@ mod uniforms;
 pub use optick as profiler;
 
 #[cfg(not(target_os = "windows"))]
-pub use linux_optick_fake as profiler;
+pub use scope_profiler as profiler;
 
 pub mod bounds;
 pub mod buffers;
 pub mod camera;
 pub mod fence;
 pub mod framebuffer;
+pub mod hiz;
 pub mod indices;
 pub mod indirect;
-pub mod linux_optick_fake;
 pub mod math;
 pub mod mesh;
+pub mod query;
+pub mod scope_profiler;
 pub mod texture;
+pub mod timer;
 pub mod vertex;
 
 pub use enums::*;
 pub use input::{Input, PositionDelta};
 pub use render_common::*;
-pub use state::State;
+pub use state::{ChunkTimes, FrameStats, MeshingStats, RenderPass, RenderStats, State};
 pub mod draw;
 pub use ::shaders::{
     ComputeProgram, ComputeProgramInternal, Program as ProgramSource, ProgramInternal, compute,
@@ -144,12 +147,12 @@ pub fn make_window(event_loop: &ActiveEventLoop) -> Display {
         let symbol = CString::new(symbol).unwrap();
         display.get_proc_address(symbol.as_c_str()).cast()
     });
+    indirect::load_with(|symbol| {
+        let symbol = CString::new(symbol).unwrap();
+        display.get_proc_address(symbol.as_c_str()).cast()
+    });
 
-    unsafe {
-        let ptr = gl::GetString(gl::VERSION) as *const i8;
-        let c_str = CStr::from_ptr(ptr);
-        println!("OpenGL version: {}", c_str.to_str().unwrap());
-    };
+    println!("OpenGL version: {}", gl_string(gl::VERSION));
 
     let swap_interval = glutin::surface::SwapInterval::DontWait;
 
@@ -184,6 +187,11 @@ pub fn make_window(event_loop: &ActiveEventLoop) -> Display {
 pub trait Renderable {
     fn render(&mut self, state: &mut State);
     fn args(&mut self, args: &Args);
+
+    /// How long meshing took, for renderables that mesh voxels
+    fn meshing_stats(&self) -> Option<MeshingStats> {
+        None
+    }
 }
 
 extern "system" fn gl_error_callback(
@@ -237,3 +245,35 @@ extern "system" fn gl_error_callback(
         source, ty, id, severity, message
     );
 }
+
+/// Driver of the current context, recorded alongside benchmark results
+#[derive(Debug, Clone, Default)]
+pub struct GlInfo {
+    pub vendor: String,
+    pub renderer: String,
+    pub version: String,
+}
+
+impl GlInfo {
+    /// Needs a current context, so only call after a window has been made
+    pub fn current() -> Self {
+        Self {
+            vendor: gl_string(gl::VENDOR),
+            renderer: gl_string(gl::RENDERER),
+            version: gl_string(gl::VERSION),
+        }
+    }
+}
+
+fn gl_string(name: gl::types::GLenum) -> String {
+    unsafe {
+        let ptr = gl::GetString(name);
+        if ptr.is_null() {
+            return String::new();
+        }
+
+        CStr::from_ptr(ptr as *const std::ffi::c_char)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -111,6 +111,11 @@ where
         Ok(())
     }
 
+    /// Drop the instance buffers, so nothing is drawn until instances are set again
+    pub fn clear_instances(&mut self) {
+        self.instance_buffers.clear();
+    }
+
     fn pre_draw(&mut self) -> (&Vao<V, I>, &mut Vbo<I>) {
         let idx = self.get_first_available();
         let last = self.instance_buffers.len() - 1;
@@ -132,6 +137,71 @@ where
     fn is_on_frustum(&self, frustum: &crate::camera::frustum::Frustum) -> bool {
         self.bounds.intersects(frustum).into()
     }
+
+    /// Draw only the `(first, count)` ranges of instances, clamped to what has been set
+    pub fn render_ranges(
+        &mut self,
+        frustum: &crate::camera::frustum::Frustum,
+        ranges: &[(usize, usize)],
+    ) {
+        if self.instance_buffers.is_empty() {
+            return;
+        }
+
+        if self.frustum_cull && !self.is_on_frustum(frustum) {
+            return;
+        }
+
+        let vertex_count = self.vertex_count() as i32;
+        let mode = self.draw_mode().into();
+
+        if vertex_count <= 0 {
+            return;
+        }
+
+        let indices = self.has_indices();
+        let (vao, buffer) = self.pre_draw();
+        let instances = buffer.count();
+
+        vao.bind();
+
+        if instances == 0 {
+            return;
+        }
+
+        for (first, count) in ranges {
+            let count = (*count).min(instances.saturating_sub(*first)) as i32;
+
+            if count <= 0 {
+                continue;
+            }
+
+            if indices {
+                unsafe {
+                    gl::DrawElementsInstancedBaseInstance(
+                        mode,
+                        vertex_count,
+                        gl::UNSIGNED_INT,
+                        std::ptr::null(),
+                        count,
+                        *first as u32,
+                    );
+                }
+            } else {
+                unsafe {
+                    gl::DrawArraysInstancedBaseInstance(
+                        mode,
+                        0,
+                        vertex_count,
+                        count,
+                        *first as u32,
+                    );
+                }
+            }
+        }
+
+        buffer.start_fence();
+    }
 }
 
 impl<V, I> Mesh<V, I> for NInstancedMesh<V, I>
@@ -172,47 +242,6 @@ where
     }
 
     fn render(&mut self, frustum: &crate::camera::frustum::Frustum) {
-        if self.instance_buffers.is_empty() {
-            return;
-        }
-
-        if self.frustum_cull && !self.is_on_frustum(frustum) {
-            return;
-        }
-
-        let vertex_count = self.vertex_count() as i32;
-        let mode = self.draw_mode().into();
-
-        if vertex_count <= 0 {
-            return;
-        }
-
-        let indices = self.has_indices();
-        let (vao, buffer) = self.pre_draw();
-        let instances = buffer.count() as i32;
-
-        vao.bind();
-
-        if instances <= 0 {
-            return;
-        }
-
-        if indices {
-            unsafe {
-                gl::DrawElementsInstanced(
-                    mode,
-                    vertex_count,
-                    gl::UNSIGNED_INT,
-                    std::ptr::null(),
-                    instances,
-                );
-            }
-        } else {
-            unsafe {
-                gl::DrawArraysInstanced(mode, 0, vertex_count, instances);
-            }
-        }
-
-        buffer.start_fence();
+        self.render_ranges(frustum, &[(0, usize::MAX)]);
     }
 }
@@ -0,0 +1,234 @@
+use glam::Vec3;
+
+use crate::{ProgramSource, Uniforms, buffers::BlankVao};
+
+/// What a [`Query`] measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryTarget {
+    SamplesPassed,
+    AnySamplesPassed,
+    AnySamplesPassedConservative,
+    TimeElapsed,
+}
+
+impl From<QueryTarget> for gl::types::GLenum {
+    fn from(value: QueryTarget) -> Self {
+        match value {
+            QueryTarget::SamplesPassed => gl::SAMPLES_PASSED,
+            QueryTarget::AnySamplesPassed => gl::ANY_SAMPLES_PASSED,
+            QueryTarget::AnySamplesPassedConservative => gl::ANY_SAMPLES_PASSED_CONSERVATIVE,
+            QueryTarget::TimeElapsed => gl::TIME_ELAPSED,
+        }
+    }
+}
+
+/// What conditional rendering does while a query's result isn't ready
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalMode {
+    /// The GPU waits for the result
+    Wait,
+    /// Draw as if the query passed
+    NoWait,
+    ByRegionWait,
+    ByRegionNoWait,
+}
+
+impl From<ConditionalMode> for gl::types::GLenum {
+    fn from(value: ConditionalMode) -> Self {
+        match value {
+            ConditionalMode::Wait => gl::QUERY_WAIT,
+            ConditionalMode::NoWait => gl::QUERY_NO_WAIT,
+            ConditionalMode::ByRegionWait => gl::QUERY_BY_REGION_WAIT,
+            ConditionalMode::ByRegionNoWait => gl::QUERY_BY_REGION_NO_WAIT,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Query {
+    id: gl::types::GLuint,
+    target: QueryTarget,
+}
+
+impl Query {
+    pub fn new(target: QueryTarget) -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::CreateQueries(target.into(), 1, &mut id);
+        }
+
+        Self { id, target }
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.id
+    }
+
+    pub fn target(&self) -> QueryTarget {
+        self.target
+    }
+
+    /// Start counting, only one query of each target can be active at once
+    pub fn begin(&self) {
+        unsafe {
+            gl::BeginQuery(self.target.into(), self.id);
+        }
+    }
+
+    pub fn end(&self) {
+        unsafe {
+            gl::EndQuery(self.target.into());
+        }
+    }
+
+    /// Whether the result can be read without stalling
+    pub fn is_available(&self) -> bool {
+        let mut available = 0;
+        unsafe {
+            gl::GetQueryObjectuiv(self.id, gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+
+        available != 0
+    }
+
+    /// The result, if it is ready
+    pub fn try_result(&self) -> Option<u64> {
+        self.is_available().then(|| self.result())
+    }
+
+    /// The result, waiting for the GPU if it isn't ready
+    pub fn result(&self) -> u64 {
+        let mut result = 0;
+        unsafe {
+            gl::GetQueryObjectui64v(self.id, gl::QUERY_RESULT, &mut result);
+        }
+
+        result
+    }
+
+    /// Only keep the draws made in `f` if this query saw any samples pass
+    pub fn conditional<R>(&self, mode: ConditionalMode, f: impl FnOnce() -> R) -> R {
+        unsafe {
+            gl::BeginConditionalRender(self.id, mode.into());
+        }
+
+        let result = f();
+
+        unsafe {
+            gl::EndConditionalRender();
+        }
+
+        result
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteQueries(1, &self.id);
+        }
+    }
+}
+
+/// Draws boxes into occlusion queries without writing colour or depth.
+/// The camera uniforms must already be bound.
+pub struct OcclusionBoxes {
+    vao: BlankVao,
+}
+
+impl OcclusionBoxes {
+    pub fn new() -> Self {
+        Self {
+            vao: BlankVao::new(),
+        }
+    }
+
+    /// Count the samples of the box `min` to `max` that pass the depth test into `query`
+    pub fn query(&self, query: &Query, min: Vec3, max: Vec3) {
+        let program = occlusion_box::Program::get();
+        program.bind();
+
+        let uniforms = occlusion_box::Uniforms {
+            box_min: min.to_array(),
+            box_max: max.to_array(),
+        };
+        uniforms.bind(&program);
+
+        self.vao.bind();
+
+        // Put the masks back as they were, as a depth prepass may have changed them
+        let mut color_mask = [gl::TRUE; 4];
+        let mut depth_mask = gl::TRUE;
+
+        unsafe {
+            gl::GetBooleanv(gl::COLOR_WRITEMASK, color_mask.as_mut_ptr());
+            gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_mask);
+
+            gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+            gl::DepthMask(gl::FALSE);
+            // Back faces still count if the front ones are clipped by the near plane
+            gl::Disable(gl::CULL_FACE);
+        }
+
+        query.begin();
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+        }
+        query.end();
+
+        unsafe {
+            let [r, g, b, a] = color_mask;
+            gl::ColorMask(r, g, b, a);
+            gl::DepthMask(depth_mask);
+            gl::Enable(gl::CULL_FACE);
+        }
+    }
+}
+
+impl Default for OcclusionBoxes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+crate::program!(occlusion_box, {
+    #vertex vert
+    #fragment frag
+
+    #snippet crate::camera_matrices
+
+    uniform vec3 box_min;
+    uniform vec3 box_max;
+
+    const vec3 corners[8] = vec3[](
+        vec3(0.0, 0.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(1.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 1.0),
+        vec3(1.0, 1.0, 1.0)
+    );
+
+    const int indices[36] = int[](
+        0, 2, 3, 3, 1, 0,
+        4, 5, 7, 7, 6, 4,
+        0, 4, 6, 6, 2, 0,
+        1, 3, 7, 7, 5, 1,
+        0, 1, 5, 5, 4, 0,
+        2, 6, 7, 7, 3, 2
+    );
+
+    void vert() {
+        mat4 vp = camera.projection * camera.inverse_view;
+
+        vec3 corner = corners[indices[gl_VertexID]];
+
+        gl_Position = vp * vec4(mix(box_min, box_max, corner), 1.0);
+    }
+
+    vec4 frag() {
+        return vec4(1.0);
+    }
+}, true);
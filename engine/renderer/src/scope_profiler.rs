@@ -0,0 +1,262 @@
+//! Records nested CPU scopes on every thread while capturing, and writes them out as a
+//! Chrome trace that `chrome://tracing` or Perfetto can open.
+//! Has the same interface as optick, which Windows uses instead,
+//! so [`event!`] is written the same everywhere.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use serde_json::{Value, json};
+
+/// Time the rest of the enclosing block, named after the function if no name is given
+#[macro_export]
+macro_rules! event {
+    () => {
+        let _scope = $crate::scope_profiler::Scope::begin({
+            fn f() {}
+            let name = ::std::any::type_name_of_val(&f);
+            &name[..name.len() - 3]
+        });
+    };
+    ($str: literal) => {
+        let _scope = $crate::scope_profiler::Scope::begin($str);
+    };
+}
+
+pub use crate::event;
+
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static FRAME: AtomicU64 = AtomicU64::new(0);
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+static THREADS: Mutex<Vec<Arc<Mutex<ThreadEvents>>>> = Mutex::new(Vec::new());
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static EVENTS: Arc<Mutex<ThreadEvents>> = register_thread();
+}
+
+struct Capture {
+    start: Instant,
+    frame_start: Instant,
+    /// Start and end of every finished frame
+    frames: Vec<(u64, Instant, Instant)>,
+}
+
+struct ScopeEvent {
+    name: &'static str,
+    frame: u64,
+    start: Instant,
+    end: Instant,
+}
+
+/// Scopes finished on one thread, kept per thread so recording never waits on another
+struct ThreadEvents {
+    id: u64,
+    name: String,
+    events: Vec<ScopeEvent>,
+}
+
+fn register_thread() -> Arc<Mutex<ThreadEvents>> {
+    let id = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+    let name = std::thread::current()
+        .name()
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("Thread {}", id));
+
+    let events = Arc::new(Mutex::new(ThreadEvents {
+        id,
+        name,
+        events: vec![],
+    }));
+    THREADS.lock().unwrap().push(events.clone());
+
+    events
+}
+
+/// A scope being timed, recorded when dropped
+pub struct Scope {
+    name: &'static str,
+    frame: u64,
+    start: Instant,
+}
+
+impl Scope {
+    /// Start timing, or nothing if there is no capture running
+    pub fn begin(name: &'static str) -> Option<Self> {
+        if !CAPTURING.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(Self {
+            name,
+            frame: FRAME.load(Ordering::Relaxed),
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let event = ScopeEvent {
+            name: self.name,
+            frame: self.frame,
+            start: self.start,
+            end: Instant::now(),
+        };
+
+        // Only fails while the thread is exiting, when there is nowhere left to put it
+        let _ = EVENTS.try_with(|events| events.lock().unwrap().events.push(event));
+    }
+}
+
+/// Start recording scopes, throwing away anything from an earlier capture
+pub fn start_capture() {
+    for thread in THREADS.lock().unwrap().iter() {
+        thread.lock().unwrap().events.clear();
+    }
+
+    let now = Instant::now();
+    *CAPTURE.lock().unwrap() = Some(Capture {
+        start: now,
+        frame_start: now,
+        frames: vec![],
+    });
+
+    FRAME.store(0, Ordering::Relaxed);
+    CAPTURING.store(true, Ordering::Relaxed);
+}
+
+/// Stop recording and write everything captured to `name` with `.json` added
+pub fn stop_capture(name: &str) {
+    CAPTURING.store(false, Ordering::Relaxed);
+
+    let Some(mut capture) = CAPTURE.lock().unwrap().take() else {
+        eprintln!("Profiler capture stopped without being started");
+        return;
+    };
+    capture.end_frame(FRAME.load(Ordering::Relaxed));
+
+    let path = Path::new(name).with_extension("json");
+    match write_trace(&capture, &path) {
+        Ok(scopes) => println!("Wrote {} scopes to {}", scopes, path.display()),
+        Err(e) => eprintln!("Failed to write profiler trace {}: {}", path.display(), e),
+    }
+}
+
+/// Mark the start of a new frame, scopes are tagged with the frame they start in
+pub fn next_frame() {
+    if !CAPTURING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+        capture.end_frame(FRAME.fetch_add(1, Ordering::Relaxed));
+    }
+}
+
+impl Capture {
+    fn end_frame(&mut self, frame: u64) {
+        let now = Instant::now();
+        self.frames.push((frame, self.frame_start, now));
+        self.frame_start = now;
+    }
+
+    /// Microseconds since the capture started, as Chrome traces count time
+    fn micros(&self, time: Instant) -> f64 {
+        time.saturating_duration_since(self.start).as_secs_f64() * 1_000_000.0
+    }
+}
+
+/// Frames go on their own track above the threads
+const FRAME_TRACK: u64 = 0;
+
+/// Write the capture in the Chrome trace event format, returning how many scopes it had
+fn write_trace(capture: &Capture, path: &Path) -> io::Result<usize> {
+    let mut events = vec![thread_name(FRAME_TRACK, "Frames")];
+
+    for (frame, start, end) in &capture.frames {
+        events.push(complete(
+            "Frame",
+            FRAME_TRACK,
+            *frame,
+            capture,
+            *start,
+            *end,
+        ));
+    }
+
+    let mut scopes = 0;
+    for thread in THREADS.lock().unwrap().iter() {
+        let thread = thread.lock().unwrap();
+        if thread.events.is_empty() {
+            continue;
+        }
+
+        events.push(thread_name(thread.id, &thread.name));
+        for event in &thread.events {
+            events.push(complete(
+                event.name,
+                thread.id,
+                event.frame,
+                capture,
+                event.start,
+                event.end,
+            ));
+        }
+
+        scopes += thread.events.len();
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(
+        file,
+        &json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+        }),
+    )?;
+
+    Ok(scopes)
+}
+
+fn thread_name(tid: u64, name: &str) -> Value {
+    json!({
+        "name": "thread_name",
+        "ph": "M",
+        "pid": 1,
+        "tid": tid,
+        "args": { "name": name },
+    })
+}
+
+/// A span with its start and length, nested by time on its thread
+fn complete(
+    name: &str,
+    tid: u64,
+    frame: u64,
+    capture: &Capture,
+    start: Instant,
+    end: Instant,
+) -> Value {
+    json!({
+        "name": name,
+        "ph": "X",
+        "pid": 1,
+        "tid": tid,
+        "ts": capture.micros(start),
+        "dur": capture.micros(end) - capture.micros(start),
+        "args": { "frame": frame },
+    })
+}
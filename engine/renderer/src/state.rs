@@ -1,4 +1,4 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, rc::Rc, time::Instant};
 
 use glutin::surface::GlSurface;
 use render_common::{Display, Program};
@@ -7,7 +7,10 @@ use winit::{
     keyboard::KeyCode,
 };
 
-use crate::{Input, PositionDelta, Uniforms, camera::CameraManager, mesh::Mesh, vertex::Vertex};
+use crate::{
+    Input, PositionDelta, Renderable, Uniforms, camera::CameraManager, mesh::Mesh, timer::GpuTimer,
+    vertex::Vertex,
+};
 
 pub struct State {
     display: Option<Rc<Display>>,
@@ -16,6 +19,248 @@ pub struct State {
     delta_time: f32,
     pub cameras: CameraManager,
     frame_deltas: Vec<f64>,
+    /// Time the GPU spent on each frame, from timer queries so it can lag a few frames
+    gpu_times: Vec<f64>,
+    gpu_timer: GpuTimer,
+    pub stats: RenderStats,
+    /// Draw everything to depth first, so the colour pass only shades visible fragments
+    pub depth_prepass: bool,
+    pass: RenderPass,
+}
+
+/// Which pass [`State::render`] is currently drawing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPass {
+    /// Colour and depth together
+    #[default]
+    Full,
+    /// Only depth is written
+    Depth,
+    /// Colour over the prepass's depth, which is left as is
+    Color,
+}
+
+/// Counters filled in by renderables over a frame
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    /// Chunks rejected by occlusion culling this frame
+    pub occlusion_culled: usize,
+    occlusion_culled_total: usize,
+    /// Blocks edited this frame
+    pub edits: usize,
+    edits_total: usize,
+    /// When each edit whose new mesh was drawn this frame was made.
+    /// Turned into a latency once the frame has been presented.
+    pub drawn_edits: Vec<Instant>,
+    remesh_latency: Vec<f64>,
+    frames: usize,
+}
+
+impl RenderStats {
+    fn next_frame(&mut self) {
+        self.occlusion_culled_total += self.occlusion_culled;
+        self.occlusion_culled = 0;
+        self.edits_total += self.edits;
+        self.edits = 0;
+        self.frames += 1;
+    }
+
+    /// The frame is on screen, so every edit drawn in it is now visible
+    fn frame_presented(&mut self) {
+        let now = Instant::now();
+        self.remesh_latency.extend(
+            self.drawn_edits
+                .drain(..)
+                .map(|edit| (now - edit).as_secs_f64() * 1000.0),
+        );
+    }
+
+    pub fn avg_occlusion_culled(&self) -> f64 {
+        self.occlusion_culled_total as f64 / self.frames.max(1) as f64
+    }
+
+    pub fn total_edits(&self) -> usize {
+        self.edits_total + self.edits
+    }
+
+    /// Milliseconds from each edit to the first presented frame showing its new mesh
+    pub fn remesh_latency(&self) -> FrameStats {
+        FrameStats::new(&self.remesh_latency)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Distribution of a set of frame times, all in milliseconds.
+/// Also used for other per item times, such as meshing each chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize)]
+pub struct FrameStats {
+    pub frames: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// Mean of the slowest 1% of frames
+    pub low_1: f64,
+    /// Frames left out as outliers
+    pub rejected: usize,
+}
+
+impl FrameStats {
+    pub fn new(times: &[f64]) -> Self {
+        if times.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = times.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let frames = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / frames as f64;
+        let variance = sorted.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / frames as f64;
+
+        // Nearest rank, so every percentile is a frame that actually happened
+        let percentile =
+            |p: f64| sorted[((p / 100.0 * frames as f64).ceil() as usize).clamp(1, frames) - 1];
+
+        let slowest = &sorted[frames - frames.div_ceil(100)..];
+        let low_1 = slowest.iter().sum::<f64>() / slowest.len() as f64;
+
+        Self {
+            frames,
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[frames - 1],
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            low_1,
+            rejected: 0,
+        }
+    }
+
+    /// Leave out frames more than `mads` scaled median absolute deviations from the median.
+    /// The scale makes a deviation match a standard deviation for normally distributed times.
+    pub fn without_outliers(times: &[f64], mads: f64) -> Self {
+        if times.is_empty() || mads <= 0.0 {
+            return Self::new(times);
+        }
+
+        let median = |values: &mut Vec<f64>| {
+            values.sort_by(f64::total_cmp);
+            let mid = values.len() / 2;
+            if values.len().is_multiple_of(2) {
+                (values[mid - 1] + values[mid]) / 2.0
+            } else {
+                values[mid]
+            }
+        };
+
+        let centre = median(&mut times.to_vec());
+        let mad = median(&mut times.iter().map(|t| (t - centre).abs()).collect()) * 1.4826;
+
+        // Every frame took the same time, so none stand out
+        if mad == 0.0 {
+            return Self::new(times);
+        }
+
+        let kept = times
+            .iter()
+            .copied()
+            .filter(|t| (t - centre).abs() <= mads * mad)
+            .collect::<Vec<_>>();
+
+        Self {
+            rejected: times.len() - kept.len(),
+            ..Self::new(&kept)
+        }
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.3}ms ± {:.3} over {} frames ({} rejected), p50 {:.3}ms, p95 {:.3}ms, p99 {:.3}ms, 1% low {:.3}ms ({:.1} FPS)",
+            self.mean,
+            self.std_dev,
+            self.frames,
+            self.rejected,
+            self.p50,
+            self.p95,
+            self.p99,
+            self.low_1,
+            1000. / self.low_1
+        )
+    }
+}
+
+/// How long one chunk spent in each meshing stage, in milliseconds.
+/// Stages a technique doesn't have, or that the mesh cache skipped, are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChunkTimes {
+    pub build_depths: Option<f64>,
+    pub faces: Option<f64>,
+    pub upload: Option<f64>,
+}
+
+/// Time taken to mesh a scene, with each stage's distribution over its chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize)]
+pub struct MeshingStats {
+    pub chunks: usize,
+    /// Wall time to mesh every chunk at startup, `None` if they were meshed over many frames
+    pub scene_mesh: Option<f64>,
+    /// Wall time to upload every chunk at startup
+    pub scene_upload: Option<f64>,
+    pub build_depths: FrameStats,
+    pub faces: FrameStats,
+    pub upload: FrameStats,
+}
+
+impl MeshingStats {
+    pub fn new(scene_mesh: Option<f64>, scene_upload: Option<f64>, chunks: &[ChunkTimes]) -> Self {
+        let stage = |time: fn(&ChunkTimes) -> Option<f64>| {
+            FrameStats::new(&chunks.iter().filter_map(time).collect::<Vec<_>>())
+        };
+
+        Self {
+            chunks: chunks.len(),
+            scene_mesh,
+            scene_upload,
+            build_depths: stage(|c| c.build_depths),
+            faces: stage(|c| c.faces),
+            upload: stage(|c| c.upload),
+        }
+    }
+}
+
+impl std::fmt::Display for MeshingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let scene = |time: Option<f64>| match time {
+            Some(time) => format!("{:.2}ms", time),
+            None => "-".to_owned(),
+        };
+
+        write!(
+            f,
+            "{} chunks, scene mesh {} upload {}, per chunk depths {:.3}ms (p95 {:.3}ms), faces {:.3}ms (p95 {:.3}ms), upload {:.3}ms (p95 {:.3}ms)",
+            self.chunks,
+            scene(self.scene_mesh),
+            scene(self.scene_upload),
+            self.build_depths.mean,
+            self.build_depths.p95,
+            self.faces.mean,
+            self.faces.p95,
+            self.upload.mean,
+            self.upload.p95
+        )
+    }
 }
 
 impl State {
@@ -34,6 +279,45 @@ impl State {
         mesh.render(&self.cameras.game_frustum());
     }
 
+    /// Draw `renderable`, in two passes if the depth prepass is on.
+    /// Renderables can check [`State::pass`] to only do per frame work once.
+    pub fn render(&mut self, renderable: &mut dyn Renderable) {
+        if !self.depth_prepass {
+            renderable.render(self);
+            return;
+        }
+
+        self.pass = RenderPass::Depth;
+        unsafe {
+            gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+        }
+        {
+            crate::profiler::event!("Depth prepass");
+            renderable.render(self);
+        }
+
+        self.pass = RenderPass::Color;
+        unsafe {
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::DepthMask(gl::FALSE);
+            gl::DepthFunc(gl::LEQUAL);
+        }
+        {
+            crate::profiler::event!("Colour pass");
+            renderable.render(self);
+        }
+
+        self.pass = RenderPass::Full;
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+
+    pub fn pass(&self) -> RenderPass {
+        self.pass
+    }
+
     pub fn display(&self) -> Rc<Display> {
         self.display
             .as_ref()
@@ -62,8 +346,11 @@ impl State {
         let time = self.frame_time();
         self.delta_time = time as f32;
         self.frame_deltas.push(time);
+        self.stats.next_frame();
         self.last_frame_time = std::time::Instant::now();
 
+        self.gpu_timer.begin();
+
         unsafe {
             gl::ClearColor(0.1, 0.1, 0.1, 1.0);
             gl::ClearDepth(1.0);
@@ -77,10 +364,16 @@ impl State {
 
         let display = self.display();
 
+        self.gpu_timer.end();
+
         unsafe {
             gl::Finish();
             _ = display.surface.swap_buffers(&display.context);
         }
+        self.stats.frame_presented();
+
+        let gpu_times = self.gpu_timer.poll();
+        self.gpu_times.extend(gpu_times);
     }
 
     pub fn avg_frame_time(&self) -> f64 {
@@ -89,8 +382,29 @@ impl State {
         avg
     }
 
+    /// Time between each frame since the last [`State::wipe_fps`], in milliseconds
+    pub fn frame_times(&self) -> &[f64] {
+        &self.frame_deltas
+    }
+
+    /// GPU time of each frame since the last [`State::wipe_fps`], in milliseconds
+    pub fn gpu_times(&self) -> &[f64] {
+        &self.gpu_times
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats::new(&self.frame_deltas)
+    }
+
+    pub fn gpu_stats(&self) -> FrameStats {
+        FrameStats::new(&self.gpu_times)
+    }
+
     pub fn wipe_fps(&mut self) {
         self.frame_deltas.clear();
+        self.gpu_times.clear();
+        self.gpu_timer.discard();
+        self.stats.reset();
     }
 
     pub fn is_pressed(&self, key: &KeyCode) -> bool {
@@ -165,6 +479,104 @@ impl Default for State {
             delta_time: 0.,
             cameras: CameraManager::default(),
             frame_deltas: vec![],
+            gpu_times: vec![],
+            gpu_timer: GpuTimer::new(),
+            stats: RenderStats::default(),
+            depth_prepass: false,
+            pass: RenderPass::Full,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `1..=count` milliseconds, out of order
+    fn times(count: usize) -> Vec<f64> {
+        (1..=count).rev().map(|t| t as f64).collect()
+    }
+
+    #[test]
+    fn no_frames() {
+        assert_eq!(FrameStats::new(&[]), FrameStats::default());
+    }
+
+    #[test]
+    fn single_frame() {
+        let stats = FrameStats::new(&[4.0]);
+
+        assert_eq!(stats.frames, 1);
+        assert_eq!(stats.std_dev, 0.0);
+        assert_eq!([stats.min, stats.max], [4.0, 4.0]);
+        assert_eq!([stats.p50, stats.p95, stats.p99, stats.low_1], [4.0; 4]);
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let stats = FrameStats::new(&times(100));
+
+        assert_eq!(stats.frames, 100);
+        assert_eq!(stats.mean, 50.5);
+        assert_eq!([stats.min, stats.max], [1.0, 100.0]);
+        assert_eq!([stats.p50, stats.p95, stats.p99], [50.0, 95.0, 99.0]);
+        assert_eq!(stats.low_1, 100.0);
+
+        // Ranks round up, so a small run's tail percentiles are its slowest frame
+        let stats = FrameStats::new(&times(10));
+
+        assert_eq!([stats.p50, stats.p95, stats.p99], [5.0, 10.0, 10.0]);
+        assert!((stats.std_dev - 8.25f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn low_1_averages_the_slowest_percent() {
+        // 1% of 250 rounds up to 3 frames
+        let stats = FrameStats::new(&times(250));
+
+        assert_eq!(stats.low_1, 249.0);
+        assert_eq!(stats.p99, 248.0);
+    }
+
+    #[test]
+    fn without_outliers_drops_frames_far_from_the_median() {
+        // Median 3, deviations 0, 1, 1, 2 and 97 so the scaled deviation is 1.4826
+        let stats = FrameStats::without_outliers(&[4.0, 1.0, 100.0, 3.0, 2.0], 3.5);
+
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.frames, 4);
+        assert_eq!(stats.max, 4.0);
+    }
+
+    #[test]
+    fn without_outliers_takes_the_middle_pair_of_an_even_count() {
+        // Median 3.5, deviations median 1.5 so the scaled deviation is 2.2239
+        let times = [1.0, 2.0, 3.0, 4.0, 5.0, 100.0];
+
+        assert_eq!(FrameStats::without_outliers(&times, 3.5).rejected, 1);
+
+        let stats = FrameStats::without_outliers(&times, 1.0);
+        assert_eq!(stats.rejected, 2);
+        assert_eq!([stats.min, stats.max], [2.0, 5.0]);
+    }
+
+    #[test]
+    fn without_outliers_keeps_everything_it_cant_judge() {
+        let spread = times(10);
+
+        assert_eq!(
+            FrameStats::without_outliers(&[], 3.5),
+            FrameStats::default()
+        );
+        assert_eq!(
+            FrameStats::without_outliers(&spread, 0.0),
+            FrameStats::new(&spread)
+        );
+        // A single frame, or frames that all took as long, have no spread to measure
+        assert_eq!(
+            FrameStats::without_outliers(&[7.0], 3.5),
+            FrameStats::new(&[7.0])
+        );
+        assert_eq!(FrameStats::without_outliers(&[2.0; 5], 0.1).rejected, 0);
+    }
+}
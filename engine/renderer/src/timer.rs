@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+use crate::query::{Query, QueryTarget};
+
+/// Measures how long the GPU spends on a span of commands with `GL_TIME_ELAPSED` queries.
+/// Results come back a few frames late, so finished queries are kept until they are ready
+/// and reading them never stalls.
+#[derive(Debug, Default)]
+pub struct GpuTimer {
+    active: Option<Query>,
+    pending: VecDeque<Query>,
+    free: Vec<Query>,
+}
+
+impl GpuTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start timing, ending any span that is still open
+    pub fn begin(&mut self) {
+        self.end();
+
+        let query = self
+            .free
+            .pop()
+            .unwrap_or_else(|| Query::new(QueryTarget::TimeElapsed));
+        query.begin();
+
+        self.active = Some(query);
+    }
+
+    /// Stop timing, does nothing if [`GpuTimer::begin`] wasn't called
+    pub fn end(&mut self) {
+        if let Some(query) = self.active.take() {
+            query.end();
+            self.pending.push_back(query);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Time `f` takes on the GPU, read later with [`GpuTimer::poll`]
+    pub fn time<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.begin();
+        let result = f();
+        self.end();
+
+        result
+    }
+
+    /// Milliseconds taken by each span whose result has come back, oldest first
+    pub fn poll(&mut self) -> Vec<f64> {
+        let mut times = vec![];
+
+        while let Some(time) = self.pending.front().and_then(|q| q.try_result()) {
+            times.push(time as f64 / 1_000_000.0);
+            self.free.extend(self.pending.pop_front());
+        }
+
+        times
+    }
+
+    /// Forget spans that haven't come back yet, so they aren't counted with the next ones
+    pub fn discard(&mut self) {
+        self.free.extend(self.pending.drain(..));
+    }
+}
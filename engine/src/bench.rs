@@ -0,0 +1,690 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::{Args, Parser};
+use renderer::{FrameStats, GlInfo, MeshingStats, RenderStats};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Used when `--auto-test` is passed without `--bench`
+pub const DEFAULT_CONFIG: &str = "benchmarks/default.toml";
+
+/// A benchmark matrix, read from a TOML file.
+/// Each group runs every combination of its scenes, sizes, techniques and flag sets.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchConfig {
+    /// Seconds each run is measured for
+    #[serde(default = "default_time_per_test")]
+    pub time_per_test: f64,
+    /// Seconds run before measuring starts, so buffer uploads and shader compiles aren't counted
+    #[serde(default = "default_warmup")]
+    pub warmup: f64,
+    /// Times each run is repeated, confidence intervals need at least 2
+    #[serde(default = "default_repetitions")]
+    pub repetitions: usize,
+    /// Run each round of repetitions in a random order, so drift doesn't favour any run.
+    /// Few runs then follow one on the same scene and test, so most generate their scene again.
+    #[serde(default = "default_shuffle")]
+    pub shuffle: bool,
+    /// Seed for the order, taken from the clock if not given
+    pub shuffle_seed: Option<u64>,
+    /// Seconds camera paths move on each frame, so every run draws the same views
+    #[serde(default = "default_camera_timestep")]
+    pub camera_timestep: f32,
+    /// Frames further than this many scaled median absolute deviations from the median are
+    /// left out of a run's statistics, 0 keeps every frame
+    #[serde(default = "default_outlier_mads")]
+    pub outlier_mads: f64,
+    /// Results are written here with `.jsonl` and `.csv` extensions
+    #[serde(default = "default_output")]
+    pub output: PathBuf,
+    #[serde(rename = "group", default)]
+    pub groups: Vec<Group>,
+}
+
+fn default_time_per_test() -> f64 {
+    5.0
+}
+
+fn default_warmup() -> f64 {
+    1.0
+}
+
+fn default_repetitions() -> usize {
+    1
+}
+
+fn default_shuffle() -> bool {
+    true
+}
+
+fn default_camera_timestep() -> f32 {
+    1.0 / 60.0
+}
+
+fn default_outlier_mads() -> f64 {
+    3.5
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from("results/bench")
+}
+
+/// One block of the matrix, any list left out uses the default for that argument
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    #[serde(default)]
+    pub scenes: Vec<String>,
+    #[serde(default)]
+    pub tests: Vec<String>,
+    #[serde(default)]
+    pub radius: Vec<i32>,
+    #[serde(default)]
+    pub fill: Vec<u8>,
+    #[serde(default)]
+    pub seed: Vec<u64>,
+    /// Recorded camera paths to fly along, runs with one last until the path ends
+    #[serde(default)]
+    pub camera_paths: Vec<PathBuf>,
+    /// Each set of flags is a seperate run, named as they are on the command line without `--`
+    #[serde(default)]
+    pub flags: Vec<Vec<String>>,
+    /// Passed to every run in the group
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// A single entry of the expanded matrix
+#[derive(Debug, Clone)]
+pub struct Run {
+    /// The arguments the run was made from, used to find it again when resuming
+    pub label: String,
+    pub args: Args,
+    pub camera_path: Option<PathBuf>,
+    pub repetition: usize,
+}
+
+impl BenchConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        toml::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Every run of every group, in order
+    pub fn runs(&self) -> Result<Vec<Run>, String> {
+        let mut runs = vec![];
+        for group in &self.groups {
+            runs.extend(group.runs()?);
+        }
+
+        Ok(runs)
+    }
+
+    /// Every repetition of every run, each round shuffled if enabled.
+    /// Returns the seed used so the order can be repeated.
+    pub fn schedule(&self, runs: &[Run]) -> (Vec<Run>, u64) {
+        let seed = self.shuffle_seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        let mut rng = XorShift::new(seed);
+
+        let mut schedule = vec![];
+        for repetition in 0..self.repetitions.max(1) {
+            let mut round = runs
+                .iter()
+                .map(|run| Run {
+                    repetition,
+                    ..run.clone()
+                })
+                .collect::<Vec<_>>();
+
+            if self.shuffle {
+                rng.shuffle(&mut round);
+            }
+
+            schedule.extend(round);
+        }
+
+        (schedule, seed)
+    }
+
+    pub fn jsonl_path(&self) -> PathBuf {
+        self.output.with_extension("jsonl")
+    }
+
+    pub fn csv_path(&self) -> PathBuf {
+        self.output.with_extension("csv")
+    }
+
+    pub fn summary_path(&self) -> PathBuf {
+        self.output.with_extension("summary.csv")
+    }
+
+    /// Directory the per frame traces go in
+    pub fn traces_path(&self) -> PathBuf {
+        self.output.with_extension("frames")
+    }
+}
+
+/// Xorshift64*, enough to shuffle runs without pulling in a crate
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero would only ever produce zero
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Fisher-Yates
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+const CAMERA_PATH: &str = "camera-path";
+
+/// Each value as its own option, or a single empty option if there are none
+fn options<T: ToString>(name: &str, values: &[T]) -> Vec<Vec<String>> {
+    if values.is_empty() {
+        return vec![vec![]];
+    }
+
+    values
+        .iter()
+        .map(|v| vec![format!("--{}", name), v.to_string()])
+        .collect()
+}
+
+impl Group {
+    fn runs(&self) -> Result<Vec<Run>, String> {
+        let flags = if self.flags.is_empty() {
+            vec![vec![]]
+        } else {
+            self.flags
+                .iter()
+                .map(|set| set.iter().map(|flag| format!("--{}", flag)).collect())
+                .collect()
+        };
+
+        // Flags vary fastest, so runs in order only generate the scene again for a new test
+        let axes = [
+            options("scene", &self.scenes),
+            options("radius", &self.radius),
+            options("fill", &self.fill),
+            options("seed", &self.seed),
+            options("test", &self.tests),
+            options(
+                CAMERA_PATH,
+                &self
+                    .camera_paths
+                    .iter()
+                    .map(|p| p.display())
+                    .collect::<Vec<_>>(),
+            ),
+            flags,
+        ];
+
+        let combinations = axes.iter().fold(vec![vec![]], |combinations, axis| {
+            combinations
+                .iter()
+                .flat_map(|prefix| {
+                    axis.iter()
+                        .map(move |option| [prefix.as_slice(), option.as_slice()].concat())
+                })
+                .collect::<Vec<Vec<String>>>()
+        });
+
+        combinations
+            .into_iter()
+            .map(|mut cli| {
+                cli.extend(self.args.iter().cloned());
+                let label = cli.join(" ");
+
+                // Not one of the args, so it is taken back out before they are parsed
+                let flag = format!("--{}", CAMERA_PATH);
+                let camera_path = cli.iter().position(|a| *a == flag).map(|i| {
+                    let path = PathBuf::from(cli.remove(i + 1));
+                    cli.remove(i);
+                    path
+                });
+
+                let mut args =
+                    Args::try_parse_from(std::iter::once("engine".to_owned()).chain(cli))
+                        .map_err(|e| format!("Invalid run \"{}\": {}", label, e))?;
+                args.auto_test = true;
+
+                Ok(Run {
+                    label,
+                    args,
+                    camera_path,
+                    repetition: 0,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Where the results were measured
+#[derive(Debug, Clone, Serialize)]
+pub struct Environment {
+    pub git_revision: String,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub cpu: String,
+    pub threads: usize,
+    pub gl_vendor: String,
+    pub gl_renderer: String,
+    pub gl_version: String,
+}
+
+impl Environment {
+    /// Needs a current GL context for the driver strings
+    pub fn current() -> Self {
+        let gl = GlInfo::current();
+
+        Self {
+            git_revision: git_revision(),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            cpu: cpu_name(),
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            gl_vendor: gl.vendor,
+            gl_renderer: gl.renderer,
+            gl_version: gl.version,
+        }
+    }
+}
+
+/// The commit being benchmarked, marked dirty if there are uncommitted changes
+fn git_revision() -> String {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_owned())
+    };
+
+    let Some(revision) = git(&["rev-parse", "HEAD"]) else {
+        return "unknown".to_owned();
+    };
+
+    match git(&["status", "--porcelain", "--untracked-files=no"]) {
+        Some(status) if !status.is_empty() => format!("{}-dirty", revision),
+        _ => revision,
+    }
+}
+
+fn cpu_name() -> String {
+    fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, name)| name.trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// A finished run, one line of the JSON results and one row of the CSV
+#[derive(Debug, Serialize)]
+pub struct RunResult<'a> {
+    pub label: &'a str,
+    pub repetition: usize,
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub environment: &'a Environment,
+    pub args: &'a Args,
+    pub camera_path: Option<&'a Path>,
+    pub avg_frame_time: f64,
+    pub fps: f64,
+    /// Time between frames, as the CPU sees it
+    pub frame: FrameStats,
+    /// Time the GPU spent drawing each frame
+    pub gpu: FrameStats,
+    /// Left at zero for renderables that don't mesh, so every row has the same columns
+    pub meshing: MeshingStats,
+    /// Blocks edited while measuring
+    pub edits: usize,
+    /// Time from each edit to the first presented frame showing it
+    pub remesh_latency: FrameStats,
+    /// File with the time of every frame
+    pub trace: String,
+}
+
+impl<'a> RunResult<'a> {
+    pub fn new(
+        run: &'a Run,
+        environment: &'a Environment,
+        frame: FrameStats,
+        gpu: FrameStats,
+        meshing: Option<MeshingStats>,
+        stats: &RenderStats,
+        trace: &Path,
+    ) -> Self {
+        Self {
+            label: &run.label,
+            repetition: run.repetition,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            environment,
+            args: &run.args,
+            camera_path: run.camera_path.as_deref(),
+            avg_frame_time: frame.mean,
+            fps: 1000. / frame.mean,
+            frame,
+            gpu,
+            meshing: meshing.unwrap_or_default(),
+            edits: stats.total_edits(),
+            remesh_latency: stats.remesh_latency(),
+            trace: trace.display().to_string(),
+        }
+    }
+}
+
+/// Every result in a JSON lines file, skipping any line that can't be read
+pub fn read_results(path: &Path) -> Vec<Value> {
+    let Ok(file) = File::open(path) else {
+        return vec![];
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<Value>(&line).ok())
+        .collect()
+}
+
+/// Labels and repetitions of the runs already in a results file, so a resumed run can skip them
+pub fn completed(path: &Path) -> BTreeSet<(String, usize)> {
+    read_results(path)
+        .iter()
+        .filter_map(|result| {
+            let label = result.get("label")?.as_str()?.to_owned();
+            let repetition = result
+                .get("repetition")
+                .and_then(Value::as_u64)
+                .unwrap_or_default();
+
+            Some((label, repetition as usize))
+        })
+        .collect()
+}
+
+/// Appends each result to the JSON lines and CSV files as soon as it finishes,
+/// so an interrupted run keeps everything measured so far
+pub struct ResultWriter {
+    jsonl: File,
+    csv: File,
+    /// Whether the CSV still needs its header
+    csv_empty: bool,
+    traces: PathBuf,
+}
+
+impl ResultWriter {
+    /// Start the files over, or add to them when resuming
+    pub fn open(config: &BenchConfig, resume: bool) -> io::Result<Self> {
+        if let Some(dir) = config.output.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let open = |path: PathBuf| {
+            OpenOptions::new()
+                .create(true)
+                .append(resume)
+                .write(true)
+                .truncate(!resume)
+                .open(path)
+        };
+
+        let jsonl = open(config.jsonl_path())?;
+        let csv = open(config.csv_path())?;
+        let csv_empty = csv.metadata()?.len() == 0;
+
+        let traces = config.traces_path();
+        if !resume && traces.exists() {
+            fs::remove_dir_all(&traces)?;
+        }
+        fs::create_dir_all(&traces)?;
+
+        Ok(Self {
+            jsonl,
+            csv,
+            csv_empty,
+            traces,
+        })
+    }
+
+    /// Write the time of every frame of a run to its own CSV, returning where it went.
+    /// GPU times lag behind, so the last few frames may not have one.
+    pub fn write_trace(
+        &self,
+        run: &Run,
+        frame_times: &[f64],
+        gpu_times: &[f64],
+    ) -> io::Result<PathBuf> {
+        let name = run
+            .label
+            .trim_start_matches('-')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let path = self
+            .traces
+            .join(format!("{}_{}", name, run.repetition))
+            .with_extension("csv");
+
+        let mut file = io::BufWriter::new(File::create(&path)?);
+        writeln!(file, "frame,frame_time,gpu_time")?;
+
+        for (i, frame_time) in frame_times.iter().enumerate() {
+            match gpu_times.get(i) {
+                Some(gpu_time) => writeln!(file, "{},{},{}", i, frame_time, gpu_time)?,
+                None => writeln!(file, "{},{},", i, frame_time)?,
+            }
+        }
+
+        file.flush()?;
+        Ok(path)
+    }
+
+    pub fn write(&mut self, result: &RunResult) -> io::Result<()> {
+        let value = serde_json::to_value(result)?;
+        writeln!(self.jsonl, "{}", value)?;
+
+        let mut columns = Map::new();
+        flatten("", &value, &mut columns);
+
+        if self.csv_empty {
+            let header = columns.keys().map(|k| csv_field(k)).collect::<Vec<_>>();
+            writeln!(self.csv, "{}", header.join(","))?;
+            self.csv_empty = false;
+        }
+
+        let row = columns
+            .values()
+            .map(|v| match v {
+                Value::String(s) => csv_field(s),
+                v => v.to_string(),
+            })
+            .collect::<Vec<_>>();
+        writeln!(self.csv, "{}", row.join(","))
+    }
+}
+
+/// Nested objects become dotted column names, like `args.noise.seed`
+fn flatten(prefix: &str, value: &Value, columns: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, columns);
+            }
+        }
+        value => {
+            columns.insert(prefix.to_owned(), value.clone());
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::tests::{Scene, Test};
+
+    use super::*;
+
+    fn group(toml: &str) -> Group {
+        toml::from_str(toml).expect("Invalid test group")
+    }
+
+    fn labels(runs: &[Run]) -> Vec<&str> {
+        runs.iter().map(|run| run.label.as_str()).collect()
+    }
+
+    #[test]
+    fn flags_vary_fastest() {
+        let runs = group(
+            r#"
+            scenes = ["single", "perlin"]
+            radius = [32, 64]
+            tests = ["culled"]
+            flags = [[], ["combine", "frustum-cull"]]
+            "#,
+        )
+        .runs()
+        .unwrap();
+
+        assert_eq!(
+            labels(&runs),
+            [
+                "--scene single --radius 32 --test culled",
+                "--scene single --radius 32 --test culled --combine --frustum-cull",
+                "--scene single --radius 64 --test culled",
+                "--scene single --radius 64 --test culled --combine --frustum-cull",
+                "--scene perlin --radius 32 --test culled",
+                "--scene perlin --radius 32 --test culled --combine --frustum-cull",
+                "--scene perlin --radius 64 --test culled",
+                "--scene perlin --radius 64 --test culled --combine --frustum-cull",
+            ]
+        );
+
+        let last = &runs[7].args;
+        assert_eq!(last.scene, Scene::Perlin);
+        assert_eq!(last.radius, 64);
+        assert_eq!(last.test, Test::Culled);
+        assert!(last.combine && last.frustum_cull);
+        assert!(runs.iter().all(|run| run.args.auto_test));
+    }
+
+    #[test]
+    fn missing_lists_use_the_defaults() {
+        let runs = group(r#"tests = ["basic", "greedy"]"#).runs().unwrap();
+        let defaults = Args::parse_from(["engine"]);
+
+        assert_eq!(labels(&runs), ["--test basic", "--test greedy"]);
+        assert!(runs.iter().all(|run| run.args.radius == defaults.radius));
+        assert!(runs.iter().all(|run| run.camera_path.is_none()));
+    }
+
+    #[test]
+    fn args_go_to_every_run() {
+        let runs = group(
+            r#"
+            fill = [10, 90]
+            args = ["--scene", "noise"]
+            "#,
+        )
+        .runs()
+        .unwrap();
+
+        assert_eq!(
+            labels(&runs),
+            ["--fill 10 --scene noise", "--fill 90 --scene noise"]
+        );
+        assert!(runs.iter().all(|run| run.args.scene == Scene::Noise));
+    }
+
+    #[test]
+    fn camera_paths_are_taken_out_of_the_args() {
+        let runs = group(
+            r#"
+            tests = ["culled"]
+            camera_paths = ["paths/orbit.json"]
+            "#,
+        )
+        .runs()
+        .unwrap();
+
+        assert_eq!(
+            labels(&runs),
+            ["--test culled --camera-path paths/orbit.json"]
+        );
+        assert_eq!(runs[0].camera_path, Some(PathBuf::from("paths/orbit.json")));
+    }
+
+    #[test]
+    fn unknown_flags_are_an_error() {
+        let error = group(r#"flags = [["not-a-flag"]]"#).runs().unwrap_err();
+
+        assert!(
+            error.starts_with("Invalid run \"--not-a-flag\""),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn default_matrix_keeps_the_hard_coded_runs() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("benchmarks/default.toml");
+        let runs = BenchConfig::load(&path).unwrap().runs().unwrap();
+
+        // Runs that neither move the camera nor edit blocks, as in the matrix this file replaced
+        let fixed = runs
+            .iter()
+            .filter(|run| run.camera_path.is_none() && run.args.edits == 0)
+            .count();
+        assert_eq!(fixed, 267);
+    }
+}
@@ -1,9 +1,14 @@
-use std::{fs::OpenOptions, io::Write, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use bench::{BenchConfig, Environment, ResultWriter, Run, RunResult};
 
 use meshing::basic::BasicRenderType;
 use renderer::{
-    Renderable, State,
-    camera::{Camera, CameraManager, PerspectiveCamera},
+    FrameStats, Renderable, State,
+    camera::{Camera, CameraManager, CameraPath, PathPlayer, PerspectiveCamera},
     make_event_loop, make_window,
 };
 use winit::{
@@ -13,178 +18,60 @@ use winit::{
     keyboard::PhysicalKey,
 };
 
-use common::{
-    Args, Parser,
-    tests::{Scene, Test},
-};
+use common::{Args, Parser, tests::Test};
+
+mod bench;
+mod report;
+mod summary;
+
+/// Everything [`Args`] takes, plus how to run benchmarks
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+
+    /// Run the benchmark matrix in this config file, implies auto test
+    #[arg(long)]
+    bench: Option<PathBuf>,
+
+    /// Skip runs already in the results of an earlier benchmark
+    #[arg(long, default_value = "false")]
+    resume: bool,
 
-macro_rules! make_test {
-    ($scene:ident, $test:ident, $frustum:literal, $combine:literal) => {{
-        let mut args = Args::default();
-        args.scene = Scene::$scene;
-        args.test = Test::$test;
-        args.frustum_cull = $frustum;
-        args.combine = $combine;
-        args.vertex_pull = false;
-        args.auto_test = true;
-        args
-    }};
-    ($scene:ident, $test:ident, $frustum:literal, $combine:literal, $vertex_pull:literal) => {{
-        let mut args = make_test!($scene, $test, $frustum, $combine);
-        args.vertex_pull = $vertex_pull;
-        args
-    }};
-    ($scene:ident, $test:ident, $frustum:literal, $combine:literal, $vertex_pull:literal, $radius:literal) => {{
-        let mut args = make_test!($scene, $test, $frustum, $combine, $vertex_pull);
-        args.radius = $radius;
-        args
-    }};
+    /// Fly along a recorded camera path on repeat, record one with F6
+    #[arg(long)]
+    camera_path: Option<PathBuf>,
 }
 
-const TIME_PER_TEST: f64 = 5.0;
-const TESTS: [Args; 132] = [
-    make_test!(Single, Basic, false, false),
-    make_test!(Single, Basic, false, true),
-    make_test!(Single, Basic, false, false, true),
-    make_test!(Single, Culled, false, false),
-    make_test!(Single, Culled, true, false),
-    make_test!(Single, Culled, false, true),
-    make_test!(Single, Culled, true, true),
-    make_test!(Single, Culled, false, false, true),
-    make_test!(Single, Culled, true, false, true),
-    make_test!(Single, Culled, false, true, true),
-    make_test!(Single, Culled, true, true, true),
-    make_test!(Single, Greedy, false, false),
-    make_test!(Single, Greedy, true, false),
-    make_test!(Single, Greedy, false, true),
-    make_test!(Single, Greedy, true, true),
-    make_test!(Single, Greedy, false, false, true),
-    make_test!(Single, Greedy, true, false, true),
-    make_test!(Single, Greedy, false, true, true),
-    make_test!(Single, Greedy, true, true, true),
-    make_test!(Cube, Basic, false, false),
-    make_test!(Cube, Basic, false, true),
-    make_test!(Cube, Basic, false, false, true),
-    make_test!(Cube, Culled, false, false),
-    make_test!(Cube, Culled, true, false),
-    make_test!(Cube, Culled, false, true),
-    make_test!(Cube, Culled, true, true),
-    make_test!(Cube, Culled, false, false, true),
-    make_test!(Cube, Culled, true, false, true),
-    make_test!(Cube, Culled, false, true, true),
-    make_test!(Cube, Culled, true, true, true),
-    make_test!(Cube, Greedy, false, false),
-    make_test!(Cube, Greedy, true, false),
-    make_test!(Cube, Greedy, false, true),
-    make_test!(Cube, Greedy, true, true),
-    make_test!(Cube, Greedy, false, false, true),
-    make_test!(Cube, Greedy, true, false, true),
-    make_test!(Cube, Greedy, false, true, true),
-    make_test!(Cube, Greedy, true, true, true),
-    make_test!(Perlin, Basic, false, false, false, 32),
-    make_test!(Perlin, Basic, false, true, false, 32),
-    make_test!(Perlin, Basic, false, false, true, 32),
-    make_test!(Perlin, Culled, false, false, false, 32),
-    make_test!(Perlin, Culled, true, false, false, 32),
-    make_test!(Perlin, Culled, false, true, false, 32),
-    make_test!(Perlin, Culled, true, true, false, 32),
-    make_test!(Perlin, Culled, false, false, true, 32),
-    make_test!(Perlin, Culled, true, false, true, 32),
-    make_test!(Perlin, Culled, false, true, true, 32),
-    make_test!(Perlin, Culled, true, true, true, 32),
-    make_test!(Perlin, Greedy, false, false, false, 32),
-    make_test!(Perlin, Greedy, true, false, false, 32),
-    make_test!(Perlin, Greedy, false, true, false, 32),
-    make_test!(Perlin, Greedy, true, true, false, 32),
-    make_test!(Perlin, Greedy, false, false, true, 32),
-    make_test!(Perlin, Greedy, true, false, true, 32),
-    make_test!(Perlin, Greedy, false, true, true, 32),
-    make_test!(Perlin, Greedy, true, true, true, 32),
-    make_test!(Perlin, Basic, false, false, false, 64),
-    make_test!(Perlin, Basic, false, true, false, 64),
-    make_test!(Perlin, Basic, false, false, true, 64),
-    make_test!(Perlin, Culled, false, false, false, 64),
-    make_test!(Perlin, Culled, true, false, false, 64),
-    make_test!(Perlin, Culled, false, true, false, 64),
-    make_test!(Perlin, Culled, true, true, false, 64),
-    make_test!(Perlin, Culled, false, false, true, 64),
-    make_test!(Perlin, Culled, true, false, true, 64),
-    make_test!(Perlin, Culled, false, true, true, 64),
-    make_test!(Perlin, Culled, true, true, true, 64),
-    make_test!(Perlin, Greedy, false, false, false, 64),
-    make_test!(Perlin, Greedy, true, false, false, 64),
-    make_test!(Perlin, Greedy, false, true, false, 64),
-    make_test!(Perlin, Greedy, true, true, false, 64),
-    make_test!(Perlin, Greedy, false, false, true, 64),
-    make_test!(Perlin, Greedy, true, false, true, 64),
-    make_test!(Perlin, Greedy, false, true, true, 64),
-    make_test!(Perlin, Greedy, true, true, true, 64),
-    make_test!(Perlin, Basic, false, false, false, 128),
-    make_test!(Perlin, Basic, false, true, false, 128),
-    make_test!(Perlin, Basic, false, false, true, 128),
-    make_test!(Perlin, Culled, false, false, false, 128),
-    make_test!(Perlin, Culled, true, false, false, 128),
-    make_test!(Perlin, Culled, false, true, false, 128),
-    make_test!(Perlin, Culled, true, true, false, 128),
-    make_test!(Perlin, Culled, false, false, true, 128),
-    make_test!(Perlin, Culled, true, false, true, 128),
-    make_test!(Perlin, Culled, false, true, true, 128),
-    make_test!(Perlin, Culled, true, true, true, 128),
-    make_test!(Perlin, Greedy, false, false, false, 128),
-    make_test!(Perlin, Greedy, true, false, false, 128),
-    make_test!(Perlin, Greedy, false, true, false, 128),
-    make_test!(Perlin, Greedy, true, true, false, 128),
-    make_test!(Perlin, Greedy, false, false, true, 128),
-    make_test!(Perlin, Greedy, true, false, true, 128),
-    make_test!(Perlin, Greedy, false, true, true, 128),
-    make_test!(Perlin, Greedy, true, true, true, 128),
-    make_test!(Perlin, Basic, false, false, false, 256),
-    make_test!(Perlin, Basic, false, true, false, 256),
-    make_test!(Perlin, Basic, false, false, true, 256),
-    make_test!(Perlin, Culled, false, false, false, 256),
-    make_test!(Perlin, Culled, true, false, false, 256),
-    make_test!(Perlin, Culled, false, true, false, 256),
-    make_test!(Perlin, Culled, true, true, false, 256),
-    make_test!(Perlin, Culled, false, false, true, 256),
-    make_test!(Perlin, Culled, true, false, true, 256),
-    make_test!(Perlin, Culled, false, true, true, 256),
-    make_test!(Perlin, Culled, true, true, true, 256),
-    make_test!(Perlin, Greedy, false, false, false, 256),
-    make_test!(Perlin, Greedy, true, false, false, 256),
-    make_test!(Perlin, Greedy, false, true, false, 256),
-    make_test!(Perlin, Greedy, true, true, false, 256),
-    make_test!(Perlin, Greedy, false, false, true, 256),
-    make_test!(Perlin, Greedy, true, false, true, 256),
-    make_test!(Perlin, Greedy, false, true, true, 256),
-    make_test!(Perlin, Greedy, true, true, true, 256),
-    make_test!(Perlin, Basic, false, true, false, 512),
-    make_test!(Perlin, Basic, false, false, true, 512),
-    make_test!(Perlin, Culled, false, false, false, 512),
-    make_test!(Perlin, Culled, true, false, false, 512),
-    make_test!(Perlin, Culled, false, true, false, 512),
-    make_test!(Perlin, Culled, true, true, false, 512),
-    make_test!(Perlin, Culled, false, false, true, 512),
-    make_test!(Perlin, Culled, true, false, true, 512),
-    make_test!(Perlin, Culled, false, true, true, 512),
-    make_test!(Perlin, Culled, true, true, true, 512),
-    make_test!(Perlin, Greedy, false, false, false, 512),
-    make_test!(Perlin, Greedy, true, false, false, 512),
-    make_test!(Perlin, Greedy, false, true, false, 512),
-    make_test!(Perlin, Greedy, true, true, false, 512),
-    make_test!(Perlin, Greedy, false, false, true, 512),
-    make_test!(Perlin, Greedy, true, false, true, 512),
-    make_test!(Perlin, Greedy, false, true, true, 512),
-    make_test!(Perlin, Greedy, true, true, true, 512),
-];
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Build Markdown and HTML tables and plots from benchmark results
+    Report(report::ReportArgs),
+}
+
+/// Seconds a camera path moves each frame when it isn't set by a benchmark config
+const CAMERA_TIMESTEP: f32 = 1.0 / 60.0;
+
+fn load_camera_path(path: &Path) -> CameraPath {
+    CameraPath::load(path)
+        .unwrap_or_else(|e| panic!("Failed to load camera path {}: {}", path.display(), e))
+}
 
 fn setup_test(app: &mut App) {
+    app.state().depth_prepass = app.args.depth_prepass;
     app.setup = Some(match app.args.test {
         Test::Tri => meshing::setup(),
-        Test::Culled | Test::Greedy => Box::new(meshing::binary::culled::setup(
+        Test::Culled | Test::NaiveCulled | Test::Greedy => Box::new(meshing::binary::culled::setup(
             &app.args,
             app.state.as_ref().unwrap(),
         )) as Box<dyn Renderable>,
 
+        Test::Smooth => Box::new(meshing::smooth::setup(&app.args)) as Box<dyn Renderable>,
+
         Test::Basic => Box::new(meshing::basic::setup(
             &app.args,
             if app.args.vertex_pull {
@@ -223,14 +110,118 @@ impl Drop for Prolfiler {
     }
 }
 
+/// The expanded matrix being run, and where its results go
+struct Bench {
+    config: BenchConfig,
+    /// Every repetition of every run, in the order they are run
+    runs: Vec<Run>,
+    results: ResultWriter,
+    /// Filled in once there is a GL context to ask about the driver
+    environment: Option<Environment>,
+    /// Whether the current run is still warming up, and nothing is being measured
+    warming_up: bool,
+    /// Every camera path the runs use, loaded up front so a bad file fails straight away
+    camera_paths: BTreeMap<PathBuf, CameraPath>,
+}
+
+impl Bench {
+    fn new(path: &Path, resume: bool) -> Self {
+        let config = BenchConfig::load(path).unwrap_or_else(|e| panic!("{}", e));
+        let runs = config.runs().unwrap_or_else(|e| panic!("{}", e));
+        let (mut runs, seed) = config.schedule(&runs);
+        let total = runs.len();
+
+        if resume {
+            let completed = bench::completed(&config.jsonl_path());
+            runs.retain(|run| !completed.contains(&(run.label.clone(), run.repetition)));
+        }
+
+        println!(
+            "Running {} of {} benchmarks from {}, results in {}",
+            runs.len(),
+            total,
+            path.display(),
+            config.jsonl_path().display()
+        );
+        if config.shuffle {
+            println!("Shuffled with seed {}", seed);
+        }
+
+        let camera_paths = runs
+            .iter()
+            .filter_map(|run| run.camera_path.clone())
+            .map(|path| {
+                let camera_path = load_camera_path(&path);
+                (path, camera_path)
+            })
+            .collect();
+
+        let results =
+            ResultWriter::open(&config, resume).expect("Failed to open benchmark results");
+
+        Self {
+            config,
+            runs,
+            results,
+            environment: None,
+            warming_up: false,
+            camera_paths,
+        }
+    }
+
+    /// Combine the repetitions of every run measured so far, including earlier runs when resuming
+    fn summarise(&self) {
+        let results = bench::read_results(&self.config.jsonl_path());
+        let summaries = summary::summarise(&results);
+
+        for summary in &summaries {
+            println!("{}: {}, GPU {}", summary.label, summary.frame, summary.gpu);
+        }
+
+        let path = self.config.summary_path();
+        match summary::write_summary(&summaries, &path) {
+            Ok(()) => println!("Summary of {} runs in {}", summaries.len(), path.display()),
+            Err(e) => eprintln!("Failed to write benchmark summary: {}", e),
+        }
+    }
+}
+
 fn main() {
-    let args = Args::parse();
+    let cli = Cli::parse();
+
+    if let Some(Command::Report(report)) = &cli.command {
+        match report::report(report) {
+            Ok(0) => {}
+            Ok(regressions) => {
+                eprintln!("{} runs regressed", regressions);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to write report: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let args = cli.args;
 
-    println!("Running {:?} test in scene: {:?}", args.test, args.scene);
+    let bench = cli
+        .bench
+        .or_else(|| args.auto_test.then(|| PathBuf::from(bench::DEFAULT_CONFIG)))
+        .map(|path| Bench::new(&path, cli.resume));
+
+    if bench.is_none() {
+        println!("Running {:?} test in scene: {:?}", args.test, args.scene);
+    }
 
     let event_loop = make_event_loop();
 
-    let mut app = App::new(args);
+    let camera = cli
+        .camera_path
+        .map(|path| PathPlayer::new(load_camera_path(&path), CAMERA_TIMESTEP, true));
+
+    let mut app = App::new(args, bench, camera);
 
     {
         let _profiler = if app.args.profile {
@@ -241,6 +232,24 @@ fn main() {
         let _ = event_loop.run_app(&mut app);
         let time = app.state().avg_frame_time();
         println!("Average Time: {} ({} FPS)", time, 1000. / time);
+        println!("Frame time: {}", app.state().frame_stats());
+        println!("GPU time: {}", app.state().gpu_stats());
+        if app.args.hiz_cull {
+            println!(
+                "Average Occluded Chunks: {:.1}",
+                app.state().stats.avg_occlusion_culled()
+            );
+        }
+        if let Some(meshing) = app.setup.as_ref().and_then(|s| s.meshing_stats()) {
+            println!("Meshing: {}", meshing);
+        }
+        if app.args.edits > 0 {
+            println!(
+                "Remesh latency over {} edits: {}",
+                app.state().stats.total_edits(),
+                app.state().stats.remesh_latency()
+            );
+        }
     }
 
     println!();
@@ -251,26 +260,133 @@ struct App {
     state: Option<State>,
     setup: Option<Box<dyn Renderable>>,
     args: Args,
+    bench: Option<Bench>,
+    /// Drives the camera instead of the auto test's spin
+    camera: Option<PathPlayer>,
     test_step: usize,
     last_test_time: std::time::Instant,
-    test_fps: Vec<f64>,
 }
 
 impl App {
-    fn new(args: Args) -> Self {
+    fn new(args: Args, bench: Option<Bench>, camera: Option<PathPlayer>) -> Self {
         Self {
             state: None,
             setup: None,
             args,
+            bench,
+            camera,
             test_step: 0,
             last_test_time: std::time::Instant::now(),
-            test_fps: vec![],
         }
     }
 
     fn state(&mut self) -> &mut State {
         self.state.as_mut().unwrap()
     }
+
+    /// Record the benchmark that just finished, then set up the next one
+    fn next_benchmark(&mut self, event_loop: &ActiveEventLoop) {
+        if self.test_step != 0 {
+            let mads = self.bench.as_ref().unwrap().config.outlier_mads;
+            let frame = FrameStats::without_outliers(self.state().frame_times(), mads);
+            let gpu = FrameStats::without_outliers(self.state().gpu_times(), mads);
+            println!("Frame time for {}: {}", self.args, frame);
+            println!("GPU time: {}", gpu);
+            if self.args.hiz_cull {
+                println!(
+                    "Average Occluded Chunks: {:.1}",
+                    self.state().stats.avg_occlusion_culled()
+                );
+            }
+
+            let meshing = self.setup.as_ref().and_then(|s| s.meshing_stats());
+            if let Some(meshing) = &meshing {
+                println!("Meshing: {}", meshing);
+            }
+            if self.args.edits > 0 {
+                println!(
+                    "Remesh latency over {} edits: {}",
+                    self.state().stats.total_edits(),
+                    self.state().stats.remesh_latency()
+                );
+            }
+
+            let state = self.state.as_ref().unwrap();
+            let bench = self.bench.as_mut().unwrap();
+            let run = &bench.runs[self.test_step - 1];
+
+            let trace = bench
+                .results
+                .write_trace(run, state.frame_times(), state.gpu_times())
+                .unwrap_or_else(|e| {
+                    eprintln!("Failed to write frame trace: {}", e);
+                    PathBuf::new()
+                });
+
+            let environment = bench.environment.get_or_insert_with(Environment::current);
+            let result =
+                RunResult::new(run, environment, frame, gpu, meshing, &state.stats, &trace);
+
+            if let Err(e) = bench.results.write(&result) {
+                eprintln!("Failed to write benchmark result: {}", e);
+            }
+        }
+
+        let bench = self.bench.as_mut().unwrap();
+        let Some(run) = bench.runs.get(self.test_step) else {
+            println!("No more tests to run");
+            bench.summarise();
+            event_loop.exit();
+            return;
+        };
+
+        bench.warming_up = bench.config.warmup > 0.0;
+        self.camera = run.camera_path.as_ref().map(|path| {
+            let camera_path = bench.camera_paths[path].clone();
+            PathPlayer::new(camera_path, bench.config.camera_timestep, false)
+        });
+        let old = std::mem::replace(&mut self.args, run.args);
+        println!(
+            "Switching to test: {} (repetition {})",
+            run.label,
+            run.repetition + 1
+        );
+
+        // Anything that changes the blocks or how faces are stored needs the scene rebuilt.
+        // Edits change the blocks too, so a run that made any can't leave its scene behind.
+        if self.args.scene != old.scene
+            || self.args.test != old.test
+            || self.args.radius != old.radius
+            || self.args.depth != old.depth
+            || self.args.fill != old.fill
+            || self.args.noise != old.noise
+            || self.args.wide_faces != old.wide_faces
+            || self.args.edits > 0
+            || old.edits > 0
+            || self.setup.is_none()
+        {
+            setup_test(self);
+        } else {
+            let setup = self.setup.as_mut().unwrap();
+            setup.args(&self.args);
+            self.state().depth_prepass = self.args.depth_prepass;
+        }
+
+        self.test_step += 1;
+        self.start_measuring();
+    }
+
+    /// Put the camera back and start timing from this frame
+    fn start_measuring(&mut self) {
+        let new_cam = PerspectiveCamera::default();
+        self.state().cameras.active_mut().transform_mut().rotation = new_cam.transform().rotation;
+        if let Some(camera) = &mut self.camera {
+            camera.restart();
+        }
+        self.last_test_time = std::time::Instant::now();
+        self.state().new_frame();
+        self.state().wipe_fps();
+    }
 }
 
 impl ApplicationHandler for App {
@@ -341,75 +457,50 @@ impl ApplicationHandler for App {
                 }
             }
             WindowEvent::RedrawRequested => {
-                if self.setup.is_none()
-                    || (self.args.auto_test
-                        && self.last_test_time.elapsed().as_secs_f64() >= TIME_PER_TEST)
-                {
-                    if self.args.auto_test {
-                        if self.test_step != 0 {
-                            let avg_time = self.state().avg_frame_time();
-                            self.test_fps.push(avg_time);
-                            println!(
-                                "Average Time for {}: {} ({} FPS)",
-                                self.args,
-                                avg_time,
-                                1000. / avg_time
-                            );
-
-                            let mut file = OpenOptions::new()
-                                .append(true)
-                                .create(true)
-                                .open("test_results.txt")
-                                .unwrap();
-
-                            file.write_all(format!("{}: {}\n", self.args, avg_time).as_bytes())
-                                .unwrap();
+                let elapsed = self.last_test_time.elapsed().as_secs_f64();
+                let warmed_up = self
+                    .bench
+                    .as_ref()
+                    .is_some_and(|bench| bench.warming_up && elapsed >= bench.config.warmup);
+                // Runs along a camera path last until it ends, so they all draw the same frames
+                let test_over = self.bench.as_ref().is_some_and(|bench| {
+                    !bench.warming_up
+                        && match &self.camera {
+                            Some(camera) => camera.is_finished(),
+                            None => elapsed >= bench.config.time_per_test,
                         }
-                        let old_scene = self.args.scene;
-                        let old_test = self.args.test;
-                        let old_radius = self.args.radius;
-                        self.args = *TESTS.get(self.test_step).unwrap_or_else(|| {
-                            println!("No more tests to run");
-                            event_loop.exit();
-                            &self.args
-                        });
-
-                        println!("Switching to test: {}", self.args);
-                        if self.args.scene != old_scene
-                            || self.args.test != old_test
-                            || self.args.radius != old_radius
-                            || self.setup.is_none()
-                        {
-                            setup_test(self);
-                        } else {
-                            let setup = self.setup.as_mut().unwrap();
-                            setup.args(&self.args);
-                        }
-
-                        let new_cam = PerspectiveCamera::default();
-                        self.state().cameras.active_mut().transform_mut().rotation =
-                            new_cam.transform().rotation;
-                        self.test_step += 1;
-                        self.last_test_time = std::time::Instant::now();
-                        self.state().new_frame();
-                        self.state().wipe_fps();
-                    } else {
-                        setup_test(self);
-                    }
+                });
+
+                if self.bench.is_some() && (self.setup.is_none() || test_over) {
+                    self.next_benchmark(event_loop);
+                } else if warmed_up {
+                    self.bench.as_mut().unwrap().warming_up = false;
+                    self.start_measuring();
+                } else if self.setup.is_none() {
+                    setup_test(self);
                 } else {
                     self.state().new_frame();
                 }
 
-                if self.args.auto_test {
+                if let Some(camera) = &mut self.camera {
+                    let transform = camera.step();
+                    *self
+                        .state
+                        .as_mut()
+                        .unwrap()
+                        .cameras
+                        .active_mut()
+                        .transform_mut() = transform;
+                } else if self.args.auto_test {
                     self.state().cameras.active_mut().rotate(0.0, 0.1, false);
                 }
 
                 self.state().handle_input();
 
-                self.setup
+                self.state
                     .as_mut()
                     .unwrap()
-                    .render(self.state.as_mut().unwrap());
+                    .render(self.setup.as_mut().unwrap().as_mut());
 
                 CameraManager::render_gizmos(self.state());
 
@@ -0,0 +1,606 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use common::{
+    Args,
+    tests::{Scene, Test},
+};
+use serde_json::Value;
+
+use crate::{
+    bench,
+    summary::{self, Interval, Summary},
+};
+
+/// Turn benchmark results into tables and plots, flagging regressions against an earlier run
+#[derive(clap::Args, Debug)]
+pub struct ReportArgs {
+    /// Results to report on, the JSON lines written by `--bench`.
+    /// Repetitions of the same run in different files are combined.
+    #[arg(required = true)]
+    pub results: Vec<PathBuf>,
+
+    /// Technique the others' speed-ups are measured against
+    #[arg(long, default_value = "culled")]
+    pub baseline: Test,
+
+    /// Earlier results to compare against, runs that have slowed down are flagged
+    #[arg(long)]
+    pub compare: Option<PathBuf>,
+
+    /// Percent slower than `--compare` a run can get before it counts as a regression
+    #[arg(long, default_value = "5.0")]
+    pub threshold: f64,
+
+    /// Written with `.md` and `.html` added, plots go in a `_plots` directory next to them
+    #[arg(long, default_value = "results/report")]
+    pub output: PathBuf,
+}
+
+/// Everything about a run apart from its scene and technique, so techniques line up in a row
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Row {
+    /// `None` for scenes that don't grow with the radius
+    size: Option<i32>,
+    flags: String,
+    /// Anything else that was changed, like the fill or a camera path
+    variant: String,
+}
+
+impl Row {
+    fn new(args: &Args, camera_path: Option<&str>) -> Self {
+        let mut variant = vec![];
+
+        if args.scene == Scene::Noise {
+            variant.push(format!("{}% fill", args.fill));
+        }
+        if args.scene == Scene::Perlin && !args.noise.is_default() {
+            variant.push(args.noise.to_string());
+        }
+        if args.edits > 0 {
+            variant.push(format!("{} edits/frame", args.edits));
+        }
+        if let Some(path) = camera_path {
+            variant.push(format!("camera {}", path));
+        }
+
+        Self {
+            size: args.scene.is_sized().then_some(args.radius),
+            flags: args.flags(),
+            variant: variant.join(", "),
+        }
+    }
+}
+
+/// A run's repetitions combined, with what it ran
+struct Entry {
+    scene: String,
+    technique: String,
+    row: Row,
+    frame: Interval,
+}
+
+/// Pair each run's summary up with the arguments it was run with
+fn entries(results: &[Value]) -> Vec<Entry> {
+    let mut runs = BTreeMap::new();
+
+    for result in results {
+        let Some(label) = result.get("label").and_then(Value::as_str) else {
+            continue;
+        };
+        if runs.contains_key(label) {
+            continue;
+        }
+
+        let Some(args) = result
+            .get("args")
+            .and_then(|args| serde_json::from_value::<Args>(args.clone()).ok())
+        else {
+            continue;
+        };
+        let camera_path = result.get("camera_path").and_then(Value::as_str);
+
+        runs.insert(label.to_owned(), (args, camera_path.map(str::to_owned)));
+    }
+
+    summary::summarise(results)
+        .into_iter()
+        .filter_map(|summary| {
+            let (args, camera_path) = runs.get(&summary.label)?;
+
+            Some(Entry {
+                scene: format!("{:?}", args.scene),
+                technique: format!("{:?}", args.test),
+                row: Row::new(args, camera_path.as_deref()),
+                frame: summary.frame,
+            })
+        })
+        .collect()
+}
+
+struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+struct Plot {
+    title: String,
+    /// Relative to the report
+    path: String,
+}
+
+struct Section {
+    title: String,
+    notes: Vec<String>,
+    table: Table,
+    plots: Vec<Plot>,
+}
+
+/// Items in the order they first appear
+fn first_seen<'a>(items: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen: Vec<&str> = vec![];
+    for item in items {
+        if !seen.contains(&item) {
+            seen.push(item);
+        }
+    }
+    seen
+}
+
+/// Write the report, returning how many runs regressed
+pub fn report(args: &ReportArgs) -> io::Result<usize> {
+    let mut results = vec![];
+    let mut sources = Table {
+        header: ["File", "Results", "Revision", "GPU", "CPU"]
+            .map(String::from)
+            .to_vec(),
+        rows: vec![],
+    };
+
+    for path in &args.results {
+        let file_results = bench::read_results(path);
+        if file_results.is_empty() {
+            eprintln!("No results in {}", path.display());
+        }
+
+        let field = |name: &str| {
+            file_results
+                .first()
+                .and_then(|r| r.get(name))
+                .and_then(Value::as_str)
+                .unwrap_or("-")
+                .to_owned()
+        };
+
+        sources.rows.push(vec![
+            path.display().to_string(),
+            file_results.len().to_string(),
+            field("git_revision"),
+            field("gl_renderer"),
+            field("cpu"),
+        ]);
+
+        results.extend(file_results);
+    }
+
+    let plot_dir = args.output.with_file_name(format!(
+        "{}_plots",
+        args.output
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default()
+    ));
+    fs::create_dir_all(&plot_dir)?;
+
+    let mut sections = vec![Section {
+        title: "Runs".to_owned(),
+        notes: vec![],
+        table: sources,
+        plots: vec![],
+    }];
+
+    let entries = entries(&results);
+    let baseline = format!("{:?}", args.baseline);
+
+    for scene in first_seen(entries.iter().map(|e| e.scene.as_str())) {
+        let entries = entries
+            .iter()
+            .filter(|e| e.scene == scene)
+            .collect::<Vec<_>>();
+        let plots = plots(scene, &entries, &plot_dir)?;
+
+        sections.push(Section {
+            title: scene.to_owned(),
+            notes: vec![format!(
+                "Mean frame time with its 95% confidence interval, and speed-up over {}",
+                baseline
+            )],
+            table: scene_table(&entries, &baseline),
+            plots,
+        });
+    }
+
+    let mut regressions = 0;
+    if let Some(path) = &args.compare {
+        let (table, regressed) = compare(
+            &summary::summarise(&results),
+            &summary::summarise(&bench::read_results(path)),
+            args.threshold,
+        );
+        regressions = regressed;
+
+        sections.push(Section {
+            title: "Regressions".to_owned(),
+            notes: vec![format!(
+                "Against {}, runs more than {}% slower are flagged. {} regressed.",
+                path.display(),
+                args.threshold,
+                regressed
+            )],
+            table,
+            plots: vec![],
+        });
+    }
+
+    let markdown_path = args.output.with_extension("md");
+    let html_path = args.output.with_extension("html");
+
+    fs::write(&markdown_path, markdown(&sections))?;
+    fs::write(&html_path, html(&sections))?;
+
+    println!(
+        "Report of {} runs in {} and {}",
+        entries.len(),
+        markdown_path.display(),
+        html_path.display()
+    );
+
+    Ok(regressions)
+}
+
+/// A row for each size and set of flags, with a column for each technique
+fn scene_table(entries: &[&Entry], baseline: &str) -> Table {
+    let techniques = first_seen(entries.iter().map(|e| e.technique.as_str()));
+
+    let mut rows: BTreeMap<&Row, BTreeMap<&str, &Interval>> = BTreeMap::new();
+    for entry in entries {
+        rows.entry(&entry.row)
+            .or_default()
+            .insert(&entry.technique, &entry.frame);
+    }
+
+    let mut header = ["Size", "Flags", "Variant"].map(String::from).to_vec();
+    header.extend(techniques.iter().map(|t| format!("{} (ms)", t)));
+
+    let rows = rows
+        .into_iter()
+        .map(|(row, times)| {
+            let mut cells = vec![
+                row.size.map(|s| s.to_string()).unwrap_or_default(),
+                row.flags.clone(),
+                row.variant.clone(),
+            ];
+
+            for technique in &techniques {
+                let cell = match (times.get(technique), times.get(baseline)) {
+                    (Some(time), Some(base)) if *technique != baseline && time.mean > 0.0 => {
+                        format!("{} ({:.2}x)", time, base.mean / time.mean)
+                    }
+                    (Some(time), _) => time.to_string(),
+                    (None, _) => String::new(),
+                };
+                cells.push(cell);
+            }
+
+            cells
+        })
+        .collect();
+
+    Table { header, rows }
+}
+
+/// Every run in both sets, with how much its mean frame time changed.
+/// Returns how many got slower by more than `threshold` percent.
+fn compare(current: &[Summary], baseline: &[Summary], threshold: f64) -> (Table, usize) {
+    let mut regressed = 0;
+    let mut rows = vec![];
+
+    for summary in current {
+        let Some(base) = baseline.iter().find(|b| b.label == summary.label) else {
+            continue;
+        };
+        if base.frame.mean <= 0.0 {
+            continue;
+        }
+
+        let change = (summary.frame.mean / base.frame.mean - 1.0) * 100.0;
+        let status = if change > threshold {
+            regressed += 1;
+            println!(
+                "Regression: {} {} -> {} ({:+.1}%)",
+                summary.label, base.frame, summary.frame, change
+            );
+            "REGRESSED"
+        } else if change < -threshold {
+            "improved"
+        } else {
+            ""
+        };
+
+        rows.push(vec![
+            summary.label.clone(),
+            base.frame.to_string(),
+            summary.frame.to_string(),
+            format!("{:+.1}%", change),
+            status.to_owned(),
+        ]);
+    }
+
+    let header = ["Run", "Baseline (ms)", "Now (ms)", "Change", ""]
+        .map(String::from)
+        .to_vec();
+
+    (Table { header, rows }, regressed)
+}
+
+const PLOT_WIDTH: f64 = 640.0;
+const PLOT_HEIGHT: f64 = 400.0;
+const PLOT_MARGIN: f64 = 60.0;
+const COLOURS: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+
+/// Points of each technique's line, as radius and mean frame time
+type Series<'a> = BTreeMap<&'a str, Vec<(f64, f64)>>;
+
+/// Frame time against radius for each set of flags run at more than one size
+fn plots(scene: &str, entries: &[&Entry], dir: &Path) -> io::Result<Vec<Plot>> {
+    let mut groups: BTreeMap<(&str, &str), Series> = BTreeMap::new();
+
+    for entry in entries {
+        let Some(size) = entry.row.size else {
+            continue;
+        };
+
+        groups
+            .entry((entry.row.flags.as_str(), entry.row.variant.as_str()))
+            .or_default()
+            .entry(entry.technique.as_str())
+            .or_default()
+            .push((size as f64, entry.frame.mean));
+    }
+
+    let mut plots = vec![];
+
+    for ((flags, variant), series) in groups {
+        let mut sizes = series
+            .values()
+            .flatten()
+            .map(|(size, _)| *size)
+            .collect::<Vec<_>>();
+        sizes.sort_by(f64::total_cmp);
+        sizes.dedup();
+
+        if sizes.len() < 2 {
+            continue;
+        }
+
+        let title = [scene, flags, variant]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let name = title
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+
+        let path = dir.join(name).with_extension("svg");
+        fs::write(&path, svg(&title, &sizes, &series))?;
+
+        let relative = Path::new(dir.file_name().unwrap_or_default())
+            .join(path.file_name().unwrap_or_default());
+
+        plots.push(Plot {
+            title,
+            path: relative.display().to_string(),
+        });
+    }
+
+    Ok(plots)
+}
+
+/// Line chart of each technique's frame time, the radius doubles so it is spaced logarithmically
+fn svg(title: &str, sizes: &[f64], series: &Series) -> String {
+    let (min_x, max_x) = (sizes[0].log2(), sizes[sizes.len() - 1].log2());
+    let max_y = series
+        .values()
+        .flatten()
+        .map(|(_, time)| *time)
+        .fold(0.0, f64::max)
+        * 1.1;
+    let max_y = if max_y > 0.0 { max_y } else { 1.0 };
+
+    let x = |size: f64| {
+        PLOT_MARGIN + (size.log2() - min_x) / (max_x - min_x) * (PLOT_WIDTH - 2.0 * PLOT_MARGIN)
+    };
+    let y =
+        |time: f64| PLOT_HEIGHT - PLOT_MARGIN - time / max_y * (PLOT_HEIGHT - 2.0 * PLOT_MARGIN);
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{PLOT_WIDTH}" height="{PLOT_HEIGHT}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="white"/><text x="{}" y="24" text-anchor="middle" font-size="14">{}</text>"#,
+        PLOT_WIDTH / 2.0,
+        escape(title)
+    );
+
+    // Axes, with a tick at every size that was run
+    let (left, bottom) = (PLOT_MARGIN, PLOT_HEIGHT - PLOT_MARGIN);
+    let _ = writeln!(
+        svg,
+        r#"<path d="M{left} {} V{bottom} H{}" stroke="black" fill="none"/>"#,
+        PLOT_MARGIN,
+        PLOT_WIDTH - PLOT_MARGIN
+    );
+    for size in sizes {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+            x(*size),
+            bottom + 18.0,
+            size
+        );
+    }
+    for i in 0..=4 {
+        let time = max_y * i as f64 / 4.0;
+        let _ = writeln!(
+            svg,
+            r##"<text x="{}" y="{:.1}" text-anchor="end">{:.2}</text><path d="M{left} {:.1} H{}" stroke="#ddd"/>"##,
+            left - 6.0,
+            y(time) + 4.0,
+            time,
+            y(time),
+            PLOT_WIDTH - PLOT_MARGIN
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">Radius</text><text transform="translate(16 {}) rotate(-90)" text-anchor="middle">Frame time (ms)</text>"#,
+        PLOT_WIDTH / 2.0,
+        PLOT_HEIGHT - 16.0,
+        PLOT_HEIGHT / 2.0
+    );
+
+    for (i, (technique, points)) in series.iter().enumerate() {
+        let colour = COLOURS[i % COLOURS.len()];
+
+        let mut points = points.clone();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let line = points
+            .iter()
+            .map(|(size, time)| format!("{:.1},{:.1}", x(*size), y(*time)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" stroke="{}" stroke-width="2" fill="none"/>"#,
+            line, colour
+        );
+
+        for (size, time) in &points {
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{:.1}" cy="{:.1}" r="3" fill="{}"/>"#,
+                x(*size),
+                y(*time),
+                colour
+            );
+        }
+
+        let legend = PLOT_MARGIN + 10.0 + i as f64 * 16.0;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="10" height="10" fill="{}"/><text x="{}" y="{}">{}</text>"#,
+            left + 10.0,
+            legend - 9.0,
+            colour,
+            left + 26.0,
+            legend,
+            escape(technique)
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn markdown(sections: &[Section]) -> String {
+    let mut md = String::from("# Benchmark report\n");
+
+    for section in sections {
+        let _ = writeln!(md, "\n## {}\n", section.title);
+        for note in &section.notes {
+            let _ = writeln!(md, "{}\n", note);
+        }
+
+        let row = |cells: &[String]| {
+            let cells = cells
+                .iter()
+                .map(|c| c.replace('|', "\\|"))
+                .collect::<Vec<_>>();
+            format!("| {} |", cells.join(" | "))
+        };
+
+        let _ = writeln!(md, "{}", row(&section.table.header));
+        let _ = writeln!(md, "|{}", " --- |".repeat(section.table.header.len()));
+        for cells in &section.table.rows {
+            let _ = writeln!(md, "{}", row(cells));
+        }
+
+        for plot in &section.plots {
+            let _ = writeln!(md, "\n![{}]({})", plot.title, plot.path);
+        }
+    }
+
+    md
+}
+
+fn html(sections: &[Section]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Benchmark report</title>\n\
+         <style>body { font-family: sans-serif; } table { border-collapse: collapse; } \
+         td, th { border: 1px solid #ccc; padding: 2px 8px; }</style>\n</head>\n<body>\n\
+         <h1>Benchmark report</h1>\n",
+    );
+
+    for section in sections {
+        let _ = writeln!(html, "<h2>{}</h2>", escape(&section.title));
+        for note in &section.notes {
+            let _ = writeln!(html, "<p>{}</p>", escape(note));
+        }
+
+        html.push_str("<table>\n<tr>");
+        for cell in &section.table.header {
+            let _ = write!(html, "<th>{}</th>", escape(cell));
+        }
+        html.push_str("</tr>\n");
+
+        for cells in &section.table.rows {
+            html.push_str("<tr>");
+            for cell in cells {
+                let _ = write!(html, "<td>{}</td>", escape(cell));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+
+        for plot in &section.plots {
+            let _ = writeln!(
+                html,
+                "<p><img src=\"{}\" alt=\"{}\"></p>",
+                escape(&plot.path),
+                escape(&plot.title)
+            );
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
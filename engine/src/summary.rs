@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde_json::Value;
+
+/// Two sided 95% critical values of Student's t distribution, by degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+fn t_critical(dof: usize) -> f64 {
+    T_95.get(dof.wrapping_sub(1)).copied().unwrap_or(1.96)
+}
+
+/// Mean of a set of samples with its 95% confidence interval
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interval {
+    pub samples: usize,
+    pub mean: f64,
+    /// Sample standard deviation
+    pub std_dev: f64,
+    /// Half the width of the interval, `None` with fewer than 2 samples
+    pub ci95: Option<f64>,
+}
+
+impl Interval {
+    pub fn new(samples: &[f64]) -> Self {
+        let n = samples.len();
+        if n == 0 {
+            return Self::default();
+        }
+
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        if n < 2 {
+            return Self {
+                samples: n,
+                mean,
+                std_dev: 0.0,
+                ci95: None,
+            };
+        }
+
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        let std_dev = variance.sqrt();
+
+        Self {
+            samples: n,
+            mean,
+            std_dev,
+            ci95: Some(t_critical(n - 1) * std_dev / (n as f64).sqrt()),
+        }
+    }
+
+    pub fn low(&self) -> Option<f64> {
+        self.ci95.map(|ci| self.mean - ci)
+    }
+
+    pub fn high(&self) -> Option<f64> {
+        self.ci95.map(|ci| self.mean + ci)
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.ci95 {
+            Some(ci) => write!(f, "{:.3}ms ± {:.3}", self.mean, ci),
+            None => write!(f, "{:.3}ms", self.mean),
+        }
+    }
+}
+
+/// Every repetition of one run combined
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub label: String,
+    /// Across the repetitions' mean frame times
+    pub frame: Interval,
+    /// Across the repetitions' mean GPU times
+    pub gpu: Interval,
+}
+
+/// Combine the repetitions of each run in a results file, in the order runs first appear
+pub fn summarise(results: &[Value]) -> Vec<Summary> {
+    let mut runs: Vec<(String, Vec<f64>, Vec<f64>)> = vec![];
+
+    for result in results {
+        let Some(label) = result.get("label").and_then(Value::as_str) else {
+            continue;
+        };
+        let mean = |stats: &str| result.get(stats)?.get("mean")?.as_f64();
+
+        let index = match runs.iter().position(|(l, _, _)| l == label) {
+            Some(index) => index,
+            None => {
+                runs.push((label.to_owned(), vec![], vec![]));
+                runs.len() - 1
+            }
+        };
+
+        let (_, frame, gpu) = &mut runs[index];
+        frame.extend(mean("frame"));
+        gpu.extend(mean("gpu"));
+    }
+
+    runs.into_iter()
+        .map(|(label, frame, gpu)| Summary {
+            label,
+            frame: Interval::new(&frame),
+            gpu: Interval::new(&gpu),
+        })
+        .collect()
+}
+
+pub fn write_summary(summaries: &[Summary], path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(
+        file,
+        "label,repetitions,frame_mean,frame_std_dev,frame_ci95_low,frame_ci95_high,gpu_mean,gpu_std_dev,gpu_ci95_low,gpu_ci95_high"
+    )?;
+
+    let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    for summary in summaries {
+        let (frame, gpu) = (summary.frame, summary.gpu);
+
+        writeln!(
+            file,
+            "\"{}\",{},{},{},{},{},{},{},{},{}",
+            summary.label.replace('"', "\"\""),
+            frame.samples,
+            frame.mean,
+            frame.std_dev,
+            optional(frame.low()),
+            optional(frame.high()),
+            gpu.mean,
+            gpu.std_dev,
+            optional(gpu.low()),
+            optional(gpu.high())
+        )?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn no_samples() {
+        let interval = Interval::new(&[]);
+
+        assert_eq!(interval.samples, 0);
+        assert_eq!(interval.mean, 0.0);
+        assert_eq!(interval.ci95, None);
+        assert_eq!(interval.to_string(), "0.000ms");
+    }
+
+    #[test]
+    fn single_sample_has_no_interval() {
+        let interval = Interval::new(&[2.5]);
+
+        assert_eq!(interval.samples, 1);
+        assert_eq!(interval.mean, 2.5);
+        assert_eq!(interval.std_dev, 0.0);
+        assert_eq!((interval.low(), interval.high()), (None, None));
+    }
+
+    #[test]
+    fn two_samples_use_one_degree_of_freedom() {
+        let interval = Interval::new(&[1.0, 3.0]);
+
+        assert_eq!(interval.mean, 2.0);
+        assert!((interval.std_dev - 2f64.sqrt()).abs() < 1e-9);
+
+        // t * s / sqrt(n), where s and sqrt(n) are both sqrt(2)
+        let ci = interval.ci95.unwrap();
+        assert!((ci - 12.706).abs() < 1e-9);
+        assert!((interval.low().unwrap() - (2.0 - ci)).abs() < 1e-9);
+        assert!((interval.high().unwrap() - (2.0 + ci)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn many_samples_fall_back_to_the_normal_distribution() {
+        assert_eq!(t_critical(30), 2.042);
+        assert_eq!(t_critical(31), 1.96);
+
+        let samples = (0..100).map(|s| (s % 2) as f64).collect::<Vec<_>>();
+        let interval = Interval::new(&samples);
+        let expected = 1.96 * interval.std_dev / 10.0;
+
+        assert!((interval.ci95.unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn summarise_groups_repetitions_by_label() {
+        let results = [
+            json!({ "label": "a", "frame": { "mean": 1.0 }, "gpu": { "mean": 0.5 } }),
+            json!({ "label": "b", "frame": { "mean": 4.0 } }),
+            json!({ "label": "a", "frame": { "mean": 3.0 }, "gpu": { "mean": 1.5 } }),
+            json!({ "frame": { "mean": 100.0 } }),
+        ];
+
+        let summaries = summarise(&results);
+
+        assert_eq!(
+            summaries
+                .iter()
+                .map(|s| s.label.as_str())
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(summaries[0].frame.samples, 2);
+        assert_eq!(summaries[0].frame.mean, 2.0);
+        assert_eq!(summaries[0].gpu.mean, 1.0);
+        assert_eq!(summaries[1].frame.samples, 1);
+        assert_eq!(summaries[1].gpu.samples, 0);
+    }
+}